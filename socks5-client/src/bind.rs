@@ -0,0 +1,140 @@
+use crate::{Error, Socks5Stream};
+use socks5_proto::Address;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+
+/// A proxy listening for an incoming connection after a BIND request
+///
+/// The address the proxy listens on is [`bound_addr()`](https://docs.rs/socks5-client/latest/socks5_client/struct.Socks5Listener.html#method.bound_addr), which is usually passed to the target through another connection, e.g. for the data connection of active mode FTP. [`accept()`](https://docs.rs/socks5-client/latest/socks5_client/struct.Socks5Listener.html#method.accept) then waits for the target to connect.
+///
+/// # Example
+///
+/// ```rust no_run
+/// use socks5_client::{Auth, Socks5Stream};
+/// use socks5_proto::Address;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let target = Address::DomainAddress(b"ftp.example.com".to_vec(), 0);
+///
+///     let listener = Socks5Stream::bind("127.0.0.1:1080", target, &Auth::None)
+///         .await
+///         .unwrap();
+///
+///     // send listener.bound_addr() to the target
+///
+///     let (stream, peer_addr) = listener.accept().await.unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Socks5Listener<S = TcpStream> {
+    stream: S,
+    bound_addr: Address,
+}
+
+impl<S> Socks5Listener<S> {
+    #[inline]
+    pub(crate) fn new(stream: S, bound_addr: Address) -> Self {
+        Self { stream, bound_addr }
+    }
+
+    /// Returns the address the proxy listens on for the incoming connection.
+    #[inline]
+    pub fn bound_addr(&self) -> &Address {
+        &self.bound_addr
+    }
+
+    /// Gets a reference to the underlying stream.
+    #[inline]
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Gets a mutable reference to the underlying stream.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Returns the underlying stream.
+    #[inline]
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S> Socks5Listener<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Waits for the incoming connection, returning the stream to it alongside the address of the connecting host.
+    ///
+    /// The [`bound_addr()`](https://docs.rs/socks5-client/latest/socks5_client/struct.Socks5Stream.html#method.bound_addr) of the returned stream is the address of the connecting host.
+    pub async fn accept(mut self) -> Result<(Socks5Stream<S>, Address), Error> {
+        let peer_addr = crate::read_reply(&mut self.stream).await?;
+        Ok((Socks5Stream::new(self.stream, peer_addr.clone()), peer_addr))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        tests::{self, SUCCEEDED},
+        Auth, Error, ReplyError, Socks5Stream,
+    };
+    use socks5_proto::Address;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn accept() {
+        let peer = [0x05, 0x00, 0x00, 0x01, 10, 0, 0, 2, 0x13, 0x88];
+        let script = [[0x05, 0x00].as_slice(), &SUCCEEDED, &peer, b"hello"].concat();
+        let (stream, proxy) = tests::proxy(&script);
+
+        let listener = Socks5Stream::bind_with(stream, tests::target(), &Auth::None)
+            .await
+            .unwrap();
+        assert_eq!(
+            listener.bound_addr(),
+            &Address::SocketAddress(([127, 0, 0, 1], 1080).into())
+        );
+
+        let (mut stream, peer_addr) = listener.accept().await.unwrap();
+        assert_eq!(
+            peer_addr,
+            Address::SocketAddress(([10, 0, 0, 2], 5000).into())
+        );
+        assert_eq!(stream.bound_addr(), &peer_addr);
+
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        drop(stream);
+        let mut request = tests::TARGET;
+        request[1] = 0x02;
+        assert_eq!(
+            proxy.await.unwrap(),
+            [[0x05, 0x01, 0x00].as_slice(), &request].concat()
+        );
+    }
+
+    #[tokio::test]
+    async fn accept_failed() {
+        let refused = [0x05, 0x05, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+        let script = [[0x05, 0x00].as_slice(), &SUCCEEDED, &refused].concat();
+        let (stream, _proxy) = tests::proxy(&script);
+
+        let listener = Socks5Stream::bind_with(stream, tests::target(), &Auth::None)
+            .await
+            .unwrap();
+
+        let res = listener.accept().await;
+        assert!(matches!(
+            res,
+            Err(Error::Reply(ReplyError::ConnectionRefused))
+        ));
+    }
+}
@@ -0,0 +1,249 @@
+use crate::{Auth, Error, Socks5Stream};
+use http::{uri::Scheme, Uri};
+use hyper_util::{
+    client::legacy::connect::{Connected, Connection},
+    rt::TokioIo,
+};
+use socks5_proto::Address;
+use std::{
+    future::Future,
+    io::{Error as IoError, ErrorKind},
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::net::{self, TcpStream};
+use tower_service::Service;
+
+/// A connector tunneling connections through a SOCKS5 proxy with CONNECT requests
+///
+/// `Socks5Connector` is a `tower::Service<Uri>` that can be used as the connector of hyper-util's [legacy `Client`](https://docs.rs/hyper-util/latest/hyper_util/client/legacy/struct.Client.html). Every connection is a new connection to the proxy. For HTTPS, wrap it in a TLS connector, e.g. `hyper-rustls`'s `HttpsConnector`.
+///
+/// By default, host names in URIs are sent to the proxy, which resolves them. Call [`set_local_dns(true)`](https://docs.rs/socks5-client/latest/socks5_client/struct.Socks5Connector.html#method.set_local_dns) to resolve them locally and send IP addresses instead.
+///
+/// # Example
+///
+/// ```rust no_run
+/// use socks5_client::{Auth, Socks5Connector};
+/// use socks5_proto::Address;
+/// use hyper_util::{client::legacy::Client, rt::TokioExecutor};
+///
+/// # fn main() {
+/// let proxy = Address::SocketAddress("127.0.0.1:1080".parse().unwrap());
+/// let connector = Socks5Connector::new(proxy, Auth::password("user", "pass"));
+///
+/// let client = Client::builder(TokioExecutor::new()).build::<_, String>(connector);
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Socks5Connector {
+    proxy: Address,
+    auth: Auth,
+    local_dns: bool,
+}
+
+impl Socks5Connector {
+    /// Creates a new `Socks5Connector` for the proxy at `proxy`. A domain name in `proxy` is resolved for each connection.
+    #[inline]
+    pub fn new(proxy: Address, auth: Auth) -> Self {
+        Self {
+            proxy,
+            auth,
+            local_dns: false,
+        }
+    }
+
+    /// Sets whether host names are resolved locally instead of by the proxy. Defaults to `false`.
+    #[inline]
+    pub fn set_local_dns(&mut self, local_dns: bool) {
+        self.local_dns = local_dns;
+    }
+
+    /// Returns whether host names are resolved locally.
+    #[inline]
+    pub fn local_dns(&self) -> bool {
+        self.local_dns
+    }
+
+    /// Returns the address of the proxy.
+    #[inline]
+    pub fn proxy(&self) -> &Address {
+        &self.proxy
+    }
+
+    /// Connects to the host of `uri` through the proxy.
+    pub async fn connect(&self, uri: &Uri) -> Result<Socks5Stream, Error> {
+        let target = target(uri, self.local_dns).await?;
+
+        let stream = match &self.proxy {
+            Address::SocketAddress(addr) => TcpStream::connect(*addr).await?,
+            Address::DomainAddress(host, port) => {
+                let host = String::from_utf8_lossy(host);
+                TcpStream::connect((host.as_ref(), *port)).await?
+            }
+        };
+
+        Socks5Stream::connect_with(stream, target, &self.auth).await
+    }
+}
+
+impl Service<Uri> for Socks5Connector {
+    type Response = TokioIo<Socks5Stream>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    #[inline]
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connector = self.clone();
+        Box::pin(async move { connector.connect(&uri).await.map(TokioIo::new) })
+    }
+}
+
+impl<S> Connection for Socks5Stream<S>
+where
+    S: Connection,
+{
+    #[inline]
+    fn connected(&self) -> Connected {
+        self.get_ref().connected()
+    }
+}
+
+/// Returns the target address of `uri`, with the default port of its scheme if none.
+async fn target(uri: &Uri, local_dns: bool) -> Result<Address, Error> {
+    let host = uri
+        .host()
+        .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "URI has no host"))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    let port = match (uri.port_u16(), uri.scheme()) {
+        (Some(port), _) => port,
+        (None, Some(scheme)) if *scheme == Scheme::HTTPS => 443,
+        (None, _) => 80,
+    };
+
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(Address::SocketAddress(SocketAddr::new(ip, port)));
+    }
+
+    if local_dns {
+        let addr = net::lookup_host((host, port))
+            .await?
+            .next()
+            .ok_or_else(|| IoError::new(ErrorKind::NotFound, "host name resolved to nothing"))?;
+
+        return Ok(Address::SocketAddress(addr));
+    }
+
+    Ok(Address::DomainAddress(host.as_bytes().to_vec(), port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::SUCCEEDED;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    async fn target_of(uri: &str, local_dns: bool) -> Address {
+        target(&uri.parse().unwrap(), local_dns).await.unwrap()
+    }
+
+    fn domain(host: &str, port: u16) -> Address {
+        Address::DomainAddress(host.as_bytes().to_vec(), port)
+    }
+
+    #[tokio::test]
+    async fn default_port() {
+        assert_eq!(
+            target_of("http://example.com", false).await,
+            domain("example.com", 80)
+        );
+        assert_eq!(
+            target_of("https://example.com", false).await,
+            domain("example.com", 443)
+        );
+        assert_eq!(
+            target_of("example.com", false).await,
+            domain("example.com", 80)
+        );
+        assert_eq!(
+            target_of("https://example.com:8443/path", false).await,
+            domain("example.com", 8443)
+        );
+    }
+
+    #[tokio::test]
+    async fn ip_literal() {
+        assert_eq!(
+            target_of("http://10.0.0.1:8080", false).await,
+            Address::SocketAddress(([10, 0, 0, 1], 8080).into())
+        );
+        assert_eq!(
+            target_of("https://[::1]", false).await,
+            Address::SocketAddress("[::1]:443".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn local_dns() {
+        assert_eq!(
+            target_of("http://localhost", false).await,
+            domain("localhost", 80)
+        );
+
+        let Address::SocketAddress(addr) = target_of("http://localhost", true).await else {
+            panic!("expected a resolved address");
+        };
+        assert!(addr.ip().is_loopback());
+        assert_eq!(addr.port(), 80);
+    }
+
+    #[tokio::test]
+    async fn no_host() {
+        let res = target(&"/path".parse().unwrap(), false).await;
+        assert!(matches!(res, Err(Error::Io(err)) if err.kind() == ErrorKind::InvalidInput));
+    }
+
+    #[tokio::test]
+    async fn call() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+
+        let proxy = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            stream.write_all(&[0x05, 0x00]).await.unwrap();
+            stream.write_all(&SUCCEEDED).await.unwrap();
+            stream.write_all(b"hello").await.unwrap();
+
+            let mut buf = Vec::new();
+            stream.read_to_end(&mut buf).await.unwrap();
+            buf
+        });
+
+        let mut connector = Socks5Connector::new(Address::SocketAddress(proxy_addr), Auth::None);
+        let stream = connector
+            .call("https://example.com/path".parse().unwrap())
+            .await
+            .unwrap();
+
+        let mut stream = stream.into_inner();
+        let mut buf = [0; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        drop(stream);
+        let expected = [
+            [0x05, 0x01, 0x00, 0x05, 0x01, 0x00, 0x03, 11].as_slice(),
+            b"example.com",
+            &[0x01, 0xbb],
+        ];
+        assert_eq!(proxy.await.unwrap(), expected.concat());
+    }
+}
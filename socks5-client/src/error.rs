@@ -0,0 +1,139 @@
+//! Error types of the SOCKS5 client
+
+use socks5_proto::{
+    handshake::password::Error as PasswordError, Error as Socks5Error, ProtocolError, Reply,
+};
+use std::io::{Error as IoError, ErrorKind};
+use thiserror::Error;
+
+/// Errors may occured while establishing a connection through a SOCKS5 proxy
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] IoError),
+
+    #[error(transparent)]
+    Protocol(#[from] ProtocolError),
+
+    #[error(transparent)]
+    Password(PasswordError),
+
+    #[error("No acceptable authentication method")]
+    NoAcceptableMethod,
+
+    #[error("Unexpected authentication method {method:#04x} chosen by the proxy")]
+    UnexpectedMethod { method: u8 },
+
+    #[error("Username / password authentication failed")]
+    AuthenticationFailed,
+
+    #[error(transparent)]
+    Reply(#[from] ReplyError),
+}
+
+impl From<Socks5Error> for Error {
+    fn from(err: Socks5Error) -> Self {
+        match err {
+            Socks5Error::Protocol(err) => Self::Protocol(err),
+            Socks5Error::Io(err) => Self::Io(err),
+        }
+    }
+}
+
+impl From<PasswordError> for Error {
+    fn from(err: PasswordError) -> Self {
+        match err {
+            PasswordError::Io(err) => Self::Io(err),
+            err => Self::Password(err),
+        }
+    }
+}
+
+impl From<Error> for IoError {
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Io(err) => err,
+            Error::Reply(err) => err.into(),
+            err => IoError::other(err),
+        }
+    }
+}
+
+/// A failure reply from the proxy
+///
+/// A `Reply` other than `Reply::Succeeded` can be converted into a `ReplyError` with `TryFrom` trait. Converting a `ReplyError` into an `std::io::Error` keeps the closest [`ErrorKind`](https://doc.rust-lang.org/std/io/enum.ErrorKind.html), e.g. `ConnectionRefused` for `ReplyError::ConnectionRefused`.
+#[derive(Clone, Copy, Debug, Error, Eq, Hash, PartialEq)]
+pub enum ReplyError {
+    #[error("General SOCKS server failure")]
+    GeneralFailure,
+
+    #[error("Connection not allowed by ruleset")]
+    ConnectionNotAllowed,
+
+    #[error("Network unreachable")]
+    NetworkUnreachable,
+
+    #[error("Host unreachable")]
+    HostUnreachable,
+
+    #[error("Connection refused")]
+    ConnectionRefused,
+
+    #[error("TTL expired")]
+    TtlExpired,
+
+    #[error("Command not supported")]
+    CommandNotSupported,
+
+    #[error("Address type not supported")]
+    AddressTypeNotSupported,
+}
+
+impl TryFrom<Reply> for ReplyError {
+    type Error = Reply;
+
+    fn try_from(reply: Reply) -> Result<Self, Self::Error> {
+        match reply {
+            Reply::Succeeded => Err(reply),
+            Reply::GeneralFailure => Ok(Self::GeneralFailure),
+            Reply::ConnectionNotAllowed => Ok(Self::ConnectionNotAllowed),
+            Reply::NetworkUnreachable => Ok(Self::NetworkUnreachable),
+            Reply::HostUnreachable => Ok(Self::HostUnreachable),
+            Reply::ConnectionRefused => Ok(Self::ConnectionRefused),
+            Reply::TtlExpired => Ok(Self::TtlExpired),
+            Reply::CommandNotSupported => Ok(Self::CommandNotSupported),
+            Reply::AddressTypeNotSupported => Ok(Self::AddressTypeNotSupported),
+        }
+    }
+}
+
+impl From<ReplyError> for Reply {
+    fn from(err: ReplyError) -> Self {
+        match err {
+            ReplyError::GeneralFailure => Reply::GeneralFailure,
+            ReplyError::ConnectionNotAllowed => Reply::ConnectionNotAllowed,
+            ReplyError::NetworkUnreachable => Reply::NetworkUnreachable,
+            ReplyError::HostUnreachable => Reply::HostUnreachable,
+            ReplyError::ConnectionRefused => Reply::ConnectionRefused,
+            ReplyError::TtlExpired => Reply::TtlExpired,
+            ReplyError::CommandNotSupported => Reply::CommandNotSupported,
+            ReplyError::AddressTypeNotSupported => Reply::AddressTypeNotSupported,
+        }
+    }
+}
+
+impl From<ReplyError> for IoError {
+    fn from(err: ReplyError) -> Self {
+        let kind = match err {
+            ReplyError::ConnectionNotAllowed => ErrorKind::PermissionDenied,
+            ReplyError::ConnectionRefused => ErrorKind::ConnectionRefused,
+            ReplyError::TtlExpired => ErrorKind::TimedOut,
+            ReplyError::CommandNotSupported | ReplyError::AddressTypeNotSupported => {
+                ErrorKind::Unsupported
+            }
+            _ => ErrorKind::Other,
+        };
+
+        IoError::new(kind, err)
+    }
+}
@@ -0,0 +1,307 @@
+#![doc = include_str!("../README.md")]
+
+use socks5_proto::{
+    handshake::{
+        password::{Request as PasswordRequest, Response as PasswordResponse},
+        Method, Request as HandshakeRequest, Response as HandshakeResponse,
+    },
+    Address, Command, Request, Response,
+};
+use std::io::{Error as IoError, ErrorKind};
+use tokio::io::{AsyncRead, AsyncWrite};
+
+mod bind;
+mod stream;
+mod udp;
+
+#[cfg(feature = "connector")]
+mod connector;
+
+pub mod error;
+
+pub use crate::{
+    bind::Socks5Listener,
+    error::{Error, ReplyError},
+    stream::Socks5Stream,
+    udp::Socks5UdpSocket,
+};
+
+#[cfg(feature = "connector")]
+pub use crate::connector::Socks5Connector;
+
+/// The authentication method used with a SOCKS5 proxy
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum Auth {
+    /// No authentication.
+    #[default]
+    None,
+    /// Username / password authentication defined in RFC 1929. The proxy may still choose not to authenticate.
+    Password {
+        username: Vec<u8>,
+        password: Vec<u8>,
+    },
+}
+
+impl Auth {
+    /// Creates a username / password authentication.
+    #[inline]
+    pub fn password(username: impl Into<Vec<u8>>, password: impl Into<Vec<u8>>) -> Self {
+        Self::Password {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+
+    fn methods(&self) -> Vec<Method> {
+        match self {
+            Self::None => vec![Method::NONE],
+            Self::Password { .. } => vec![Method::NONE, Method::PASSWORD],
+        }
+    }
+}
+
+/// Negotiates the authentication method and authenticates on a fresh connection to the proxy.
+pub(crate) async fn authenticate<S>(stream: &mut S, auth: &Auth) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    if let Auth::Password { username, password } = auth {
+        if username.is_empty()
+            || username.len() > 255
+            || password.is_empty()
+            || password.len() > 255
+        {
+            return Err(Error::Io(IoError::new(
+                ErrorKind::InvalidInput,
+                "username and password must be 1 to 255 bytes long",
+            )));
+        }
+    }
+
+    let methods = auth.methods();
+    HandshakeRequest::new(methods.clone())
+        .write_to(stream)
+        .await?;
+
+    let method = HandshakeResponse::read_from(stream).await?.method;
+
+    if method == Method::UNACCEPTABLE {
+        return Err(Error::NoAcceptableMethod);
+    } else if !methods.contains(&method) {
+        return Err(Error::UnexpectedMethod { method: method.0 });
+    }
+
+    if let (Method::PASSWORD, Auth::Password { username, password }) = (method, auth) {
+        PasswordRequest::new(username.clone(), password.clone())
+            .write_to(stream)
+            .await?;
+
+        if !PasswordResponse::read_from(stream).await?.status {
+            return Err(Error::AuthenticationFailed);
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends a request on an authenticated connection and waits for a successful reply, returning the address in it.
+pub(crate) async fn request<S>(
+    stream: &mut S,
+    command: Command,
+    addr: Address,
+) -> Result<Address, Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    Request::new(command, addr).write_to(stream).await?;
+    read_reply(stream).await
+}
+
+/// Reads a reply, returning the address in it if the reply is successful.
+pub(crate) async fn read_reply<S>(stream: &mut S) -> Result<Address, Error>
+where
+    S: AsyncRead + Unpin,
+{
+    let resp = Response::read_from(stream).await?;
+
+    match ReplyError::try_from(resp.reply) {
+        Ok(err) => Err(Error::Reply(err)),
+        Err(_) => Ok(resp.address),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::Socks5Stream;
+    use tokio::{
+        io::{self, AsyncReadExt, AsyncWriteExt, DuplexStream},
+        task::JoinHandle,
+    };
+
+    /// A request to `10.0.0.1:80`.
+    pub(crate) const TARGET: [u8; 10] = [0x05, 0x01, 0x00, 0x01, 10, 0, 0, 1, 0, 80];
+
+    /// A successful reply with the address `127.0.0.1:1080`.
+    pub(crate) const SUCCEEDED: [u8; 10] = [0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0x04, 0x38];
+
+    pub(crate) fn target() -> Address {
+        Address::SocketAddress(([10, 0, 0, 1], 80).into())
+    }
+
+    /// A proxy sending `script` whatever the client sends. Returns what the client sent once it closes the connection.
+    pub(crate) fn proxy(script: &[u8]) -> (DuplexStream, JoinHandle<Vec<u8>>) {
+        let (client, mut server) = io::duplex(4096);
+        let script = script.to_vec();
+
+        let handle = tokio::spawn(async move {
+            server.write_all(&script).await.unwrap();
+
+            let mut buf = Vec::new();
+            server.read_to_end(&mut buf).await.unwrap();
+            buf
+        });
+
+        (client, handle)
+    }
+
+    #[tokio::test]
+    async fn no_auth() {
+        let (stream, proxy) = proxy(&[[0x05, 0x00].as_slice(), &SUCCEEDED].concat());
+
+        let stream = Socks5Stream::connect_with(stream, target(), &Auth::None)
+            .await
+            .unwrap();
+        assert_eq!(
+            stream.bound_addr(),
+            &Address::SocketAddress(([127, 0, 0, 1], 1080).into())
+        );
+
+        drop(stream);
+        let sent = proxy.await.unwrap();
+        assert_eq!(sent, [[0x05, 0x01, 0x00].as_slice(), &TARGET].concat());
+    }
+
+    #[tokio::test]
+    async fn password() {
+        let script = [[0x05, 0x02, 0x01, 0x00].as_slice(), &SUCCEEDED].concat();
+        let (stream, proxy) = proxy(&script);
+
+        let auth = Auth::password("user", "pass");
+        let stream = Socks5Stream::connect_with(stream, target(), &auth)
+            .await
+            .unwrap();
+
+        drop(stream);
+        let sent = proxy.await.unwrap();
+        let expected = [
+            [0x05, 0x02, 0x00, 0x02].as_slice(),
+            &[0x01, 0x04],
+            b"user",
+            &[0x04],
+            b"pass",
+            &TARGET,
+        ];
+        assert_eq!(sent, expected.concat());
+    }
+
+    #[tokio::test]
+    async fn password_not_required() {
+        let (stream, proxy) = proxy(&[[0x05, 0x00].as_slice(), &SUCCEEDED].concat());
+
+        let auth = Auth::password("user", "pass");
+        let stream = Socks5Stream::connect_with(stream, target(), &auth)
+            .await
+            .unwrap();
+
+        drop(stream);
+        let sent = proxy.await.unwrap();
+        assert_eq!(
+            sent,
+            [[0x05, 0x02, 0x00, 0x02].as_slice(), &TARGET].concat()
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_credentials() {
+        let (stream, proxy) = proxy(&[]);
+
+        let auth = Auth::password("", "pass");
+        let res = Socks5Stream::connect_with(stream, target(), &auth).await;
+        assert!(matches!(res, Err(Error::Io(err)) if err.kind() == ErrorKind::InvalidInput));
+
+        assert!(proxy.await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn no_acceptable_method() {
+        let (stream, _proxy) = proxy(&[0x05, 0xff]);
+
+        let res = Socks5Stream::connect_with(stream, target(), &Auth::None).await;
+        assert!(matches!(res, Err(Error::NoAcceptableMethod)));
+    }
+
+    #[tokio::test]
+    async fn unexpected_method() {
+        let (stream, _proxy) = proxy(&[0x05, 0x02]);
+
+        let res = Socks5Stream::connect_with(stream, target(), &Auth::None).await;
+        assert!(matches!(res, Err(Error::UnexpectedMethod { method: 0x02 })));
+    }
+
+    #[tokio::test]
+    async fn authentication_failed() {
+        let (stream, _proxy) = proxy(&[0x05, 0x02, 0x01, 0xff]);
+
+        let auth = Auth::password("user", "pass");
+        let res = Socks5Stream::connect_with(stream, target(), &auth).await;
+        assert!(matches!(res, Err(Error::AuthenticationFailed)));
+    }
+
+    #[tokio::test]
+    async fn failure_replies() {
+        let replies = [
+            (0x01, ReplyError::GeneralFailure, ErrorKind::Other),
+            (
+                0x02,
+                ReplyError::ConnectionNotAllowed,
+                ErrorKind::PermissionDenied,
+            ),
+            (0x03, ReplyError::NetworkUnreachable, ErrorKind::Other),
+            (0x04, ReplyError::HostUnreachable, ErrorKind::Other),
+            (
+                0x05,
+                ReplyError::ConnectionRefused,
+                ErrorKind::ConnectionRefused,
+            ),
+            (0x06, ReplyError::TtlExpired, ErrorKind::TimedOut),
+            (
+                0x07,
+                ReplyError::CommandNotSupported,
+                ErrorKind::Unsupported,
+            ),
+            (
+                0x08,
+                ReplyError::AddressTypeNotSupported,
+                ErrorKind::Unsupported,
+            ),
+        ];
+
+        for (code, expected, kind) in replies {
+            let script = [0x05, 0x00, 0x05, code, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+            let (stream, _proxy) = proxy(&script);
+
+            let err = Socks5Stream::connect_with(stream, target(), &Auth::None)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, Error::Reply(err) if err == expected));
+
+            let err = IoError::from(err);
+            assert_eq!(err.kind(), kind);
+            assert_eq!(
+                err.into_inner().unwrap().downcast::<ReplyError>().ok(),
+                Some(Box::new(expected))
+            );
+        }
+    }
+}
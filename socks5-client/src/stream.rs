@@ -0,0 +1,259 @@
+use crate::{Auth, Error, Socks5Listener};
+use socks5_proto::{Address, Command, UdpFrame, UdpOverTcp};
+use std::{
+    io::{Error as IoError, ErrorKind, IoSlice},
+    ops::{Deref, DerefMut},
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::{TcpStream, ToSocketAddrs},
+};
+
+/// A connection established through a SOCKS5 proxy
+///
+/// A `Socks5Stream` is created by [`connect()`](https://docs.rs/socks5-client/latest/socks5_client/struct.Socks5Stream.html#method.connect)ing to a target through a proxy, and can be used as a regular async TCP stream to the target. It can also be the control connection of a UDP association, see [`udp_associate()`](https://docs.rs/socks5-client/latest/socks5_client/struct.Socks5Stream.html#method.udp_associate).
+///
+/// Generic type `<S>` is the stream to the proxy, which defaults to a tokio [`TcpStream`](https://docs.rs/tokio/latest/tokio/net/struct.TcpStream.html). Use the `*_with()` functions to establish the connection over an existing stream, e.g. a TLS stream or a connection through another proxy.
+///
+/// A `Socks5Stream<TcpStream>` can be converted to a regular tokio [`TcpStream`](https://docs.rs/tokio/latest/tokio/net/struct.TcpStream.html) by using the `From` trait, or into the underlying stream with `into_inner()`.
+///
+/// # Example
+///
+/// ```rust no_run
+/// use socks5_client::{Auth, Socks5Stream};
+/// use socks5_proto::Address;
+/// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let target = Address::DomainAddress(b"example.com".to_vec(), 80);
+///     let auth = Auth::password("user", "pass");
+///
+///     let mut stream = Socks5Stream::connect("127.0.0.1:1080", target, &auth)
+///         .await
+///         .unwrap();
+///
+///     stream
+///         .write_all(b"GET / HTTP/1.0\r\nHost: example.com\r\n\r\n")
+///         .await
+///         .unwrap();
+///
+///     let mut resp = Vec::new();
+///     stream.read_to_end(&mut resp).await.unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Socks5Stream<S = TcpStream> {
+    stream: S,
+    bound_addr: Address,
+}
+
+impl Socks5Stream<TcpStream> {
+    /// Connects to `target` through the proxy at `proxy`.
+    ///
+    /// A domain name in `target` is resolved by the proxy.
+    pub async fn connect<P>(proxy: P, target: Address, auth: &Auth) -> Result<Self, Error>
+    where
+        P: ToSocketAddrs,
+    {
+        let stream = TcpStream::connect(proxy).await?;
+        Self::connect_with(stream, target, auth).await
+    }
+
+    /// Asks the proxy at `proxy` to listen for an incoming connection from `target`.
+    ///
+    /// See [`Socks5Listener`](https://docs.rs/socks5-client/latest/socks5_client/struct.Socks5Listener.html).
+    pub async fn bind<P>(proxy: P, target: Address, auth: &Auth) -> Result<Socks5Listener, Error>
+    where
+        P: ToSocketAddrs,
+    {
+        let stream = TcpStream::connect(proxy).await?;
+        Self::bind_with(stream, target, auth).await
+    }
+
+    /// Asks the proxy at `proxy` to relay UDP datagrams.
+    ///
+    /// `local` is the address the client will send datagrams from, or [`Address::unspecified()`](https://docs.rs/socks5-proto/latest/socks5_proto/enum.Address.html#method.unspecified) if it is not known yet. The address of the UDP relay is [`bound_addr()`](https://docs.rs/socks5-client/latest/socks5_client/struct.Socks5Stream.html#method.bound_addr). The association lasts until the returned stream is closed.
+    ///
+    /// [`Socks5UdpSocket`](https://docs.rs/socks5-client/latest/socks5_client/struct.Socks5UdpSocket.html) takes care of the association and of the SOCKS5 UDP header.
+    pub async fn udp_associate<P>(proxy: P, local: Address, auth: &Auth) -> Result<Self, Error>
+    where
+        P: ToSocketAddrs,
+    {
+        let stream = TcpStream::connect(proxy).await?;
+        Self::udp_associate_with(stream, local, auth).await
+    }
+
+    /// Asks the proxy at `proxy` to relay UDP datagrams framed on the connection, for networks where UDP is blocked.
+    ///
+    /// An error of kind `Unsupported` is returned if the proxy does not support UDP-over-TCP. See [`UdpOverTcp`](https://docs.rs/socks5-proto/latest/socks5_proto/struct.UdpOverTcp.html).
+    pub async fn udp_over_tcp<P>(proxy: P, auth: &Auth) -> Result<UdpOverTcp<TcpStream>, Error>
+    where
+        P: ToSocketAddrs,
+    {
+        let stream = TcpStream::connect(proxy).await?;
+        Self::udp_over_tcp_with(stream, auth).await
+    }
+}
+
+impl<S> Socks5Stream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Connects to `target` through the proxy on the connection `stream`.
+    pub async fn connect_with(mut stream: S, target: Address, auth: &Auth) -> Result<Self, Error> {
+        crate::authenticate(&mut stream, auth).await?;
+        let bound_addr = crate::request(&mut stream, Command::Connect, target).await?;
+        Ok(Self::new(stream, bound_addr))
+    }
+
+    /// Asks the proxy on the connection `stream` to listen for an incoming connection from `target`.
+    pub async fn bind_with(
+        mut stream: S,
+        target: Address,
+        auth: &Auth,
+    ) -> Result<Socks5Listener<S>, Error> {
+        crate::authenticate(&mut stream, auth).await?;
+        let bound_addr = crate::request(&mut stream, Command::Bind, target).await?;
+        Ok(Socks5Listener::new(stream, bound_addr))
+    }
+
+    /// Asks the proxy on the connection `stream` to relay UDP datagrams.
+    pub async fn udp_associate_with(
+        mut stream: S,
+        local: Address,
+        auth: &Auth,
+    ) -> Result<Self, Error> {
+        crate::authenticate(&mut stream, auth).await?;
+        let bound_addr = crate::request(&mut stream, Command::Associate, local).await?;
+        Ok(Self::new(stream, bound_addr))
+    }
+
+    /// Asks the proxy on the connection `stream` to relay UDP datagrams framed on the connection.
+    pub async fn udp_over_tcp_with(mut stream: S, auth: &Auth) -> Result<UdpOverTcp<S>, Error> {
+        crate::authenticate(&mut stream, auth).await?;
+        let addr = crate::request(
+            &mut stream,
+            Command::Associate,
+            UdpFrame::associate_address(),
+        )
+        .await?;
+
+        if !UdpFrame::is_associate_address(&addr) {
+            return Err(Error::Io(IoError::new(
+                ErrorKind::Unsupported,
+                format!("proxy does not support UDP-over-TCP, UDP relay at {addr}"),
+            )));
+        }
+
+        Ok(UdpOverTcp::new(stream))
+    }
+}
+
+impl<S> Socks5Stream<S> {
+    #[inline]
+    pub(crate) fn new(stream: S, bound_addr: Address) -> Self {
+        Self { stream, bound_addr }
+    }
+
+    /// Returns the address in the reply of the proxy, i.e. the local address of the proxy to the target, or the address of the UDP relay for a UDP association.
+    #[inline]
+    pub fn bound_addr(&self) -> &Address {
+        &self.bound_addr
+    }
+
+    /// Gets a reference to the underlying stream.
+    #[inline]
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Gets a mutable reference to the underlying stream.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Returns the underlying stream.
+    #[inline]
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+}
+
+impl<S> Deref for Socks5Stream<S> {
+    type Target = S;
+
+    #[inline]
+    fn deref(&self) -> &Self::Target {
+        &self.stream
+    }
+}
+
+impl<S> DerefMut for Socks5Stream<S> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.stream
+    }
+}
+
+impl<S> AsyncRead for Socks5Stream<S>
+where
+    S: AsyncRead + Unpin,
+{
+    #[inline]
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl<S> AsyncWrite for Socks5Stream<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    #[inline]
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, IoError>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    #[inline]
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, IoError>> {
+        Pin::new(&mut self.stream).poll_write_vectored(cx, bufs)
+    }
+
+    #[inline]
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    #[inline]
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IoError>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+impl From<Socks5Stream<TcpStream>> for TcpStream {
+    #[inline]
+    fn from(stream: Socks5Stream<TcpStream>) -> Self {
+        stream.stream
+    }
+}
@@ -0,0 +1,292 @@
+use crate::{Auth, Error};
+use bytes::{Bytes, BytesMut};
+use socks5_proto::{Address, Command, UdpHeader};
+use std::{
+    io::{Error as IoError, ErrorKind},
+    net::{IpAddr, SocketAddr},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{self, TcpStream, ToSocketAddrs, UdpSocket},
+};
+
+/// A UDP socket relaying datagrams through a SOCKS5 proxy
+///
+/// A `Socks5UdpSocket` sends an ASSOCIATE request over a control connection to the proxy, and then exchanges datagrams with the UDP relay in the reply, adding and stripping the SOCKS5 UDP header. The association is released by the proxy when the `Socks5UdpSocket` is dropped, which closes the control connection.
+///
+/// Generic type `<S>` is the control connection, which defaults to a tokio [`TcpStream`](https://docs.rs/tokio/latest/tokio/net/struct.TcpStream.html).
+///
+/// Fragmented datagrams (with a non-zero `FRAG` field) received from the relay are dropped.
+///
+/// # Example
+///
+/// ```rust no_run
+/// use socks5_client::{Auth, Socks5UdpSocket};
+/// use socks5_proto::Address;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let socket = Socks5UdpSocket::associate("127.0.0.1:1080", &Auth::None)
+///         .await
+///         .unwrap();
+///
+///     let dns = Address::DomainAddress(b"dns.google".to_vec(), 53);
+///     socket.send_to(b"query", dns).await.unwrap();
+///
+///     let (resp, from) = socket.recv_from().await.unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Socks5UdpSocket<S = TcpStream> {
+    socket: UdpSocket,
+    control: S,
+    relay_addr: SocketAddr,
+}
+
+impl Socks5UdpSocket<TcpStream> {
+    /// Requests a UDP association from the proxy at `proxy`.
+    ///
+    /// The local UDP socket is bound to the local IP address of the control connection, on a random port.
+    pub async fn associate<P>(proxy: P, auth: &Auth) -> Result<Self, Error>
+    where
+        P: ToSocketAddrs,
+    {
+        let stream = TcpStream::connect(proxy).await?;
+        let proxy_ip = stream.peer_addr()?.ip();
+        let socket = UdpSocket::bind((stream.local_addr()?.ip(), 0)).await?;
+        Self::associate_with(stream, socket, proxy_ip, auth).await
+    }
+}
+
+impl<S> Socks5UdpSocket<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Requests a UDP association from the proxy on the connection `stream`, relaying the datagrams of `socket`.
+    ///
+    /// The local address of `socket` is sent in the request. `proxy_ip` is the IP address of the proxy, used if the relay address in the reply is unspecified, as many proxies reply with all zeros to mean the address the client connected to.
+    pub async fn associate_with(
+        mut stream: S,
+        socket: UdpSocket,
+        proxy_ip: IpAddr,
+        auth: &Auth,
+    ) -> Result<Self, Error> {
+        crate::authenticate(&mut stream, auth).await?;
+
+        let local_addr = Address::SocketAddress(socket.local_addr()?);
+        let relay_addr = match crate::request(&mut stream, Command::Associate, local_addr).await? {
+            Address::SocketAddress(addr) if addr.ip().is_unspecified() => {
+                SocketAddr::new(proxy_ip, addr.port())
+            }
+            Address::SocketAddress(addr) => addr,
+            Address::DomainAddress(domain, port) => {
+                let domain = String::from_utf8(domain)
+                    .map_err(|err| IoError::new(ErrorKind::InvalidData, err))?;
+                let mut addrs = net::lookup_host((domain.as_str(), port)).await?;
+
+                addrs.next().ok_or_else(|| {
+                    IoError::new(ErrorKind::NotFound, "relay address resolved to nothing")
+                })?
+            }
+        };
+
+        socket.connect(relay_addr).await?;
+
+        Ok(Self {
+            socket,
+            control: stream,
+            relay_addr,
+        })
+    }
+}
+
+impl<S> Socks5UdpSocket<S> {
+    /// Sends a datagram to `addr` through the relay. Returns the length of the payload sent.
+    ///
+    /// A domain name in `addr` is resolved by the proxy.
+    pub async fn send_to<P: AsRef<[u8]>>(&self, pkt: P, addr: Address) -> Result<usize, IoError> {
+        let header = UdpHeader::new(0, addr);
+        let mut buf = BytesMut::with_capacity(header.serialized_len() + pkt.as_ref().len());
+        header.write_to_buf(&mut buf);
+        buf.extend_from_slice(pkt.as_ref());
+
+        self.socket
+            .send(&buf)
+            .await
+            .map(|len| len - header.serialized_len())
+    }
+
+    /// Receives a datagram from the relay, returning its payload and the address of the remote it came from.
+    ///
+    /// An error is returned if the SOCKS5 UDP header of the datagram is malformed. Fragmented datagrams are dropped.
+    pub async fn recv_from(&self) -> Result<(Bytes, Address), Error> {
+        let mut buf = BytesMut::with_capacity(u16::MAX as usize);
+
+        loop {
+            // reclaims the space of a discarded fragment without reallocating
+            buf.clear();
+            buf.reserve(u16::MAX as usize);
+            self.socket.recv_buf(&mut buf).await?;
+
+            let Some(header) = UdpHeader::decode(&mut buf)? else {
+                return Err(Error::Io(IoError::new(
+                    ErrorKind::UnexpectedEof,
+                    "truncated SOCKS5 UDP header",
+                )));
+            };
+
+            if header.frag == 0 {
+                return Ok((buf.freeze(), header.address));
+            }
+        }
+    }
+
+    /// Returns the address of the UDP relay.
+    #[inline]
+    pub fn relay_addr(&self) -> SocketAddr {
+        self.relay_addr
+    }
+
+    /// Returns the local address of the UDP socket.
+    #[inline]
+    pub fn local_addr(&self) -> Result<SocketAddr, IoError> {
+        self.socket.local_addr()
+    }
+
+    /// Gets a reference to the underlying UDP socket, which is connected to the relay.
+    #[inline]
+    pub fn get_ref(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    /// Gets a reference to the control connection.
+    #[inline]
+    pub fn control(&self) -> &S {
+        &self.control
+    }
+
+    /// Returns the underlying UDP socket and the control connection. The association lasts until the control connection is closed.
+    #[inline]
+    pub fn into_inner(self) -> (UdpSocket, S) {
+        (self.socket, self.control)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests;
+    use std::{net::Ipv4Addr, time::Duration};
+    use tokio::{io::DuplexStream, task::JoinHandle, time};
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    /// Associates through a proxy replying with `reply_ip` and the port of a fake relay. Returns the socket, the relay and the proxy.
+    async fn associate(
+        reply_ip: [u8; 4],
+    ) -> (
+        Socks5UdpSocket<DuplexStream>,
+        UdpSocket,
+        JoinHandle<Vec<u8>>,
+    ) {
+        let relay = UdpSocket::bind((LOCALHOST, 0)).await.unwrap();
+        let port = relay.local_addr().unwrap().port().to_be_bytes();
+
+        let reply = [0x05, 0x00, 0x05, 0x00, 0x00, 0x01];
+        let script = [reply.as_slice(), &reply_ip, &port].concat();
+        let (stream, proxy) = tests::proxy(&script);
+
+        let socket = UdpSocket::bind((LOCALHOST, 0)).await.unwrap();
+        let socket = Socks5UdpSocket::associate_with(stream, socket, LOCALHOST, &Auth::None)
+            .await
+            .unwrap();
+        assert_eq!(socket.relay_addr(), relay.local_addr().unwrap());
+
+        (socket, relay, proxy)
+    }
+
+    async fn recv(socket: &Socks5UdpSocket<DuplexStream>) -> Result<(Bytes, Address), Error> {
+        time::timeout(Duration::from_secs(5), socket.recv_from())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn send_to() {
+        let (socket, relay, proxy) = associate([127, 0, 0, 1]).await;
+        let local_addr = socket.local_addr().unwrap();
+
+        assert_eq!(socket.send_to(b"query", tests::target()).await.unwrap(), 5);
+
+        let dns = Address::DomainAddress(b"dns.google".to_vec(), 53);
+        assert_eq!(socket.send_to(b"query", dns).await.unwrap(), 5);
+
+        let mut buf = [0; 64];
+        let (len, src) = relay.recv_from(&mut buf).await.unwrap();
+        assert_eq!(src, local_addr);
+        assert_eq!(
+            &buf[..len],
+            b"\x00\x00\x00\x01\x0a\x00\x00\x01\x00\x50query"
+        );
+
+        let len = relay.recv(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"\x00\x00\x00\x03\x0adns.google\x00\x35query");
+
+        // the request carries the local address of the socket
+        drop(socket);
+        let mut expected = vec![0x05, 0x01, 0x00, 0x05, 0x03, 0x00, 0x01, 127, 0, 0, 1];
+        expected.extend_from_slice(&local_addr.port().to_be_bytes());
+        assert_eq!(proxy.await.unwrap(), expected);
+    }
+
+    #[tokio::test]
+    async fn fragments_are_skipped() {
+        let (socket, relay, _proxy) = associate([127, 0, 0, 1]).await;
+        let local_addr = socket.local_addr().unwrap();
+
+        let mut fragment = b"\x00\x00\x01\x01\x0a\x00\x00\x01\x00\x50".to_vec();
+        fragment.extend_from_slice(&[0xaa; 2000]);
+        relay.send_to(&fragment, local_addr).await.unwrap();
+
+        let pkt = b"\x00\x00\x00\x01\x0a\x00\x00\x02\x00\x50reply";
+        relay.send_to(pkt, local_addr).await.unwrap();
+
+        let (pkt, from) = recv(&socket).await.unwrap();
+        assert_eq!(&pkt[..], b"reply");
+        assert_eq!(from, Address::SocketAddress(([10, 0, 0, 2], 80).into()));
+    }
+
+    #[tokio::test]
+    async fn malformed_header() {
+        let (socket, relay, _proxy) = associate([127, 0, 0, 1]).await;
+        let local_addr = socket.local_addr().unwrap();
+
+        // unknown address type
+        relay
+            .send_to(b"\x00\x00\x00\x09payload", local_addr)
+            .await
+            .unwrap();
+        assert!(matches!(recv(&socket).await, Err(Error::Protocol(_))));
+
+        relay.send_to(b"\x00\x00\x00", local_addr).await.unwrap();
+        let res = recv(&socket).await;
+        assert!(matches!(res, Err(Error::Io(err)) if err.kind() == ErrorKind::UnexpectedEof));
+
+        // the socket is still usable
+        let pkt = b"\x00\x00\x00\x01\x0a\x00\x00\x01\x00\x50reply";
+        relay.send_to(pkt, local_addr).await.unwrap();
+        assert_eq!(&recv(&socket).await.unwrap().0[..], b"reply");
+    }
+
+    #[tokio::test]
+    async fn unspecified_relay_address() {
+        let (socket, relay, _proxy) = associate([0, 0, 0, 0]).await;
+
+        socket.send_to(b"query", tests::target()).await.unwrap();
+
+        let mut buf = [0; 64];
+        let (len, src) = relay.recv_from(&mut buf).await.unwrap();
+        assert_eq!(src, socket.local_addr().unwrap());
+        assert!(buf[..len].ends_with(b"query"));
+    }
+}
@@ -71,6 +71,62 @@ impl Address {
         }
     }
 
+    /// Parses an address from the front of `buf` without consuming it.
+    ///
+    /// On success, returns the address and the number of bytes it occupies, or `None` if `buf` does not contain a complete address yet.
+    pub(crate) fn parse(buf: &[u8]) -> Result<Option<(Self, usize)>, AddressError> {
+        let Some(&atyp) = buf.first() else {
+            return Ok(None);
+        };
+
+        match atyp {
+            Self::ATYP_IPV4 => {
+                if buf.len() < 1 + 6 {
+                    return Ok(None);
+                }
+
+                let addr = Ipv4Addr::new(buf[1], buf[2], buf[3], buf[4]);
+                let port = u16::from_be_bytes([buf[5], buf[6]]);
+
+                Ok(Some((
+                    Self::SocketAddress(SocketAddr::from((addr, port))),
+                    1 + 6,
+                )))
+            }
+            Self::ATYP_FQDN => {
+                let Some(&len) = buf.get(1) else {
+                    return Ok(None);
+                };
+                let len = len as usize;
+
+                if buf.len() < 2 + len + 2 {
+                    return Ok(None);
+                }
+
+                let addr = buf[2..2 + len].to_vec();
+                let port = u16::from_be_bytes([buf[2 + len], buf[2 + len + 1]]);
+
+                Ok(Some((Self::DomainAddress(addr, port), 2 + len + 2)))
+            }
+            Self::ATYP_IPV6 => {
+                if buf.len() < 1 + 18 {
+                    return Ok(None);
+                }
+
+                let mut octets = [0; 16];
+                octets.copy_from_slice(&buf[1..17]);
+                let addr = Ipv6Addr::from(octets);
+                let port = u16::from_be_bytes([buf[17], buf[18]]);
+
+                Ok(Some((
+                    Self::SocketAddress(SocketAddr::from((addr, port))),
+                    1 + 18,
+                )))
+            }
+            atyp => Err(AddressError::InvalidType(atyp)),
+        }
+    }
+
     pub(crate) fn write_to_buf<B: BufMut>(&self, buf: &mut B) {
         match self {
             Self::SocketAddress(SocketAddr::V4(addr)) => {
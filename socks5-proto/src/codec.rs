@@ -0,0 +1,341 @@
+//! [`tokio_util::codec`](https://docs.rs/tokio-util/latest/tokio_util/codec/index.html) implementations for SOCKS5 protocol messages.
+//!
+//! Codecs are split by side and by stage of the protocol. A `Framed` can be switched from one stage to the next with [`Framed::map_codec()`](https://docs.rs/tokio-util/latest/tokio_util/codec/struct.Framed.html#method.map_codec), which keeps the bytes already buffered.
+//!
+//! | Stage              | Server side              | Client side              |
+//! | ------------------ | ------------------------ | ------------------------ |
+//! | Handshake          | [`ServerHandshakeCodec`] | [`ClientHandshakeCodec`] |
+//! | Password auth      | [`ServerPasswordCodec`]  | [`ClientPasswordCodec`]  |
+//! | Request / response | [`ServerRequestCodec`]   | [`ClientRequestCodec`]   |
+//!
+//! [`UdpCodec`] can be used with [`UdpFramed`](https://docs.rs/tokio-util/latest/tokio_util/udp/struct.UdpFramed.html) to send and receive SOCKS5 UDP packets.
+
+use crate::{
+    handshake::{
+        password::{
+            Error as PasswordError, Request as PasswordRequest, Response as PasswordResponse,
+        },
+        Request as HandshakeRequest, Response as HandshakeResponse,
+    },
+    Error, Request, Response, UdpHeader,
+};
+use bytes::{Bytes, BytesMut};
+use std::io::{Error as IoError, ErrorKind};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Server side handshake codec, decoding [`handshake::Request`](crate::handshake::Request) and encoding [`handshake::Response`](crate::handshake::Response).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ServerHandshakeCodec;
+
+impl Decoder for ServerHandshakeCodec {
+    type Item = HandshakeRequest;
+    type Error = Error;
+
+    #[inline]
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        HandshakeRequest::decode(src)
+    }
+}
+
+impl Encoder<HandshakeResponse> for ServerHandshakeCodec {
+    type Error = Error;
+
+    #[inline]
+    fn encode(&mut self, item: HandshakeResponse, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        item.encode(dst);
+        Ok(())
+    }
+}
+
+/// Client side handshake codec, decoding [`handshake::Response`](crate::handshake::Response) and encoding [`handshake::Request`](crate::handshake::Request).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ClientHandshakeCodec;
+
+impl Decoder for ClientHandshakeCodec {
+    type Item = HandshakeResponse;
+    type Error = Error;
+
+    #[inline]
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        HandshakeResponse::decode(src)
+    }
+}
+
+impl Encoder<HandshakeRequest> for ClientHandshakeCodec {
+    type Error = Error;
+
+    #[inline]
+    fn encode(&mut self, item: HandshakeRequest, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        item.encode(dst);
+        Ok(())
+    }
+}
+
+/// Server side password authentication codec, decoding [`password::Request`](crate::handshake::password::Request) and encoding [`password::Response`](crate::handshake::password::Response).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ServerPasswordCodec;
+
+impl Decoder for ServerPasswordCodec {
+    type Item = PasswordRequest;
+    type Error = PasswordError;
+
+    #[inline]
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        PasswordRequest::decode(src)
+    }
+}
+
+impl Encoder<PasswordResponse> for ServerPasswordCodec {
+    type Error = PasswordError;
+
+    #[inline]
+    fn encode(&mut self, item: PasswordResponse, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        item.encode(dst);
+        Ok(())
+    }
+}
+
+/// Client side password authentication codec, decoding [`password::Response`](crate::handshake::password::Response) and encoding [`password::Request`](crate::handshake::password::Request).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ClientPasswordCodec;
+
+impl Decoder for ClientPasswordCodec {
+    type Item = PasswordResponse;
+    type Error = PasswordError;
+
+    #[inline]
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        PasswordResponse::decode(src)
+    }
+}
+
+impl Encoder<PasswordRequest> for ClientPasswordCodec {
+    type Error = PasswordError;
+
+    #[inline]
+    fn encode(&mut self, item: PasswordRequest, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        item.encode(dst);
+        Ok(())
+    }
+}
+
+/// Server side request codec, decoding [`Request`] and encoding [`Response`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ServerRequestCodec;
+
+impl Decoder for ServerRequestCodec {
+    type Item = Request;
+    type Error = Error;
+
+    #[inline]
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        Request::decode(src)
+    }
+}
+
+impl Encoder<Response> for ServerRequestCodec {
+    type Error = Error;
+
+    #[inline]
+    fn encode(&mut self, item: Response, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        item.encode(dst);
+        Ok(())
+    }
+}
+
+/// Client side request codec, decoding [`Response`] and encoding [`Request`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ClientRequestCodec;
+
+impl Decoder for ClientRequestCodec {
+    type Item = Response;
+    type Error = Error;
+
+    #[inline]
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        Response::decode(src)
+    }
+}
+
+impl Encoder<Request> for ClientRequestCodec {
+    type Error = Error;
+
+    #[inline]
+    fn encode(&mut self, item: Request, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        item.encode(dst);
+        Ok(())
+    }
+}
+
+/// SOCKS5 UDP packet codec, decoding and encoding `(payload, UdpHeader)` pairs.
+///
+/// This codec is meant to be used with [`UdpFramed`](https://docs.rs/tokio-util/latest/tokio_util/udp/struct.UdpFramed.html), in which every call to `decode()` receives exactly one datagram. A datagram with a malformed or truncated header is discarded and reported as an error, so the stream can keep being polled afterwards.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct UdpCodec;
+
+impl Decoder for UdpCodec {
+    type Item = (Bytes, UdpHeader);
+    type Error = Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if src.is_empty() {
+            return Ok(None);
+        }
+
+        match UdpHeader::decode(src) {
+            Ok(Some(header)) => Ok(Some((src.split().freeze(), header))),
+            Ok(None) => {
+                src.clear();
+                Err(Error::Io(IoError::new(
+                    ErrorKind::UnexpectedEof,
+                    "truncated SOCKS5 UDP header",
+                )))
+            }
+            Err(err) => {
+                src.clear();
+                Err(err)
+            }
+        }
+    }
+}
+
+impl Encoder<(Bytes, UdpHeader)> for UdpCodec {
+    type Error = Error;
+
+    #[inline]
+    fn encode(&mut self, item: (Bytes, UdpHeader), dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let (pkt, header) = item;
+        dst.reserve(header.serialized_len() + pkt.len());
+        header.write_to_buf(dst);
+        dst.extend_from_slice(&pkt);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{handshake::Method, Address, Command, Reply};
+    use std::{fmt::Debug, net::SocketAddr};
+
+    /// Encodes `item` with `encoder`, checks that `decoder` waits for every prefix of it, and decodes it followed by more bytes.
+    fn round_trip<T, E, D>(encoder: &mut E, item: T, decoder: &mut D) -> D::Item
+    where
+        E: Encoder<T>,
+        E::Error: Debug,
+        D: Decoder,
+        D::Item: Debug,
+        D::Error: Debug,
+    {
+        let mut full = BytesMut::new();
+        encoder.encode(item, &mut full).unwrap();
+
+        for len in 0..full.len() {
+            let mut buf = BytesMut::from(&full[..len]);
+            assert!(decoder.decode(&mut buf).unwrap().is_none());
+            assert_eq!(buf.len(), len);
+        }
+
+        full.extend_from_slice(b"rest");
+        let item = decoder.decode(&mut full).unwrap().unwrap();
+        assert_eq!(&full[..], b"rest");
+        item
+    }
+
+    fn addr() -> Address {
+        Address::SocketAddress(SocketAddr::from(([127, 0, 0, 1], 1080)))
+    }
+
+    #[test]
+    fn handshake() {
+        let methods = vec![Method::NONE, Method::PASSWORD];
+        let req = round_trip(
+            &mut ClientHandshakeCodec,
+            HandshakeRequest::new(methods.clone()),
+            &mut ServerHandshakeCodec,
+        );
+        assert_eq!(req.methods, methods);
+
+        let resp = round_trip(
+            &mut ServerHandshakeCodec,
+            HandshakeResponse::new(Method::PASSWORD),
+            &mut ClientHandshakeCodec,
+        );
+        assert_eq!(resp.method, Method::PASSWORD);
+    }
+
+    #[test]
+    fn password() {
+        let req = round_trip(
+            &mut ClientPasswordCodec,
+            PasswordRequest::new(b"user".to_vec(), b"pass".to_vec()),
+            &mut ServerPasswordCodec,
+        );
+        assert_eq!(req.username, b"user");
+        assert_eq!(req.password, b"pass");
+
+        for status in [true, false] {
+            let resp = round_trip(
+                &mut ServerPasswordCodec,
+                PasswordResponse::new(status),
+                &mut ClientPasswordCodec,
+            );
+            assert_eq!(resp.status, status);
+        }
+    }
+
+    #[test]
+    fn request() {
+        let domain = Address::DomainAddress(b"example.com".to_vec(), 443);
+
+        let req = round_trip(
+            &mut ClientRequestCodec,
+            Request::new(Command::Connect, domain.clone()),
+            &mut ServerRequestCodec,
+        );
+        assert_eq!(req.command, Command::Connect);
+        assert_eq!(req.address, domain);
+
+        let resp = round_trip(
+            &mut ServerRequestCodec,
+            Response::new(Reply::Succeeded, addr()),
+            &mut ClientRequestCodec,
+        );
+        assert_eq!(resp.reply, Reply::Succeeded);
+        assert_eq!(resp.address, addr());
+    }
+
+    #[test]
+    fn udp() {
+        let mut buf = BytesMut::new();
+        UdpCodec
+            .encode(
+                (Bytes::from_static(b"payload"), UdpHeader::new(0, addr())),
+                &mut buf,
+            )
+            .unwrap();
+
+        let (pkt, header) = UdpCodec.decode(&mut buf).unwrap().unwrap();
+        assert_eq!(&pkt[..], b"payload");
+        assert_eq!(header.frag, 0);
+        assert_eq!(header.address, addr());
+        assert!(buf.is_empty());
+        assert!(UdpCodec.decode(&mut buf).unwrap().is_none());
+    }
+
+    #[test]
+    fn udp_clears_malformed_datagram() {
+        // truncated header
+        let mut buf = BytesMut::from(&[0x00, 0x00, 0x00, 0x01, 127, 0][..]);
+        assert!(matches!(
+            UdpCodec.decode(&mut buf),
+            Err(Error::Io(err)) if err.kind() == ErrorKind::UnexpectedEof
+        ));
+        assert!(buf.is_empty());
+
+        // invalid address type
+        let mut buf = BytesMut::from(&[0x00, 0x00, 0x00, 0x05, 0x00, 0x00][..]);
+        assert!(UdpCodec.decode(&mut buf).is_err());
+        assert!(buf.is_empty());
+    }
+}
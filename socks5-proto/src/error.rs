@@ -1,7 +1,7 @@
 //! Error types for the SOCKS5 protocol
 
 use crate::{handshake::Method, Command, Reply};
-use std::io::{Error as IoError, ErrorKind};
+use std::io::Error as IoError;
 use thiserror::Error;
 
 /// Errors may occured during protocol header parsing
@@ -45,7 +45,7 @@ pub enum ProtocolError {
 
 impl From<ProtocolError> for IoError {
     fn from(err: ProtocolError) -> Self {
-        IoError::new(ErrorKind::Other, err)
+        IoError::other(err)
     }
 }
 
@@ -62,7 +62,7 @@ impl From<Error> for IoError {
     fn from(err: Error) -> Self {
         match err {
             Error::Io(err) => err,
-            err => IoError::new(ErrorKind::Other, err),
+            err => IoError::other(err),
         }
     }
 }
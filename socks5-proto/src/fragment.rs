@@ -0,0 +1,314 @@
+use crate::{Address, UdpHeader};
+use bytes::{Bytes, BytesMut};
+use std::time::{Duration, Instant};
+
+/// Reassembles fragmented SOCKS5 UDP datagrams.
+///
+/// RFC 1928 fragments a datagram into a sequence of packets with `FRAG` from 1 to 127, the last of which has the high-order bit set. A reassembler keeps a single queue of fragments:
+///
+/// - a standalone datagram (`FRAG` 0) is returned as is and abandons the queue
+/// - a fragment with a position not following the previous one abandons the queue, and starts a new one if its position is 1
+/// - the queue is abandoned if the sequence is not completed within the reassembly timeout, or if it grows beyond the maximum length
+///
+/// This is a sans-IO type. The current time is passed in by the caller, so the reassembly timer can be driven by any clock.
+#[derive(Debug)]
+pub struct Reassembler {
+    timeout: Duration,
+    max_len: usize,
+    queue: Option<Queue>,
+}
+
+#[derive(Debug)]
+struct Queue {
+    address: Address,
+    position: u8,
+    fragments: Vec<Bytes>,
+    len: usize,
+    deadline: Instant,
+}
+
+impl Reassembler {
+    /// The minimum reassembly timeout recommended by RFC 1928.
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Creates a new `Reassembler`. `max_len` is the maximum length of a reassembled payload.
+    pub fn new(timeout: Duration, max_len: usize) -> Self {
+        Self {
+            timeout,
+            max_len,
+            queue: None,
+        }
+    }
+
+    /// Pushes a received packet.
+    ///
+    /// Returns the reassembled payload with a header of `FRAG` 0 once a datagram is complete, or `None` if more fragments are needed or the packet was discarded.
+    pub fn push(
+        &mut self,
+        header: UdpHeader,
+        payload: Bytes,
+        now: Instant,
+    ) -> Option<(Bytes, UdpHeader)> {
+        self.expire(now);
+
+        if header.frag == 0 {
+            self.queue = None;
+            return Some((payload, header));
+        }
+
+        let position = header.frag & 0x7f;
+        let is_last = header.frag & 0x80 != 0;
+
+        let queue = match self.queue.take() {
+            Some(queue) if queue.position + 1 == position && queue.address == header.address => {
+                self.queue.insert(queue)
+            }
+            _ if position == 1 => self.queue.insert(Queue {
+                address: header.address,
+                position: 0,
+                fragments: Vec::new(),
+                len: 0,
+                deadline: now + self.timeout,
+            }),
+            _ => return None,
+        };
+
+        if queue.len + payload.len() > self.max_len {
+            self.queue = None;
+            return None;
+        }
+
+        queue.position = position;
+        queue.len += payload.len();
+        queue.fragments.push(payload);
+
+        if !is_last {
+            return None;
+        }
+
+        let queue = self.queue.take().unwrap();
+        let mut buf = BytesMut::with_capacity(queue.len);
+
+        for fragment in queue.fragments {
+            buf.extend_from_slice(&fragment);
+        }
+
+        Some((buf.freeze(), UdpHeader::new(0, queue.address)))
+    }
+
+    /// Abandons the queue if its reassembly timer has expired.
+    pub fn expire(&mut self, now: Instant) {
+        if self
+            .queue
+            .as_ref()
+            .is_some_and(|queue| now >= queue.deadline)
+        {
+            self.queue = None;
+        }
+    }
+
+    /// Returns when the reassembly timer of the current queue expires, if there is one.
+    #[inline]
+    pub fn deadline(&self) -> Option<Instant> {
+        self.queue.as_ref().map(|queue| queue.deadline)
+    }
+
+    /// Abandons the current queue.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.queue = None;
+    }
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_TIMEOUT, u16::MAX as usize)
+    }
+}
+
+/// Splits a payload into SOCKS5 UDP fragments.
+///
+/// Each fragment, header included, fits in the maximum packet size. A payload that fits in a single packet is not fragmented and gets a header of `FRAG` 0.
+#[derive(Clone, Copy, Debug)]
+pub struct Fragmenter {
+    max_pkt_size: usize,
+}
+
+impl Fragmenter {
+    /// The maximum number of fragments of a datagram.
+    pub const MAX_FRAGMENTS: usize = 127;
+
+    /// Creates a new `Fragmenter`. `max_pkt_size` is the maximum size of a packet, with SOCKS5 UDP header included.
+    #[inline]
+    pub fn new(max_pkt_size: usize) -> Self {
+        Self { max_pkt_size }
+    }
+
+    /// Splits `payload` to be sent to `address`.
+    ///
+    /// Returns `None` if the payload would need more than 127 fragments, or the header alone does not fit in a packet.
+    pub fn fragment(&self, payload: Bytes, address: Address) -> Option<Vec<(Bytes, UdpHeader)>> {
+        let header = UdpHeader::new(0, address);
+        let chunk_size = self.max_pkt_size.checked_sub(header.serialized_len())?;
+
+        if payload.len() <= chunk_size {
+            return Some(vec![(payload, header)]);
+        }
+
+        if chunk_size == 0 {
+            return None;
+        }
+
+        let count = payload.len().div_ceil(chunk_size);
+
+        if count > Self::MAX_FRAGMENTS {
+            return None;
+        }
+
+        let fragments = (0..count)
+            .map(|idx| {
+                let start = idx * chunk_size;
+                let end = (start + chunk_size).min(payload.len());
+                let mut frag = idx as u8 + 1;
+
+                if idx + 1 == count {
+                    frag |= 0x80;
+                }
+
+                (
+                    payload.slice(start..end),
+                    UdpHeader::new(frag, header.address.clone()),
+                )
+            })
+            .collect();
+
+        Some(fragments)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    fn addr() -> Address {
+        Address::SocketAddress(SocketAddr::from(([127, 0, 0, 1], 53)))
+    }
+
+    fn push(r: &mut Reassembler, frag: u8, payload: &'static [u8], now: Instant) -> Option<Bytes> {
+        r.push(
+            UdpHeader::new(frag, addr()),
+            Bytes::from_static(payload),
+            now,
+        )
+        .map(|(payload, header)| {
+            assert_eq!(header.frag, 0);
+            assert_eq!(header.address, addr());
+            payload
+        })
+    }
+
+    #[test]
+    fn in_order() {
+        let mut r = Reassembler::default();
+        let now = Instant::now();
+
+        assert_eq!(push(&mut r, 1, b"ab", now), None);
+        assert_eq!(push(&mut r, 2, b"cd", now), None);
+        assert_eq!(push(&mut r, 0x83, b"e", now).unwrap(), &b"abcde"[..]);
+        assert_eq!(r.deadline(), None);
+    }
+
+    #[test]
+    fn out_of_order() {
+        let mut r = Reassembler::default();
+        let now = Instant::now();
+
+        assert_eq!(push(&mut r, 2, b"cd", now), None);
+        assert_eq!(r.deadline(), None);
+
+        assert_eq!(push(&mut r, 1, b"ab", now), None);
+        assert_eq!(push(&mut r, 0x83, b"e", now), None);
+        assert_eq!(r.deadline(), None);
+
+        // a new sequence can start right after
+        assert_eq!(push(&mut r, 1, b"ab", now), None);
+        assert_eq!(push(&mut r, 0x82, b"cd", now).unwrap(), &b"abcd"[..]);
+    }
+
+    #[test]
+    fn duplicate() {
+        let mut r = Reassembler::default();
+        let now = Instant::now();
+
+        assert_eq!(push(&mut r, 1, b"ab", now), None);
+        assert_eq!(push(&mut r, 2, b"cd", now), None);
+        assert_eq!(push(&mut r, 2, b"cd", now), None);
+        assert_eq!(push(&mut r, 0x83, b"e", now), None);
+
+        // a duplicate first fragment restarts the sequence
+        assert_eq!(push(&mut r, 1, b"xx", now), None);
+        assert_eq!(push(&mut r, 1, b"ab", now), None);
+        assert_eq!(push(&mut r, 0x82, b"cd", now).unwrap(), &b"abcd"[..]);
+    }
+
+    #[test]
+    fn timeout() {
+        let mut r = Reassembler::new(Duration::from_secs(5), 1024);
+        let now = Instant::now();
+
+        assert_eq!(push(&mut r, 1, b"ab", now), None);
+        assert_eq!(r.deadline(), Some(now + Duration::from_secs(5)));
+
+        assert_eq!(
+            push(&mut r, 0x82, b"cd", now + Duration::from_secs(5)),
+            None
+        );
+        assert_eq!(r.deadline(), None);
+
+        assert_eq!(push(&mut r, 1, b"ab", now), None);
+        r.expire(now + Duration::from_secs(4));
+        assert!(r.deadline().is_some());
+        r.expire(now + Duration::from_secs(6));
+        assert_eq!(r.deadline(), None);
+    }
+
+    #[test]
+    fn standalone_and_max_len() {
+        let mut r = Reassembler::new(Duration::from_secs(5), 4);
+        let now = Instant::now();
+
+        assert_eq!(push(&mut r, 1, b"ab", now), None);
+        assert_eq!(push(&mut r, 0, b"solo", now).unwrap(), &b"solo"[..]);
+        assert_eq!(push(&mut r, 0x82, b"cd", now), None);
+
+        assert_eq!(push(&mut r, 1, b"abc", now), None);
+        assert_eq!(push(&mut r, 0x82, b"de", now), None);
+        assert_eq!(r.deadline(), None);
+    }
+
+    #[test]
+    fn fragment_and_reassemble() {
+        let payload = Bytes::from((0..=255).collect::<Vec<u8>>());
+        let header_len = UdpHeader::new(0, addr()).serialized_len();
+        let fragments = Fragmenter::new(header_len + 100)
+            .fragment(payload.clone(), addr())
+            .unwrap();
+
+        assert_eq!(fragments.len(), 3);
+        assert_eq!(fragments[2].1.frag, 0x83);
+
+        let mut r = Reassembler::default();
+        let now = Instant::now();
+        let mut res = None;
+
+        for (payload, header) in fragments {
+            res = r.push(header, payload, now);
+        }
+
+        assert_eq!(res.unwrap().0, payload);
+        assert!(Fragmenter::new(header_len + 1)
+            .fragment(payload, addr())
+            .is_none());
+    }
+}
@@ -1,4 +1,4 @@
-use std::io::{Error as IoError, ErrorKind};
+use std::io::Error as IoError;
 use thiserror::Error;
 
 /// Errors may occured during SOCKS5 password authentication
@@ -18,7 +18,7 @@ impl From<Error> for IoError {
     fn from(err: Error) -> Self {
         match err {
             Error::Io(err) => err,
-            err => IoError::new(ErrorKind::Other, err),
+            err => IoError::other(err),
         }
     }
 }
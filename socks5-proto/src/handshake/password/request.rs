@@ -1,5 +1,5 @@
 use super::Error;
-use bytes::{BufMut, BytesMut};
+use bytes::{Buf, BufMut, BytesMut};
 use std::io::Error as IoError;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
@@ -45,6 +45,39 @@ impl Request {
         Ok(Self::new(username, password))
     }
 
+    /// Decodes a password handshake request from the front of `buf`.
+    ///
+    /// Returns `Ok(None)` without consuming any byte if `buf` does not contain a complete password handshake request yet. On success, the decoded bytes are removed from `buf`.
+    pub fn decode(buf: &mut BytesMut) -> Result<Option<Self>, Error> {
+        let Some(&ver) = buf.first() else {
+            return Ok(None);
+        };
+
+        if ver != super::SUBNEGOTIATION_VERSION {
+            return Err(Error::SubNegotiationVersion { version: ver });
+        }
+
+        let Some(&ulen) = buf.get(1) else {
+            return Ok(None);
+        };
+        let ulen = ulen as usize;
+
+        let Some(&plen) = buf.get(2 + ulen) else {
+            return Ok(None);
+        };
+        let plen = plen as usize;
+
+        if buf.len() < 3 + ulen + plen {
+            return Ok(None);
+        }
+
+        let username = buf[2..2 + ulen].to_vec();
+        let password = buf[3 + ulen..3 + ulen + plen].to_vec();
+        buf.advance(3 + ulen + plen);
+
+        Ok(Some(Self::new(username, password)))
+    }
+
     pub async fn write_to<W>(&self, w: &mut W) -> Result<(), IoError>
     where
         W: AsyncWrite + Unpin,
@@ -66,7 +99,45 @@ impl Request {
         buf.put_slice(&self.password);
     }
 
+    /// Encodes the password handshake request into `buf`.
+    pub fn encode(&self, buf: &mut BytesMut) {
+        buf.reserve(self.serialized_len());
+        self.write_to_buf(buf);
+    }
+
     pub fn serialized_len(&self) -> usize {
         3 + self.username.len() + self.password.len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_partial() {
+        let mut full = BytesMut::new();
+        Request::new(b"user".to_vec(), b"pass".to_vec()).encode(&mut full);
+
+        for len in 0..full.len() {
+            let mut buf = BytesMut::from(&full[..len]);
+            assert!(Request::decode(&mut buf).unwrap().is_none());
+            assert_eq!(buf.len(), len);
+        }
+
+        full.extend_from_slice(b"rest");
+        let req = Request::decode(&mut full).unwrap().unwrap();
+        assert_eq!(req.username, b"user");
+        assert_eq!(req.password, b"pass");
+        assert_eq!(&full[..], b"rest");
+    }
+
+    #[test]
+    fn decode_garbage() {
+        let mut buf = BytesMut::from(&[0x05, 0x01, b'u', 0x01, b'p'][..]);
+        assert!(matches!(
+            Request::decode(&mut buf),
+            Err(Error::SubNegotiationVersion { version: 0x05 })
+        ));
+    }
+}
@@ -1,5 +1,5 @@
 use super::Error;
-use bytes::{BufMut, BytesMut};
+use bytes::{Buf, BufMut, BytesMut};
 use std::io::Error as IoError;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
@@ -50,6 +50,38 @@ impl Response {
         Ok(Self::new(status))
     }
 
+    /// Decodes a password handshake response from the front of `buf`.
+    ///
+    /// Returns `Ok(None)` without consuming any byte if `buf` does not contain a complete password handshake response yet. On success, the decoded bytes are removed from `buf`.
+    pub fn decode(buf: &mut BytesMut) -> Result<Option<Self>, Error> {
+        let Some(&ver) = buf.first() else {
+            return Ok(None);
+        };
+
+        if ver != super::SUBNEGOTIATION_VERSION {
+            return Err(Error::SubNegotiationVersion { version: ver });
+        }
+
+        let Some(&status) = buf.get(1) else {
+            return Ok(None);
+        };
+
+        let status = match status {
+            Self::FAILED => false,
+            Self::SUCCEEDED => true,
+            code => {
+                return Err(Error::SubNegotiationStatus {
+                    version: ver,
+                    status: code,
+                });
+            }
+        };
+
+        buf.advance(2);
+
+        Ok(Some(Self::new(status)))
+    }
+
     pub async fn write_to<W>(&self, w: &mut W) -> Result<(), IoError>
     where
         W: AsyncWrite + Unpin,
@@ -71,7 +103,46 @@ impl Response {
         }
     }
 
+    /// Encodes the password handshake response into `buf`.
+    pub fn encode(&self, buf: &mut BytesMut) {
+        buf.reserve(self.serialized_len());
+        self.write_to_buf(buf);
+    }
+
     pub const fn serialized_len(&self) -> usize {
         1 + 1
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_partial() {
+        let mut buf = BytesMut::from(&[0x01][..]);
+        assert!(Response::decode(&mut buf).unwrap().is_none());
+        assert_eq!(buf.len(), 1);
+
+        buf.extend_from_slice(&[0x00, 0x01, 0xff]);
+        assert!(Response::decode(&mut buf).unwrap().unwrap().status);
+        assert!(!Response::decode(&mut buf).unwrap().unwrap().status);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn decode_garbage() {
+        let mut buf = BytesMut::from(&[0x05, 0x00][..]);
+        assert!(matches!(
+            Response::decode(&mut buf),
+            Err(Error::SubNegotiationVersion { version: 0x05 })
+        ));
+
+        let mut buf = BytesMut::from(&[0x01, 0x42][..]);
+        assert!(matches!(
+            Response::decode(&mut buf),
+            Err(Error::SubNegotiationStatus { status: 0x42, .. })
+        ));
+        assert_eq!(buf.len(), 2);
+    }
+}
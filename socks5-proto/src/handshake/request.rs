@@ -1,6 +1,6 @@
 use super::Method;
 use crate::{Error, ProtocolError};
-use bytes::{BufMut, BytesMut};
+use bytes::{Buf, BufMut, BytesMut};
 use std::{
     io::Error as IoError,
     mem::{self, ManuallyDrop},
@@ -55,6 +55,35 @@ impl Request {
         Ok(Self::new(methods))
     }
 
+    /// Decodes a handshake request from the front of `buf`.
+    ///
+    /// Returns `Ok(None)` without consuming any byte if `buf` does not contain a complete handshake request yet. On success, the decoded bytes are removed from `buf`.
+    pub fn decode(buf: &mut BytesMut) -> Result<Option<Self>, Error> {
+        let Some(&ver) = buf.first() else {
+            return Ok(None);
+        };
+
+        if ver != crate::SOCKS_VERSION {
+            return Err(Error::Protocol(ProtocolError::ProtocolVersion {
+                version: ver,
+            }));
+        }
+
+        let Some(&mlen) = buf.get(1) else {
+            return Ok(None);
+        };
+        let mlen = mlen as usize;
+
+        if buf.len() < 2 + mlen {
+            return Ok(None);
+        }
+
+        let methods = buf[2..2 + mlen].iter().copied().map(Method::from).collect();
+        buf.advance(2 + mlen);
+
+        Ok(Some(Self::new(methods)))
+    }
+
     pub async fn write_to<W>(&self, w: &mut W) -> Result<(), IoError>
     where
         W: AsyncWrite + Unpin,
@@ -70,11 +99,61 @@ impl Request {
         buf.put_u8(crate::SOCKS_VERSION);
         buf.put_u8(self.methods.len() as u8);
 
-        let methods = unsafe { mem::transmute(self.methods.as_slice()) };
+        let methods = unsafe { mem::transmute::<&[Method], &[u8]>(self.methods.as_slice()) };
         buf.put_slice(methods);
     }
 
+    /// Encodes the handshake request into `buf`.
+    pub fn encode(&self, buf: &mut BytesMut) {
+        buf.reserve(self.serialized_len());
+        self.write_to_buf(buf);
+    }
+
     pub fn serialized_len(&self) -> usize {
         1 + 1 + self.methods.len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_partial() {
+        let mut full = BytesMut::new();
+        Request::new(vec![Method::NONE, Method::PASSWORD]).encode(&mut full);
+
+        for len in 0..full.len() {
+            let mut buf = BytesMut::from(&full[..len]);
+            assert!(Request::decode(&mut buf).unwrap().is_none());
+            assert_eq!(buf.len(), len);
+        }
+
+        full.extend_from_slice(b"rest");
+        let req = Request::decode(&mut full).unwrap().unwrap();
+        assert_eq!(req.methods, [Method::NONE, Method::PASSWORD]);
+        assert_eq!(&full[..], b"rest");
+    }
+
+    #[test]
+    fn decode_empty_methods() {
+        let mut buf = BytesMut::from(&[0x05, 0x00][..]);
+        assert!(Request::decode(&mut buf)
+            .unwrap()
+            .unwrap()
+            .methods
+            .is_empty());
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn decode_garbage() {
+        let mut buf = BytesMut::from(&b"GET / HTTP/1.1\r\n"[..]);
+        assert!(matches!(
+            Request::decode(&mut buf),
+            Err(Error::Protocol(ProtocolError::ProtocolVersion {
+                version: b'G'
+            }))
+        ));
+    }
+}
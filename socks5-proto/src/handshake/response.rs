@@ -1,6 +1,6 @@
 use super::Method;
 use crate::{Error, ProtocolError};
-use bytes::{BufMut, BytesMut};
+use bytes::{Buf, BufMut, BytesMut};
 use std::io::Error as IoError;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
@@ -40,6 +40,29 @@ impl Response {
         Ok(Self::new(method))
     }
 
+    /// Decodes a handshake response from the front of `buf`.
+    ///
+    /// Returns `Ok(None)` without consuming any byte if `buf` does not contain a complete handshake response yet. On success, the decoded bytes are removed from `buf`.
+    pub fn decode(buf: &mut BytesMut) -> Result<Option<Self>, Error> {
+        let Some(&ver) = buf.first() else {
+            return Ok(None);
+        };
+
+        if ver != crate::SOCKS_VERSION {
+            return Err(Error::Protocol(ProtocolError::ProtocolVersion {
+                version: ver,
+            }));
+        }
+
+        let Some(&method) = buf.get(1) else {
+            return Ok(None);
+        };
+
+        buf.advance(2);
+
+        Ok(Some(Self::new(Method::from(method))))
+    }
+
     pub async fn write_to<W>(&self, w: &mut W) -> Result<(), IoError>
     where
         W: AsyncWrite + Unpin,
@@ -56,7 +79,46 @@ impl Response {
         buf.put_u8(u8::from(self.method));
     }
 
+    /// Encodes the handshake response into `buf`.
+    pub fn encode(&self, buf: &mut BytesMut) {
+        buf.reserve(self.serialized_len());
+        self.write_to_buf(buf);
+    }
+
     pub const fn serialized_len(&self) -> usize {
         1 + 1
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_partial() {
+        let mut buf = BytesMut::new();
+        assert!(Response::decode(&mut buf).unwrap().is_none());
+
+        let mut buf = BytesMut::from(&[0x05][..]);
+        assert!(Response::decode(&mut buf).unwrap().is_none());
+        assert_eq!(buf.len(), 1);
+
+        buf.extend_from_slice(&[0x02, 0xaa]);
+        assert_eq!(
+            Response::decode(&mut buf).unwrap().unwrap().method,
+            Method::PASSWORD
+        );
+        assert_eq!(&buf[..], [0xaa]);
+    }
+
+    #[test]
+    fn decode_garbage() {
+        let mut buf = BytesMut::from(&[0x04, 0x00][..]);
+        assert!(matches!(
+            Response::decode(&mut buf),
+            Err(Error::Protocol(ProtocolError::ProtocolVersion {
+                version: 0x04
+            }))
+        ));
+    }
+}
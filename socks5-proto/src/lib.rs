@@ -3,21 +3,28 @@
 mod address;
 mod command;
 mod error;
+mod fragment;
 mod reply;
 mod request;
 mod response;
 mod udp;
+mod udp_over_tcp;
 
 pub mod handshake;
 
+#[cfg(feature = "codec")]
+pub mod codec;
+
 pub use self::{
     address::Address,
     command::Command,
     error::{Error, ProtocolError},
+    fragment::{Fragmenter, Reassembler},
     reply::Reply,
     request::Request,
     response::Response,
     udp::UdpHeader,
+    udp_over_tcp::{UdpFrame, UdpOverTcp},
 };
 
 pub const SOCKS_VERSION: u8 = 0x05;
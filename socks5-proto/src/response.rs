@@ -1,5 +1,5 @@
 use crate::{address::AddressError, Address, Error, ProtocolError, Reply};
-use bytes::{BufMut, BytesMut};
+use bytes::{Buf, BufMut, BytesMut};
 use std::io::Error as IoError;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
@@ -57,6 +57,52 @@ impl Response {
         Ok(Self::new(rep, addr))
     }
 
+    /// Decodes a response from the front of `buf`.
+    ///
+    /// Returns `Ok(None)` without consuming any byte if `buf` does not contain a complete response yet. On success, the decoded bytes are removed from `buf`.
+    pub fn decode(buf: &mut BytesMut) -> Result<Option<Self>, Error> {
+        let Some(&ver) = buf.first() else {
+            return Ok(None);
+        };
+
+        if ver != crate::SOCKS_VERSION {
+            return Err(Error::Protocol(ProtocolError::ProtocolVersion {
+                version: ver,
+            }));
+        }
+
+        let Some(&rep) = buf.get(1) else {
+            return Ok(None);
+        };
+        let rep = Reply::try_from(rep).map_err(|rep| ProtocolError::InvalidReply {
+            version: ver,
+            reply: rep,
+        })?;
+
+        if buf.len() < 3 {
+            return Ok(None);
+        }
+
+        let addr = Address::parse(&buf[3..]).map_err(|err| match err {
+            AddressError::Io(err) => Error::Io(err),
+            AddressError::InvalidType(code) => {
+                Error::Protocol(ProtocolError::InvalidAddressTypeInResponse {
+                    version: ver,
+                    reply: rep,
+                    address_type: code,
+                })
+            }
+        })?;
+
+        let Some((addr, addr_len)) = addr else {
+            return Ok(None);
+        };
+
+        buf.advance(3 + addr_len);
+
+        Ok(Some(Self::new(rep, addr)))
+    }
+
     pub async fn write_to<W>(&self, w: &mut W) -> Result<(), IoError>
     where
         W: AsyncWrite + Unpin,
@@ -75,7 +121,60 @@ impl Response {
         self.address.write_to_buf(buf);
     }
 
+    /// Encodes the response into `buf`.
+    pub fn encode(&self, buf: &mut BytesMut) {
+        buf.reserve(self.serialized_len());
+        self.write_to_buf(buf);
+    }
+
     pub fn serialized_len(&self) -> usize {
         1 + 1 + 1 + self.address.serialized_len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn decode_partial() {
+        let addrs = [
+            Address::SocketAddress(SocketAddr::from(([127, 0, 0, 1], 80))),
+            Address::SocketAddress(SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 443))),
+            Address::DomainAddress(b"example.com".to_vec(), 8080),
+        ];
+
+        for addr in addrs {
+            let mut full = BytesMut::new();
+            Response::new(Reply::Succeeded, addr.clone()).encode(&mut full);
+
+            for len in 0..full.len() {
+                let mut buf = BytesMut::from(&full[..len]);
+                assert!(Response::decode(&mut buf).unwrap().is_none());
+                assert_eq!(buf.len(), len);
+            }
+
+            full.extend_from_slice(b"rest");
+            let resp = Response::decode(&mut full).unwrap().unwrap();
+            assert_eq!(resp.reply, Reply::Succeeded);
+            assert_eq!(resp.address, addr);
+            assert_eq!(&full[..], b"rest");
+        }
+    }
+
+    #[test]
+    fn decode_garbage() {
+        for bytes in [
+            &[0x04, 0x00, 0x00, 0x01][..],
+            &[0x05, 0x42, 0x00, 0x01],
+            &[0x05, 0x00, 0x00, 0x02, 0x00],
+        ] {
+            let mut buf = BytesMut::from(bytes);
+            assert!(matches!(
+                Response::decode(&mut buf),
+                Err(Error::Protocol(_))
+            ));
+        }
+    }
+}
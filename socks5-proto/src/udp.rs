@@ -1,5 +1,5 @@
 use crate::{address::AddressError, Address, Error, ProtocolError};
-use bytes::{BufMut, BytesMut};
+use bytes::{Buf, BufMut, BytesMut};
 use std::io::Error as IoError;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
@@ -19,6 +19,9 @@ pub struct UdpHeader {
 }
 
 impl UdpHeader {
+    /// The maximum length of a serialized UDP header, i.e. one with a 255 bytes long domain name.
+    pub const MAX_SERIALIZED_LEN: usize = 2 + 1 + 1 + 1 + 255 + 2;
+
     pub const fn new(frag: u8, address: Address) -> Self {
         Self { frag, address }
     }
@@ -44,6 +47,37 @@ impl UdpHeader {
         Ok(Self::new(frag, addr))
     }
 
+    /// Decodes a UDP header from the front of `buf`.
+    ///
+    /// Returns `Ok(None)` without consuming any byte if `buf` does not contain a complete UDP header yet. On success, the decoded bytes are removed from `buf`.
+    ///
+    /// The payload following the header is left in `buf`.
+    pub fn decode(buf: &mut BytesMut) -> Result<Option<Self>, Error> {
+        if buf.len() < 3 {
+            return Ok(None);
+        }
+
+        let frag = buf[2];
+
+        let addr = Address::parse(&buf[3..]).map_err(|err| match err {
+            AddressError::Io(err) => Error::Io(err),
+            AddressError::InvalidType(code) => {
+                Error::Protocol(ProtocolError::InvalidAddressTypeInUdpHeader {
+                    frag,
+                    address_type: code,
+                })
+            }
+        })?;
+
+        let Some((addr, addr_len)) = addr else {
+            return Ok(None);
+        };
+
+        buf.advance(3 + addr_len);
+
+        Ok(Some(Self::new(frag, addr)))
+    }
+
     pub async fn write_to<W>(&self, w: &mut W) -> Result<(), IoError>
     where
         W: AsyncWrite + Unpin,
@@ -61,7 +95,59 @@ impl UdpHeader {
         self.address.write_to_buf(buf);
     }
 
+    /// Encodes the UDP header into `buf`.
+    pub fn encode(&self, buf: &mut BytesMut) {
+        buf.reserve(self.serialized_len());
+        self.write_to_buf(buf);
+    }
+
     pub fn serialized_len(&self) -> usize {
         2 + 1 + self.address.serialized_len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn decode_partial() {
+        let addrs = [
+            Address::SocketAddress(SocketAddr::from(([127, 0, 0, 1], 53))),
+            Address::SocketAddress(SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], 53))),
+            Address::DomainAddress(b"dns.google".to_vec(), 53),
+        ];
+
+        for addr in addrs {
+            let mut full = BytesMut::new();
+            UdpHeader::new(1, addr.clone()).encode(&mut full);
+
+            for len in 0..full.len() {
+                let mut buf = BytesMut::from(&full[..len]);
+                assert!(UdpHeader::decode(&mut buf).unwrap().is_none());
+                assert_eq!(buf.len(), len);
+            }
+
+            full.extend_from_slice(b"payload");
+            let header = UdpHeader::decode(&mut full).unwrap().unwrap();
+            assert_eq!(header.frag, 1);
+            assert_eq!(header.address, addr);
+            assert_eq!(&full[..], b"payload");
+        }
+    }
+
+    #[test]
+    fn decode_garbage() {
+        let mut buf = BytesMut::from(&[0x00, 0x00, 0x00, 0x05, 0x00][..]);
+        assert!(matches!(
+            UdpHeader::decode(&mut buf),
+            Err(Error::Protocol(
+                ProtocolError::InvalidAddressTypeInUdpHeader {
+                    frag: 0,
+                    address_type: 0x05
+                }
+            ))
+        ));
+    }
+}
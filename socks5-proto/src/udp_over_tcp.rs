@@ -0,0 +1,311 @@
+use crate::{Address, Command, Error, Reply, Request, Response, UdpHeader};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use std::io::{Error as IoError, ErrorKind};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// A SOCKS5 UDP packet framed over a stream, for associations relaying datagrams over TCP
+///
+/// ```plain
+/// +-----+------------+----------+
+/// | LEN | UDP header |   DATA   |
+/// +-----+------------+----------+
+/// |  2  |  Variable  | Variable |
+/// +-----+------------+----------+
+/// ```
+///
+/// `LEN` is the length of the UDP header and the data, in network byte order.
+///
+/// A client asks for UDP-over-TCP by sending an ASSOCIATE request with the domain name [`ASSOCIATE_DOMAIN`](https://docs.rs/socks5-proto/latest/socks5_proto/struct.UdpFrame.html#associatedconstant.ASSOCIATE_DOMAIN) as address. A server accepting it replies with the same domain name, after which frames are exchanged over the connection in both directions. A server unaware of it treats it as a regular ASSOCIATE request, and replies with the address of its UDP relay.
+#[derive(Clone, Debug)]
+pub struct UdpFrame {
+    pub header: UdpHeader,
+    pub payload: Bytes,
+}
+
+impl UdpFrame {
+    /// The domain name in the ASSOCIATE request and reply of a UDP-over-TCP association.
+    pub const ASSOCIATE_DOMAIN: &'static [u8] = b"udp-over-tcp.arpa";
+
+    /// The maximum length of the UDP header and the data of a frame.
+    pub const MAX_LEN: usize = u16::MAX as usize;
+
+    pub const fn new(header: UdpHeader, payload: Bytes) -> Self {
+        Self { header, payload }
+    }
+
+    /// Returns the address in the ASSOCIATE request and reply of a UDP-over-TCP association.
+    #[inline]
+    pub fn associate_address() -> Address {
+        Address::DomainAddress(Self::ASSOCIATE_DOMAIN.to_vec(), 0)
+    }
+
+    /// Returns whether the address of an ASSOCIATE request or reply asks for or accepts UDP-over-TCP. The port is ignored.
+    #[inline]
+    pub fn is_associate_address(addr: &Address) -> bool {
+        matches!(addr, Address::DomainAddress(domain, _) if domain == Self::ASSOCIATE_DOMAIN)
+    }
+
+    pub async fn read_from<R>(r: &mut R) -> Result<Self, Error>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let len = r.read_u16().await? as usize;
+        let mut buf = BytesMut::zeroed(len);
+        r.read_exact(&mut buf).await?;
+
+        Self::decode_body(buf)
+    }
+
+    /// Decodes a frame from the front of `buf`.
+    ///
+    /// Returns `Ok(None)` without consuming any byte if `buf` does not contain a complete frame yet. On success, the decoded bytes are removed from `buf`. On error, the malformed frame is removed from `buf`, so the next frame can be decoded.
+    pub fn decode(buf: &mut BytesMut) -> Result<Option<Self>, Error> {
+        if buf.len() < 2 {
+            return Ok(None);
+        }
+
+        let len = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+
+        if buf.len() < 2 + len {
+            return Ok(None);
+        }
+
+        buf.advance(2);
+        Self::decode_body(buf.split_to(len)).map(Some)
+    }
+
+    fn decode_body(mut buf: BytesMut) -> Result<Self, Error> {
+        match UdpHeader::decode(&mut buf)? {
+            Some(header) => Ok(Self::new(header, buf.freeze())),
+            None => Err(Error::Io(IoError::new(
+                ErrorKind::InvalidData,
+                "truncated SOCKS5 UDP header in frame",
+            ))),
+        }
+    }
+
+    /// Writes the frame to `w`. An error of kind `InvalidInput` is returned if the frame is longer than [`MAX_LEN`](https://docs.rs/socks5-proto/latest/socks5_proto/struct.UdpFrame.html#associatedconstant.MAX_LEN).
+    pub async fn write_to<W>(&self, w: &mut W) -> Result<(), IoError>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        check_len(&self.header, &self.payload)?;
+
+        let mut buf = BytesMut::with_capacity(self.serialized_len());
+        self.write_to_buf(&mut buf);
+        w.write_all(&buf).await?;
+
+        Ok(())
+    }
+
+    pub fn write_to_buf<B: BufMut>(&self, buf: &mut B) {
+        buf.put_u16((self.header.serialized_len() + self.payload.len()) as u16);
+        self.header.write_to_buf(buf);
+        buf.put_slice(&self.payload);
+    }
+
+    /// Encodes the frame into `buf`. The frame must not be longer than [`MAX_LEN`](https://docs.rs/socks5-proto/latest/socks5_proto/struct.UdpFrame.html#associatedconstant.MAX_LEN).
+    pub fn encode(&self, buf: &mut BytesMut) {
+        buf.reserve(self.serialized_len());
+        self.write_to_buf(buf);
+    }
+
+    pub fn serialized_len(&self) -> usize {
+        2 + self.header.serialized_len() + self.payload.len()
+    }
+}
+
+fn check_len(header: &UdpHeader, payload: &[u8]) -> Result<(), IoError> {
+    if header.serialized_len() + payload.len() > UdpFrame::MAX_LEN {
+        return Err(IoError::new(
+            ErrorKind::InvalidInput,
+            "datagram too large for a UDP-over-TCP frame",
+        ));
+    }
+
+    Ok(())
+}
+
+/// A stream carrying SOCKS5 UDP packets in [`UdpFrame`](https://docs.rs/socks5-proto/latest/socks5_proto/struct.UdpFrame.html)s, for either side of a UDP-over-TCP association.
+///
+/// On the client side, [`associate()`](https://docs.rs/socks5-proto/latest/socks5_proto/struct.UdpOverTcp.html#method.associate) sends the ASSOCIATE request on an authenticated connection. On the server side, wrap the connection with [`new()`](https://docs.rs/socks5-proto/latest/socks5_proto/struct.UdpOverTcp.html#method.new) after replying.
+///
+/// [`recv()`](https://docs.rs/socks5-proto/latest/socks5_proto/struct.UdpOverTcp.html#method.recv) is cancel safe, so sending and receiving can be done from a single task with `tokio::select!`.
+#[derive(Debug)]
+pub struct UdpOverTcp<S> {
+    stream: S,
+    read_buf: BytesMut,
+    write_buf: BytesMut,
+}
+
+impl<S> UdpOverTcp<S> {
+    /// Wraps a connection on which the UDP-over-TCP association is established.
+    #[inline]
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            read_buf: BytesMut::new(),
+            write_buf: BytesMut::new(),
+        }
+    }
+
+    /// Gets a reference to the underlying stream.
+    #[inline]
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Gets a mutable reference to the underlying stream.
+    ///
+    /// Note that reading directly from the underlying stream skips the bytes already buffered.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Consumes the `UdpOverTcp`, returning the underlying stream alongside the bytes already buffered.
+    #[inline]
+    pub fn into_parts(self) -> (S, BytesMut) {
+        (self.stream, self.read_buf)
+    }
+}
+
+impl<S> UdpOverTcp<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Sends an ASSOCIATE request asking for UDP-over-TCP on an authenticated connection, and waits for the reply.
+    ///
+    /// An error of kind `ConnectionRefused` is returned if the server rejects the request, and of kind `Unsupported` if it replies with the address of a regular UDP relay instead.
+    pub async fn associate(mut stream: S) -> Result<Self, Error> {
+        let req = Request::new(Command::Associate, UdpFrame::associate_address());
+        req.write_to(&mut stream).await?;
+
+        let resp = Response::read_from(&mut stream).await?;
+
+        if resp.reply != Reply::Succeeded {
+            return Err(Error::Io(IoError::new(
+                ErrorKind::ConnectionRefused,
+                format!("UDP-over-TCP association rejected: {:?}", resp.reply),
+            )));
+        }
+
+        if !UdpFrame::is_associate_address(&resp.address) {
+            return Err(Error::Io(IoError::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "server does not support UDP-over-TCP, UDP relay at {}",
+                    resp.address
+                ),
+            )));
+        }
+
+        Ok(Self::new(stream))
+    }
+}
+
+impl<S> UdpOverTcp<S>
+where
+    S: AsyncRead + Unpin,
+{
+    /// Receives a SOCKS5 UDP packet, returning its payload and header.
+    ///
+    /// A frame with a malformed header is skipped and reported as an error, so receiving can go on. An error of kind `UnexpectedEof` is returned if the stream is closed.
+    pub async fn recv(&mut self) -> Result<(Bytes, UdpHeader), Error> {
+        loop {
+            if let Some(frame) = UdpFrame::decode(&mut self.read_buf)? {
+                return Ok((frame.payload, frame.header));
+            }
+
+            if self.stream.read_buf(&mut self.read_buf).await? == 0 {
+                return Err(Error::Io(IoError::from(ErrorKind::UnexpectedEof)));
+            }
+        }
+    }
+}
+
+impl<S> UdpOverTcp<S>
+where
+    S: AsyncWrite + Unpin,
+{
+    /// Sends a SOCKS5 UDP packet. Returns the length of the payload.
+    ///
+    /// An error of kind `InvalidInput` is returned if the packet is longer than [`UdpFrame::MAX_LEN`](https://docs.rs/socks5-proto/latest/socks5_proto/struct.UdpFrame.html#associatedconstant.MAX_LEN).
+    pub async fn send<P: AsRef<[u8]>>(
+        &mut self,
+        pkt: P,
+        header: &UdpHeader,
+    ) -> Result<usize, IoError> {
+        let pkt = pkt.as_ref();
+        check_len(header, pkt)?;
+
+        self.write_buf.clear();
+        self.write_buf
+            .put_u16((header.serialized_len() + pkt.len()) as u16);
+        header.write_to_buf(&mut self.write_buf);
+        self.write_buf.extend_from_slice(pkt);
+
+        self.stream.write_all(&self.write_buf).await?;
+
+        Ok(pkt.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ProtocolError;
+    use std::net::SocketAddr;
+
+    fn header() -> UdpHeader {
+        UdpHeader::new(
+            0,
+            Address::SocketAddress(SocketAddr::from(([127, 0, 0, 1], 53))),
+        )
+    }
+
+    #[tokio::test]
+    async fn recv_skips_malformed_frame() {
+        let mut buf = BytesMut::new();
+        UdpFrame::new(header(), Bytes::from_static(b"first")).encode(&mut buf);
+        // a valid length prefix around a header with an invalid address type
+        buf.extend_from_slice(&[0x00, 0x06, 0x00, 0x00, 0x00, 0x05, 0x00, 0x00]);
+        UdpFrame::new(header(), Bytes::from_static(b"second")).encode(&mut buf);
+
+        let mut stream = UdpOverTcp::new(&buf[..]);
+
+        let (pkt, hdr) = stream.recv().await.unwrap();
+        assert_eq!(&pkt[..], b"first");
+        assert_eq!(hdr.address, header().address);
+
+        assert!(matches!(
+            stream.recv().await,
+            Err(Error::Protocol(
+                ProtocolError::InvalidAddressTypeInUdpHeader {
+                    address_type: 0x05,
+                    ..
+                }
+            ))
+        ));
+
+        let (pkt, _) = stream.recv().await.unwrap();
+        assert_eq!(&pkt[..], b"second");
+
+        assert!(matches!(
+            stream.recv().await,
+            Err(Error::Io(err)) if err.kind() == ErrorKind::UnexpectedEof
+        ));
+    }
+
+    #[test]
+    fn decode_truncated_header() {
+        // the length prefix ends inside the header
+        let mut buf = BytesMut::from(&[0x00, 0x03, 0x00, 0x00, 0x00][..]);
+        UdpFrame::new(header(), Bytes::from_static(b"next")).encode(&mut buf);
+
+        assert!(UdpFrame::decode(&mut buf).is_err());
+        let frame = UdpFrame::decode(&mut buf).unwrap().unwrap();
+        assert_eq!(&frame.payload[..], b"next");
+        assert!(buf.is_empty());
+    }
+}
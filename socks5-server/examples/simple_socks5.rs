@@ -27,7 +27,7 @@ async fn main() -> Result<(), IoError> {
 
 async fn handle(conn: IncomingConnection<()>) -> Result<(), Error> {
     let conn = match conn.authenticate().await {
-        Ok((conn, _)) => conn,
+        Ok((conn, _, _)) => conn,
         Err((err, mut conn)) => {
             let _ = conn.shutdown().await;
             return Err(err);
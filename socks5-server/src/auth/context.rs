@@ -0,0 +1,185 @@
+use socks5_proto::handshake::Method;
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    net::SocketAddr,
+};
+
+/// Information about the connection being authenticated.
+///
+/// An `AuthContext` is created alongside every [`IncomingConnection`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.IncomingConnection.html) and handed to [`Auth::execute()`](https://docs.rs/socks5-server/latest/socks5_server/auth/trait.Auth.html#tymethod.execute), so adaptors can make decisions based on who is connecting. After authentication, it is kept in the [`Authenticated`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Authenticated.html) connection, so data stored in its [`Extensions`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.Extensions.html) can be handed to later stages.
+///
+/// Connections accepted by a [`Server`](https://docs.rs/socks5-server/latest/socks5_server/struct.Server.html) have the addresses and the listener ID filled in. For connections created with [`IncomingConnection::new()`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.IncomingConnection.html#method.new), use [`IncomingConnection::context_mut()`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.IncomingConnection.html#method.context_mut) to fill them in.
+#[derive(Debug, Default)]
+pub struct AuthContext {
+    peer_addr: Option<SocketAddr>,
+    local_addr: Option<SocketAddr>,
+    listener_id: Option<usize>,
+    methods: Vec<Method>,
+    extensions: Extensions,
+}
+
+impl AuthContext {
+    /// Create an empty `AuthContext`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the remote address of the connection, if known.
+    #[inline]
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Sets the remote address of the connection.
+    #[inline]
+    pub fn set_peer_addr(&mut self, addr: Option<SocketAddr>) {
+        self.peer_addr = addr;
+    }
+
+    /// Returns the local address of the connection, if known.
+    #[inline]
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// Sets the local address of the connection.
+    #[inline]
+    pub fn set_local_addr(&mut self, addr: Option<SocketAddr>) {
+        self.local_addr = addr;
+    }
+
+    /// Returns the ID of the listener that accepted the connection, if any.
+    ///
+    /// See [`Server::set_listener_id()`](https://docs.rs/socks5-server/latest/socks5_server/struct.Server.html#method.set_listener_id).
+    #[inline]
+    pub fn listener_id(&self) -> Option<usize> {
+        self.listener_id
+    }
+
+    /// Sets the ID of the listener that accepted the connection.
+    #[inline]
+    pub fn set_listener_id(&mut self, id: Option<usize>) {
+        self.listener_id = id;
+    }
+
+    /// Returns the handshake methods offered by the client.
+    ///
+    /// This is empty until the handshake request from the client has been read.
+    #[inline]
+    pub fn methods(&self) -> &[Method] {
+        &self.methods
+    }
+
+    #[inline]
+    pub(crate) fn set_methods(&mut self, methods: Vec<Method>) {
+        self.methods = methods;
+    }
+
+    /// Returns a reference to the extensions of the connection.
+    #[inline]
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    /// Returns a mutable reference to the extensions of the connection.
+    #[inline]
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+}
+
+/// A type map storing per-connection data.
+///
+/// At most one value of each type can be stored.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::auth::Extensions;
+///
+/// struct UserId(u32);
+///
+/// let mut ext = Extensions::new();
+/// ext.insert(UserId(42));
+///
+/// assert_eq!(ext.get::<UserId>().map(|id| id.0), Some(42));
+/// ```
+#[derive(Default)]
+pub struct Extensions {
+    map: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    /// Create an empty `Extensions`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert a value, returning the previous value of the same type if there was one.
+    pub fn insert<V>(&mut self, val: V) -> Option<V>
+    where
+        V: Any + Send + Sync,
+    {
+        self.map
+            .insert(TypeId::of::<V>(), Box::new(val))
+            .and_then(|prev| prev.downcast().ok().map(|prev| *prev))
+    }
+
+    /// Get a reference to the value of type `V`.
+    pub fn get<V>(&self) -> Option<&V>
+    where
+        V: Any + Send + Sync,
+    {
+        self.map
+            .get(&TypeId::of::<V>())
+            .and_then(|val| val.downcast_ref())
+    }
+
+    /// Get a mutable reference to the value of type `V`.
+    pub fn get_mut<V>(&mut self) -> Option<&mut V>
+    where
+        V: Any + Send + Sync,
+    {
+        self.map
+            .get_mut(&TypeId::of::<V>())
+            .and_then(|val| val.downcast_mut())
+    }
+
+    /// Remove the value of type `V`, returning it if there was one.
+    pub fn remove<V>(&mut self) -> Option<V>
+    where
+        V: Any + Send + Sync,
+    {
+        self.map
+            .remove(&TypeId::of::<V>())
+            .and_then(|val| val.downcast().ok().map(|val| *val))
+    }
+
+    /// Returns the number of stored values.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Returns `true` if no value is stored.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Remove all stored values.
+    #[inline]
+    pub fn clear(&mut self) {
+        self.map.clear();
+    }
+}
+
+impl Debug for Extensions {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("Extensions")
+            .field("len", &self.map.len())
+            .finish()
+    }
+}
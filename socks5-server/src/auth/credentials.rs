@@ -0,0 +1,756 @@
+use super::{Auth, AuthContext};
+use crate::connection::BufferedStream;
+use argon2::{Argon2, PasswordHash as PhcString, PasswordVerifier};
+use async_trait::async_trait;
+use blake2::{Blake2b512, Digest};
+use pwhash::{bcrypt, sha256_crypt, sha512_crypt};
+use socks5_proto::handshake::{
+    password::{Error as PasswordError, Request as PasswordRequest, Response as PasswordResponse},
+    Method,
+};
+use std::{
+    collections::HashMap,
+    fmt::{Debug, Formatter, Result as FmtResult},
+    io::Error as IoError,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, SystemTime},
+};
+use subtle::ConstantTimeEq;
+use thiserror::Error;
+use tokio::{
+    fs,
+    io::{AsyncRead, AsyncWrite},
+    sync::Mutex as AsyncMutex,
+    task::{self, JoinHandle},
+    time::{self, MissedTickBehavior},
+};
+
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+
+/// A stored password to check RFC 1929 passwords against.
+///
+/// Hashes can be parsed from their crypt / PHC string representation with [`parse()`](https://docs.rs/socks5-server/latest/socks5_server/auth/enum.PasswordHash.html#method.parse). All comparisons are done in constant time.
+///
+/// Note that the `Debug` output never contains the password or the hash.
+#[derive(Clone)]
+pub enum PasswordHash {
+    /// A plaintext password.
+    Plain(Vec<u8>),
+    /// A bcrypt hash (`$2a$`, `$2b$` or `$2y$`).
+    Bcrypt(String),
+    /// An Argon2 hash in the PHC string format (`$argon2id$`, `$argon2i$` or `$argon2d$`).
+    Argon2(String),
+    /// A SHA-crypt hash (`$5$` for SHA-256, `$6$` for SHA-512).
+    ShaCrypt(String),
+}
+
+impl PasswordHash {
+    /// Parses a bcrypt, Argon2 or SHA-crypt hash, detecting the algorithm by its prefix.
+    ///
+    /// Plaintext passwords are never detected. Use [`PasswordHash::Plain`](https://docs.rs/socks5-server/latest/socks5_server/auth/enum.PasswordHash.html#variant.Plain) instead.
+    pub fn parse(hash: &str) -> Result<Self, UnsupportedHash> {
+        if ["$2a$", "$2b$", "$2y$"].iter().any(|p| hash.starts_with(p)) {
+            Ok(Self::Bcrypt(hash.to_owned()))
+        } else if hash.starts_with("$argon2") {
+            PhcString::new(hash).map_err(|_| UnsupportedHash)?;
+            Ok(Self::Argon2(hash.to_owned()))
+        } else if hash.starts_with("$5$") || hash.starts_with("$6$") {
+            Ok(Self::ShaCrypt(hash.to_owned()))
+        } else {
+            Err(UnsupportedHash)
+        }
+    }
+
+    /// Checks a password against this hash.
+    ///
+    /// Verifying a bcrypt, Argon2 or SHA-crypt hash is intentionally slow. Avoid calling this directly in an async context.
+    pub fn verify(&self, password: &[u8]) -> bool {
+        match self {
+            Self::Plain(plain) => Blake2b512::digest(plain)
+                .ct_eq(&Blake2b512::digest(password))
+                .into(),
+            Self::Bcrypt(hash) => bcrypt::verify(password, hash),
+            Self::Argon2(hash) => PhcString::new(hash)
+                .map(|hash| Argon2::default().verify_password(password, &hash).is_ok())
+                .unwrap_or(false),
+            Self::ShaCrypt(hash) if hash.starts_with("$5$") => sha256_crypt::verify(password, hash),
+            Self::ShaCrypt(hash) => sha512_crypt::verify(password, hash),
+        }
+    }
+}
+
+impl FromStr for PasswordHash {
+    type Err = UnsupportedHash;
+
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+impl Debug for PasswordHash {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        let kind = match self {
+            Self::Plain(_) => "Plain",
+            Self::Bcrypt(_) => "Bcrypt",
+            Self::Argon2(_) => "Argon2",
+            Self::ShaCrypt(_) => "ShaCrypt",
+        };
+
+        f.debug_tuple(kind).field(&"..").finish()
+    }
+}
+
+/// The error returned when parsing a password hash of an unknown or malformed format.
+#[derive(Clone, Copy, Debug, Error)]
+#[error("Unsupported password hash format")]
+pub struct UnsupportedHash;
+
+/// A stored password alongside the identity of its user.
+#[derive(Clone, Debug)]
+pub struct Credential<I> {
+    hash: PasswordHash,
+    identity: I,
+}
+
+impl<I> Credential<I> {
+    /// Create a new `Credential`.
+    #[inline]
+    pub fn new(hash: PasswordHash, identity: I) -> Self {
+        Self { hash, identity }
+    }
+
+    /// Returns the stored password.
+    #[inline]
+    pub fn hash(&self) -> &PasswordHash {
+        &self.hash
+    }
+
+    /// Returns the identity of the user.
+    #[inline]
+    pub fn identity(&self) -> &I {
+        &self.identity
+    }
+
+    /// Consumes the `Credential`, returning the stored password and the identity.
+    #[inline]
+    pub fn into_parts(self) -> (PasswordHash, I) {
+        (self.hash, self.identity)
+    }
+}
+
+/// A source of credentials for [`StoredPassword`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.StoredPassword.html).
+///
+/// Implement this trait to look users up in a database or a remote service. Associate type `Identity` is what a successful authentication yields, e.g. a user ID.
+///
+/// # Example
+///
+/// ```rust
+/// use async_trait::async_trait;
+/// use socks5_server::auth::{Credential, CredentialStore, PasswordHash};
+/// use std::io::Error;
+///
+/// struct Database;
+///
+/// #[async_trait]
+/// impl CredentialStore for Database {
+///     type Identity = u64;
+///
+///     async fn lookup(&self, username: &[u8]) -> Result<Option<Credential<u64>>, Error> {
+///         // query the database
+///         let hash = PasswordHash::parse("$2b$05$bvIG6Nmid91Mu9RcmmWZfO5HJIMCT8riNW0hEp8f6/FuA2/mHZFpe").unwrap();
+///         Ok(Some(Credential::new(hash, 42)))
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait CredentialStore {
+    type Identity;
+
+    /// Looks up the credential of a user. `Ok(None)` is returned if the user does not exist.
+    async fn lookup(&self, username: &[u8]) -> Result<Option<Credential<Self::Identity>>, IoError>;
+}
+
+#[async_trait]
+impl<S> CredentialStore for Arc<S>
+where
+    S: CredentialStore + Send + Sync + ?Sized,
+{
+    type Identity = S::Identity;
+
+    #[inline]
+    async fn lookup(&self, username: &[u8]) -> Result<Option<Credential<Self::Identity>>, IoError> {
+        S::lookup(self, username).await
+    }
+}
+
+/// An in-memory credential store.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::auth::{Credential, MemoryStore, PasswordHash};
+///
+/// let mut store = MemoryStore::new();
+/// store.insert(
+///     b"alice".to_vec(),
+///     Credential::new(PasswordHash::Plain(b"secret".to_vec()), "alice"),
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct MemoryStore<I> {
+    users: HashMap<Vec<u8>, Credential<I>>,
+}
+
+impl<I> MemoryStore<I> {
+    /// Create an empty `MemoryStore`.
+    #[allow(clippy::new_without_default)]
+    #[inline]
+    pub fn new() -> Self {
+        Self {
+            users: HashMap::new(),
+        }
+    }
+
+    /// Inserts a user, returning the previous credential of the user if there was one.
+    #[inline]
+    pub fn insert(
+        &mut self,
+        username: Vec<u8>,
+        credential: Credential<I>,
+    ) -> Option<Credential<I>> {
+        self.users.insert(username, credential)
+    }
+
+    /// Removes a user, returning its credential if it existed.
+    #[inline]
+    pub fn remove(&mut self, username: &[u8]) -> Option<Credential<I>> {
+        self.users.remove(username)
+    }
+
+    /// Returns the credential of a user.
+    #[inline]
+    pub fn get(&self, username: &[u8]) -> Option<&Credential<I>> {
+        self.users.get(username)
+    }
+
+    /// Returns the number of users.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.users.len()
+    }
+
+    /// Returns whether the store contains no user.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.users.is_empty()
+    }
+}
+
+impl MemoryStore<String> {
+    /// Parses the content of an htpasswd file. The identity of each user is its username.
+    ///
+    /// Each line is a `username:hash` pair. Empty lines and lines starting with `#` are skipped. Hashes must be bcrypt, Argon2 or SHA-crypt. See [`PasswordHash::parse()`](https://docs.rs/socks5-server/latest/socks5_server/auth/enum.PasswordHash.html#method.parse).
+    pub fn from_htpasswd(content: &str) -> Result<Self, HtpasswdError> {
+        let mut store = Self::new();
+
+        for (idx, line) in content.lines().enumerate() {
+            let line = line.trim_end_matches('\r');
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (username, hash) = match line.split_once(':') {
+                Some((username, hash)) if !username.is_empty() => (username, hash),
+                _ => return Err(HtpasswdError::InvalidLine { line: idx + 1 }),
+            };
+
+            let hash = PasswordHash::parse(hash)
+                .map_err(|_| HtpasswdError::UnsupportedHash { line: idx + 1 })?;
+
+            store.insert(
+                username.as_bytes().to_vec(),
+                Credential::new(hash, username.to_owned()),
+            );
+        }
+
+        Ok(store)
+    }
+}
+
+impl<I> FromIterator<(Vec<u8>, Credential<I>)> for MemoryStore<I> {
+    #[inline]
+    fn from_iter<It: IntoIterator<Item = (Vec<u8>, Credential<I>)>>(iter: It) -> Self {
+        Self {
+            users: HashMap::from_iter(iter),
+        }
+    }
+}
+
+#[async_trait]
+impl<I> CredentialStore for MemoryStore<I>
+where
+    I: Clone + Send + Sync,
+{
+    type Identity = I;
+
+    #[inline]
+    async fn lookup(&self, username: &[u8]) -> Result<Option<Credential<I>>, IoError> {
+        Ok(self.users.get(username).cloned())
+    }
+}
+
+/// A credential store loaded from an htpasswd file, which can be reloaded at runtime.
+///
+/// The identity of each user is its username. See [`MemoryStore::from_htpasswd()`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.MemoryStore.html#method.from_htpasswd) for the supported format.
+///
+/// The file can be reloaded with [`reload()`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.HtpasswdStore.html#method.reload), polled for changes with [`watch()`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.HtpasswdStore.html#method.watch), or reloaded on `SIGHUP` with [`reload_on_sighup()`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.HtpasswdStore.html#method.reload_on_sighup). The whole set of users is swapped atomically, so a lookup sees either the old or the new file, never a mix of both. If the new file fails to load, the previous users are kept. Reloading only affects authentications performed afterwards, connections that are already established are left untouched.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::auth::{HtpasswdStore, StoredPassword};
+/// use std::{sync::Arc, time::Duration};
+///
+/// async fn auth() {
+///     let store = Arc::new(HtpasswdStore::open("/etc/socks5/htpasswd").await.unwrap());
+///     store.watch(Duration::from_secs(5));
+///
+///     let auth = StoredPassword::new(store);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct HtpasswdStore {
+    path: PathBuf,
+    users: RwLock<Arc<MemoryStore<String>>>,
+    modified: Mutex<Option<SystemTime>>,
+    reloading: AsyncMutex<()>,
+}
+
+impl HtpasswdStore {
+    /// Loads an htpasswd file.
+    pub async fn open<P: AsRef<Path>>(path: P) -> Result<Self, HtpasswdError> {
+        let path = path.as_ref().to_path_buf();
+        let (users, modified) = Self::load(&path).await?;
+
+        Ok(Self {
+            path,
+            users: RwLock::new(Arc::new(users)),
+            modified: Mutex::new(modified),
+            reloading: AsyncMutex::new(()),
+        })
+    }
+
+    /// Returns the path of the htpasswd file.
+    #[inline]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns a snapshot of the currently loaded users.
+    #[inline]
+    pub fn users(&self) -> Arc<MemoryStore<String>> {
+        self.users.read().unwrap().clone()
+    }
+
+    /// Reloads the htpasswd file and swaps the set of users.
+    ///
+    /// On error, the previously loaded users are kept. Concurrent reloads are serialized, so an older read of the file never replaces a newer one.
+    pub async fn reload(&self) -> Result<(), HtpasswdError> {
+        let _reloading = self.reloading.lock().await;
+        self.swap().await
+    }
+
+    /// Reloads the htpasswd file if its modification time changed since it was last loaded. Returns whether the file was reloaded.
+    pub async fn reload_if_modified(&self) -> Result<bool, HtpasswdError> {
+        let _reloading = self.reloading.lock().await;
+        let modified = fs::metadata(&self.path).await?.modified().ok();
+
+        if modified.is_some() && modified == *self.modified.lock().unwrap() {
+            return Ok(false);
+        }
+
+        self.swap().await?;
+        Ok(true)
+    }
+
+    /// Spawns a task checking the htpasswd file for changes every `interval`, reloading it when modified.
+    ///
+    /// Errors are ignored and the previous users are kept. A file that is malformed or changes while being read is not loaded, but a file truncated at a line boundary parses successfully and drops the users after the cut, so update the file atomically, i.e. write a temporary file and rename it over the htpasswd file. Call [`reload_if_modified()`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.HtpasswdStore.html#method.reload_if_modified) in your own loop to handle them instead. The task stops once the store is dropped.
+    pub fn watch(self: &Arc<Self>, interval: Duration) -> JoinHandle<()> {
+        let store = Arc::downgrade(self);
+
+        task::spawn(async move {
+            let mut interval = time::interval(interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            interval.tick().await;
+
+            loop {
+                interval.tick().await;
+
+                let Some(store) = store.upgrade() else {
+                    break;
+                };
+
+                let _ = store.reload_if_modified().await;
+            }
+        })
+    }
+
+    /// Spawns a task reloading the htpasswd file every time the process receives `SIGHUP`.
+    ///
+    /// Errors are ignored and the previous users are kept. The task stops once the store is dropped.
+    #[cfg(unix)]
+    pub fn reload_on_sighup(self: &Arc<Self>) -> Result<JoinHandle<()>, IoError> {
+        let mut sighup = signal(SignalKind::hangup())?;
+        let store = Arc::downgrade(self);
+
+        Ok(task::spawn(async move {
+            while sighup.recv().await.is_some() {
+                let Some(store) = store.upgrade() else {
+                    break;
+                };
+
+                let _ = store.reload().await;
+            }
+        }))
+    }
+
+    async fn swap(&self) -> Result<(), HtpasswdError> {
+        let (users, modified) = Self::load(&self.path).await?;
+        *self.users.write().unwrap() = Arc::new(users);
+        *self.modified.lock().unwrap() = modified;
+        Ok(())
+    }
+
+    async fn load(path: &Path) -> Result<(MemoryStore<String>, Option<SystemTime>), HtpasswdError> {
+        let modified = fs::metadata(path).await?.modified().ok();
+        let content = fs::read_to_string(path).await?;
+        let meta = fs::metadata(path).await?;
+
+        if meta.modified().ok() != modified || meta.len() != content.len() as u64 {
+            return Err(HtpasswdError::Modified);
+        }
+
+        let users = MemoryStore::from_htpasswd(&content)?;
+        Ok((users, modified))
+    }
+}
+
+#[async_trait]
+impl CredentialStore for HtpasswdStore {
+    type Identity = String;
+
+    #[inline]
+    async fn lookup(&self, username: &[u8]) -> Result<Option<Credential<String>>, IoError> {
+        self.users().lookup(username).await
+    }
+}
+
+/// Errors may occur when loading an htpasswd file
+#[derive(Debug, Error)]
+pub enum HtpasswdError {
+    #[error(transparent)]
+    Io(#[from] IoError),
+
+    #[error("Invalid htpasswd entry at line {line}")]
+    InvalidLine { line: usize },
+
+    #[error("Unsupported password hash format at line {line}")]
+    UnsupportedHash { line: usize },
+
+    #[error("htpasswd file was modified while being read")]
+    Modified,
+}
+
+/// Username and password authentication backed by a [`CredentialStore`](https://docs.rs/socks5-server/latest/socks5_server/auth/trait.CredentialStore.html).
+///
+/// Hashes other than plaintext are verified on tokio's blocking thread pool, so a tokio runtime is required.
+///
+/// The output is `Ok(Some(identity))` if the user is authenticated, `Ok(None)` if the username or the password is wrong, or an error if the sub-negotiation or the lookup failed.
+///
+/// To not reveal which usernames exist through the response time, the password of an unknown user is verified against a dummy hash, a bcrypt hash with cost 10 by default. Set it with [`set_dummy_hash()`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.StoredPassword.html#method.set_dummy_hash) to a hash of the same algorithm and cost as the stored ones.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::auth::{HtpasswdStore, StoredPassword};
+///
+/// async fn auth() {
+///     let store = HtpasswdStore::open("/etc/socks5/htpasswd").await.unwrap();
+///     let auth = StoredPassword::new(store);
+/// }
+/// ```
+pub struct StoredPassword<S> {
+    store: S,
+    dummy: PasswordHash,
+}
+
+impl<S> StoredPassword<S> {
+    const DUMMY_HASH: &'static str = "$2b$10$PrRX0Rm.w82wh158U5vMreAm38GOIkY2uhjH.ZRRVEDuZ4hEn4FxK";
+
+    /// Create a new `StoredPassword` authentication adaptor.
+    #[inline]
+    pub fn new(store: S) -> Self {
+        Self {
+            store,
+            dummy: PasswordHash::Bcrypt(Self::DUMMY_HASH.to_owned()),
+        }
+    }
+
+    /// Sets the hash the password of an unknown user is verified against. Its algorithm and cost should match the stored hashes.
+    #[inline]
+    pub fn set_dummy_hash(&mut self, hash: PasswordHash) {
+        self.dummy = hash;
+    }
+
+    /// Returns the credential store.
+    #[inline]
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+}
+
+#[async_trait]
+impl<T, S> Auth<T> for StoredPassword<S>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+    S: CredentialStore + Send + Sync,
+    S::Identity: Send,
+{
+    type Output = Result<Option<S::Identity>, PasswordError>;
+    type Stream = T;
+
+    fn as_handshake_method(&self) -> Method {
+        Method::PASSWORD
+    }
+
+    async fn execute(&self, stream: &mut BufferedStream<T>, _: &mut AuthContext) -> Self::Output {
+        let req = stream.read_message(PasswordRequest::decode).await?;
+
+        let identity = match self.store.lookup(&req.username).await? {
+            Some(credential) => {
+                let (hash, identity) = credential.into_parts();
+                verify(hash, req.password).await?.then_some(identity)
+            }
+            None => {
+                // spend as long as for a known user, so the response time does not reveal whether the user exists
+                verify(self.dummy.clone(), req.password).await?;
+                None
+            }
+        };
+
+        let resp = PasswordResponse::new(identity.is_some());
+        resp.write_to(stream).await?;
+        Ok(identity)
+    }
+
+    #[inline]
+    fn encapsulate(&self, stream: BufferedStream<T>, _: &mut AuthContext) -> BufferedStream<T> {
+        stream
+    }
+}
+
+/// Verifies a password, on the blocking thread pool unless the hash is plaintext.
+async fn verify(hash: PasswordHash, password: Vec<u8>) -> Result<bool, IoError> {
+    if let PasswordHash::Plain(_) = hash {
+        return Ok(hash.verify(&password));
+    }
+
+    task::spawn_blocking(move || hash.verify(&password))
+        .await
+        .map_err(IoError::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+
+    const BCRYPT: &str = "$2y$04$saltsaltsaltsaltsaltsOh6eq3EFJkUrLtlyPfsES9Dguki/0EY.";
+    const ARGON2ID: &str =
+        "$argon2id$v=19$m=64,t=1,p=1$c29tZXNhbHRzYWx0$Gh8sW4trBHZt8WsaHG4N08sGiShWjlZo2YT8aGT2WPg";
+    const SHA512_CRYPT: &str = "$6$saltsalt$TVLlQcbpFVof5W3Yz4DTP6gRstiNuHwwTt6GLc1E5n0U0aDehy0S5knV8wiOQSpT0Y77vwPZN.Pq.H91p5hVO1";
+
+    #[test]
+    fn plain_verify() {
+        let hash = PasswordHash::Plain(b"secret".to_vec());
+        assert!(hash.verify(b"secret"));
+        assert!(!hash.verify(b"secre"));
+        assert!(!hash.verify(b"secret\0"));
+        assert!(!hash.verify(b""));
+    }
+
+    #[test]
+    fn dummy_hash_is_bcrypt() {
+        let dummy = StoredPassword::<MemoryStore<()>>::DUMMY_HASH;
+        assert!(matches!(
+            PasswordHash::parse(dummy),
+            Ok(PasswordHash::Bcrypt(_))
+        ));
+        assert!(!PasswordHash::parse(dummy).unwrap().verify(b"secret"));
+    }
+
+    #[test]
+    fn hash_verify() {
+        for (hash, kind) in [
+            (BCRYPT, "Bcrypt"),
+            (ARGON2ID, "Argon2"),
+            (SHA512_CRYPT, "ShaCrypt"),
+        ] {
+            let hash = PasswordHash::parse(hash).unwrap();
+            assert_eq!(format!("{hash:?}"), format!("{kind}(\"..\")"));
+            assert!(hash.verify(b"secret"));
+            assert!(!hash.verify(b"Secret"));
+            assert!(!hash.verify(b""));
+        }
+
+        assert!(PasswordHash::parse("secret").is_err());
+        assert!(PasswordHash::parse("$1$saltsalt$md5crypt").is_err());
+    }
+
+    #[test]
+    fn from_htpasswd() {
+        let content =
+            format!("# users\r\nalice:{BCRYPT}\r\n\nbob:{ARGON2ID}\ncarol:{SHA512_CRYPT}\n");
+        let store = MemoryStore::from_htpasswd(&content).unwrap();
+
+        assert_eq!(store.len(), 3);
+        assert_eq!(store.get(b"alice").unwrap().identity(), "alice");
+        assert!(store.get(b"alice").unwrap().hash().verify(b"secret"));
+        assert!(store.get(b"carol").unwrap().hash().verify(b"secret"));
+        assert!(store.get(b"dave").is_none());
+
+        assert!(matches!(
+            MemoryStore::from_htpasswd(&format!("alice:{BCRYPT}\n:{BCRYPT}")),
+            Err(HtpasswdError::InvalidLine { line: 2 })
+        ));
+        assert!(matches!(
+            MemoryStore::from_htpasswd("\n\nalice"),
+            Err(HtpasswdError::InvalidLine { line: 3 })
+        ));
+        assert!(matches!(
+            MemoryStore::from_htpasswd("alice:secret"),
+            Err(HtpasswdError::UnsupportedHash { line: 1 })
+        ));
+    }
+
+    async fn execute(
+        auth: &StoredPassword<MemoryStore<String>>,
+        username: &[u8],
+        password: &[u8],
+    ) -> (Option<String>, [u8; 2]) {
+        let (mut client, server) = io::duplex(1024);
+        PasswordRequest::new(username.to_vec(), password.to_vec())
+            .write_to(&mut client)
+            .await
+            .unwrap();
+
+        let mut stream = BufferedStream::new(server);
+        let identity = Auth::execute(auth, &mut stream, &mut AuthContext::new())
+            .await
+            .unwrap();
+
+        let mut resp = [0; 2];
+        client.read_exact(&mut resp).await.unwrap();
+        client.shutdown().await.unwrap();
+        (identity, resp)
+    }
+
+    #[tokio::test]
+    async fn stored_password() {
+        let store = MemoryStore::from_htpasswd(&format!("alice:{BCRYPT}\nbob:{ARGON2ID}")).unwrap();
+        let mut auth = StoredPassword::new(store);
+        auth.set_dummy_hash(PasswordHash::parse(BCRYPT).unwrap());
+
+        assert_eq!(
+            execute(&auth, b"alice", b"secret").await,
+            (Some("alice".to_owned()), [0x01, 0x00])
+        );
+        assert_eq!(
+            execute(&auth, b"bob", b"secret").await,
+            (Some("bob".to_owned()), [0x01, 0x00])
+        );
+        assert_eq!(
+            execute(&auth, b"alice", b"wrong").await,
+            (None, [0x01, 0xff])
+        );
+        assert_eq!(
+            execute(&auth, b"mallory", b"secret").await,
+            (None, [0x01, 0xff])
+        );
+    }
+
+    fn write_htpasswd(path: &Path, content: &str, modified: SystemTime) {
+        std::fs::write(path, content).unwrap();
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("socks5-server-{}-{name}", std::process::id()))
+    }
+
+    #[tokio::test]
+    async fn htpasswd_reload() {
+        let path = temp_path("reload");
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        write_htpasswd(&path, &format!("alice:{BCRYPT}"), t0);
+
+        let store = HtpasswdStore::open(&path).await.unwrap();
+        assert_eq!(store.users().len(), 1);
+
+        write_htpasswd(&path, &format!("alice:{BCRYPT}\nbob:{BCRYPT}"), t0);
+        store.reload().await.unwrap();
+        assert_eq!(store.users().len(), 2);
+
+        write_htpasswd(&path, "garbage", t0);
+        assert!(matches!(
+            store.reload().await,
+            Err(HtpasswdError::InvalidLine { line: 1 })
+        ));
+        assert_eq!(store.users().len(), 2);
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(store.reload().await, Err(HtpasswdError::Io(_))));
+        assert!(store.lookup(b"bob").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn htpasswd_reload_if_modified() {
+        let path = temp_path("reload-if-modified");
+        let t0 = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        write_htpasswd(&path, &format!("alice:{BCRYPT}"), t0);
+
+        let store = HtpasswdStore::open(&path).await.unwrap();
+        assert!(!store.reload_if_modified().await.unwrap());
+
+        // same modification time, not reloaded
+        write_htpasswd(&path, &format!("alice:{BCRYPT}\nbob:{BCRYPT}"), t0);
+        assert!(!store.reload_if_modified().await.unwrap());
+        assert_eq!(store.users().len(), 1);
+
+        let t1 = t0 + Duration::from_secs(1);
+        write_htpasswd(&path, &format!("alice:{BCRYPT}\nbob:{BCRYPT}"), t1);
+        assert!(store.reload_if_modified().await.unwrap());
+        assert_eq!(store.users().len(), 2);
+        assert!(!store.reload_if_modified().await.unwrap());
+
+        // a failed reload is retried on the next call
+        let t2 = t1 + Duration::from_secs(1);
+        write_htpasswd(&path, "garbage", t2);
+        assert!(store.reload_if_modified().await.is_err());
+        assert!(store.reload_if_modified().await.is_err());
+        assert_eq!(store.users().len(), 2);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,613 @@
+use super::{Auth, AuthContext};
+use crate::connection::BufferedStream;
+use async_trait::async_trait;
+use socks5_proto::handshake::{
+    password::{Error as PasswordError, Request as PasswordRequest, Response as PasswordResponse},
+    Method,
+};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    time,
+};
+
+/// The output of a username / password authentication adaptor, as seen by [`Guarded`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.Guarded.html).
+pub trait PasswordOutput {
+    /// Returns `Some(true)` if the user is authenticated, `Some(false)` if the credentials are rejected, or `None` if the sub-negotiation failed.
+    fn is_authenticated(&self) -> Option<bool>;
+}
+
+impl PasswordOutput for Result<bool, PasswordError> {
+    #[inline]
+    fn is_authenticated(&self) -> Option<bool> {
+        self.as_ref().ok().copied()
+    }
+}
+
+impl<I> PasswordOutput for Result<Option<I>, PasswordError> {
+    #[inline]
+    fn is_authenticated(&self) -> Option<bool> {
+        self.as_ref().ok().map(Option::is_some)
+    }
+}
+
+/// Thresholds of a [`Lockout`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.Lockout.html).
+#[derive(Clone, Copy, Debug)]
+pub struct LockoutConfig {
+    /// Failures within `failure_window` before a client IP or a username is banned. Defaults to 5.
+    pub max_failures: u32,
+    /// Period after the last failure in which failures are counted. Defaults to 10 minutes.
+    pub failure_window: Duration,
+    /// How long a ban lasts. Defaults to 15 minutes.
+    pub ban_duration: Duration,
+    /// Delay before evaluating an attempt after the first failure, doubled on every further failure. Defaults to 500 milliseconds.
+    pub base_delay: Duration,
+    /// Upper bound of the delay. Defaults to 10 seconds.
+    pub max_delay: Duration,
+    /// Maximum number of client IPs and usernames tracked at once. When full, the least recently failed entry that is not banned is forgotten. Defaults to 65536.
+    pub max_records: usize,
+}
+
+impl Default for LockoutConfig {
+    fn default() -> Self {
+        Self {
+            max_failures: 5,
+            failure_window: Duration::from_secs(600),
+            ban_duration: Duration::from_secs(900),
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(10),
+            max_records: 65536,
+        }
+    }
+}
+
+/// What a failure counter or a ban applies to.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum BanKey {
+    Ip(IpAddr),
+    Username(Vec<u8>),
+}
+
+#[derive(Debug)]
+struct Record {
+    failures: u32,
+    pending: u32,
+    last_failure: Instant,
+    banned_until: Option<Instant>,
+}
+
+impl Record {
+    fn new(now: Instant) -> Self {
+        Self {
+            failures: 0,
+            pending: 0,
+            last_failure: now,
+            banned_until: None,
+        }
+    }
+
+    /// Failures within the window, counting the attempts in flight as failed.
+    fn attempts(&self, now: Instant, window: Duration) -> u32 {
+        let failures = if now.duration_since(self.last_failure) < window {
+            self.failures
+        } else {
+            0
+        };
+
+        failures.saturating_add(self.pending)
+    }
+
+    fn is_banned(&self, now: Instant) -> bool {
+        self.banned_until.is_some_and(|until| until > now)
+    }
+
+    fn is_stale(&self, now: Instant, window: Duration) -> bool {
+        !self.is_banned(now) && self.pending == 0 && now.duration_since(self.last_failure) >= window
+    }
+}
+
+/// Failure counters and temporary bans, shared by [`Guarded`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.Guarded.html) adaptors.
+///
+/// Failed attempts are tracked per client IP and per username. Every failure increases the delay before the next attempt from the same IP or for the same username is evaluated, and reaching [`max_failures`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.LockoutConfig.html#structfield.max_failures) bans it for [`ban_duration`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.LockoutConfig.html#structfield.ban_duration). A successful authentication resets the counters.
+///
+/// An attempt counts as failed from the moment it passes [`check()`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.Lockout.html#method.check) until its result is recorded, so attempts made in parallel cannot get past the threshold. At most [`max_records`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.LockoutConfig.html#structfield.max_records) client IPs and usernames are tracked.
+///
+/// The current bans can be inspected with [`bans()`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.Lockout.html#method.bans) and lifted with [`unban()`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.Lockout.html#method.unban) or [`clear()`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.Lockout.html#method.clear).
+#[derive(Debug)]
+pub struct Lockout {
+    config: LockoutConfig,
+    records: Mutex<Records>,
+}
+
+#[derive(Debug)]
+struct Records {
+    map: HashMap<BanKey, Record>,
+    last_purge: Instant,
+}
+
+impl Records {
+    /// Returns the record of a key, creating it if the table is not full of banned or in-use records.
+    fn get_or_insert(
+        &mut self,
+        key: &BanKey,
+        now: Instant,
+        config: &LockoutConfig,
+    ) -> Option<&mut Record> {
+        if !self.map.contains_key(key) {
+            if self.map.len() >= config.max_records
+                || now.duration_since(self.last_purge) >= config.failure_window
+            {
+                let window = config.failure_window;
+                self.map.retain(|_, record| !record.is_stale(now, window));
+                self.last_purge = now;
+            }
+
+            if self.map.len() >= config.max_records {
+                let oldest = self
+                    .map
+                    .iter()
+                    .filter(|(_, record)| !record.is_banned(now) && record.pending == 0)
+                    .min_by_key(|(_, record)| record.last_failure)
+                    .map(|(key, _)| key.clone())?;
+
+                self.map.remove(&oldest);
+            }
+
+            self.map.insert(key.clone(), Record::new(now));
+        }
+
+        self.map.get_mut(key)
+    }
+}
+
+impl Lockout {
+    /// Create a new `Lockout` with the given thresholds.
+    pub fn new(config: LockoutConfig) -> Self {
+        Self {
+            config,
+            records: Mutex::new(Records {
+                map: HashMap::new(),
+                last_purge: Instant::now(),
+            }),
+        }
+    }
+
+    /// Returns the thresholds of this `Lockout`.
+    #[inline]
+    pub fn config(&self) -> &LockoutConfig {
+        &self.config
+    }
+
+    /// Checks the keys of an attempt and reserves it. Returns the delay to apply before evaluating it, or when to retry if any key is banned or has too many attempts in flight.
+    ///
+    /// A reserved attempt counts as failed until [`record_success()`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.Lockout.html#method.record_success), [`record_failure()`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.Lockout.html#method.record_failure) or [`release()`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.Lockout.html#method.release) is called with the same keys.
+    #[inline]
+    pub fn check(&self, keys: &[BanKey]) -> Result<Duration, Instant> {
+        self.check_at(keys, Instant::now())
+    }
+
+    fn check_at(&self, keys: &[BanKey], now: Instant) -> Result<Duration, Instant> {
+        let mut records = self.records.lock().unwrap();
+        let mut failures = 0;
+
+        for key in keys {
+            if let Some(record) = records.map.get(key) {
+                if let Some(until) = record.banned_until.filter(|until| *until > now) {
+                    return Err(until);
+                }
+
+                failures = failures.max(record.attempts(now, self.config.failure_window));
+            }
+        }
+
+        // the attempts in flight may reach the threshold, the client has to wait for their results
+        if failures >= self.config.max_failures {
+            return Err(now + self.delay(failures));
+        }
+
+        for key in keys {
+            if let Some(record) = records.get_or_insert(key, now, &self.config) {
+                record.pending += 1;
+            }
+        }
+
+        Ok(self.delay(failures))
+    }
+
+    /// Records a failed attempt for the keys, banning those reaching the threshold.
+    #[inline]
+    pub fn record_failure(&self, keys: &[BanKey]) {
+        self.record_failure_at(keys, Instant::now());
+    }
+
+    fn record_failure_at(&self, keys: &[BanKey], now: Instant) {
+        let mut records = self.records.lock().unwrap();
+
+        for key in keys {
+            let Some(record) = records.get_or_insert(key, now, &self.config) else {
+                continue;
+            };
+
+            if now.duration_since(record.last_failure) >= self.config.failure_window {
+                record.failures = 0;
+            }
+
+            record.pending = record.pending.saturating_sub(1);
+            record.failures += 1;
+            record.last_failure = now;
+
+            if record.failures >= self.config.max_failures {
+                record.failures = 0;
+                record.banned_until = Some(now + self.config.ban_duration);
+            }
+        }
+    }
+
+    /// Records a successful attempt, resetting the failure counters of the keys.
+    pub fn record_success(&self, keys: &[BanKey]) {
+        let mut records = self.records.lock().unwrap();
+
+        for key in keys {
+            if let Some(record) = records.map.get_mut(key) {
+                record.pending = record.pending.saturating_sub(1);
+                record.failures = 0;
+            }
+        }
+    }
+
+    /// Releases an attempt reserved by [`check()`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.Lockout.html#method.check) without recording a result, e.g. when the sub-negotiation failed.
+    pub fn release(&self, keys: &[BanKey]) {
+        let mut records = self.records.lock().unwrap();
+
+        for key in keys {
+            if let Some(record) = records.map.get_mut(key) {
+                record.pending = record.pending.saturating_sub(1);
+            }
+        }
+    }
+
+    /// Bans a key until the given duration elapses.
+    #[inline]
+    pub fn ban(&self, key: BanKey, dur: Duration) {
+        self.ban_at(key, dur, Instant::now());
+    }
+
+    fn ban_at(&self, key: BanKey, dur: Duration, now: Instant) {
+        let mut records = self.records.lock().unwrap();
+
+        let record = records.map.entry(key).or_insert(Record::new(now));
+        record.banned_until = Some(now + dur);
+    }
+
+    /// Lifts the ban and resets the failure counter of a key. Returns whether the key was banned.
+    pub fn unban(&self, key: &BanKey) -> bool {
+        let now = Instant::now();
+        let mut records = self.records.lock().unwrap();
+
+        records
+            .map
+            .remove(key)
+            .and_then(|record| record.banned_until)
+            .is_some_and(|until| until > now)
+    }
+
+    /// Returns the currently banned keys alongside the expiry of their bans.
+    #[inline]
+    pub fn bans(&self) -> Vec<(BanKey, Instant)> {
+        self.bans_at(Instant::now())
+    }
+
+    fn bans_at(&self, now: Instant) -> Vec<(BanKey, Instant)> {
+        let records = self.records.lock().unwrap();
+
+        records
+            .map
+            .iter()
+            .filter_map(|(key, record)| {
+                record
+                    .banned_until
+                    .filter(|until| *until > now)
+                    .map(|until| (key.clone(), until))
+            })
+            .collect()
+    }
+
+    /// Lifts all bans and resets all failure counters.
+    pub fn clear(&self) {
+        self.records.lock().unwrap().map.clear();
+    }
+
+    fn delay(&self, failures: u32) -> Duration {
+        if failures == 0 {
+            return Duration::ZERO;
+        }
+
+        self.config
+            .base_delay
+            .saturating_mul(1 << (failures - 1).min(31))
+            .min(self.config.max_delay)
+    }
+}
+
+impl Default for Lockout {
+    fn default() -> Self {
+        Self::new(LockoutConfig::default())
+    }
+}
+
+/// The error returned by [`Guarded`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.Guarded.html) when the client IP or the username is banned.
+///
+/// The request is rejected without being passed to the wrapped adaptor.
+#[derive(Clone, Copy, Debug, Error)]
+#[error("Client IP or username is temporarily banned")]
+pub struct Banned {
+    /// When the ban expires.
+    pub until: Instant,
+}
+
+/// Brute-force protection for username / password authentication adaptors, e.g. [`Password`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.Password.html).
+///
+/// The username / password request is inspected before the wrapped adaptor handles it. Attempts from a banned client IP or for a banned username are rejected right away, and others are delayed according to previous failures. The result of the wrapped adaptor is then recorded in the shared [`Lockout`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.Lockout.html). The client IP is taken from [`AuthContext::peer_addr()`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.AuthContext.html#method.peer_addr).
+///
+/// The output is the output of the wrapped adaptor, or [`Banned`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.Banned.html) if the attempt was rejected by the lockout.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::auth::{Guarded, Lockout, Password};
+/// use std::sync::Arc;
+///
+/// let lockout = Arc::new(Lockout::default());
+/// let auth = Guarded::new(
+///     Password::new(b"alice".to_vec(), b"secret".to_vec()),
+///     lockout.clone(),
+/// );
+///
+/// // later, e.g. from an admin interface
+/// for (key, until) in lockout.bans() {
+///     lockout.unban(&key);
+/// }
+/// ```
+pub struct Guarded<A> {
+    auth: A,
+    lockout: Arc<Lockout>,
+}
+
+impl<A> Guarded<A> {
+    /// Create a new `Guarded` authentication adaptor.
+    #[inline]
+    pub fn new(auth: A, lockout: Arc<Lockout>) -> Self {
+        Self { auth, lockout }
+    }
+
+    /// Returns the shared lockout state.
+    #[inline]
+    pub fn lockout(&self) -> &Arc<Lockout> {
+        &self.lockout
+    }
+}
+
+#[async_trait]
+impl<T, A> Auth<T> for Guarded<A>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+    A: Auth<T> + Send + Sync,
+    A::Output: PasswordOutput,
+{
+    type Output = Result<A::Output, Banned>;
+    type Stream = A::Stream;
+
+    fn as_handshake_method(&self) -> Method {
+        self.auth.as_handshake_method()
+    }
+
+    fn is_acceptable(&self, ctx: &AuthContext) -> bool {
+        self.auth.is_acceptable(ctx)
+    }
+
+    async fn execute(&self, stream: &mut BufferedStream<T>, ctx: &mut AuthContext) -> Self::Output {
+        // peek at the request without consuming it, errors are left to the wrapped adaptor
+        let Ok(req) = stream
+            .read_message(|buf| PasswordRequest::decode(&mut buf.clone()))
+            .await
+        else {
+            return Ok(self.auth.execute(stream, ctx).await);
+        };
+
+        let mut keys = Vec::with_capacity(2);
+
+        if let Some(addr) = ctx.peer_addr() {
+            keys.push(BanKey::Ip(addr.ip()));
+        }
+
+        keys.push(BanKey::Username(req.username));
+
+        let attempt = match self.lockout.check(&keys) {
+            Ok(delay) => {
+                let attempt = Attempt {
+                    lockout: &self.lockout,
+                    keys: &keys,
+                    recorded: false,
+                };
+
+                if !delay.is_zero() {
+                    time::sleep(delay).await;
+                }
+
+                attempt
+            }
+            Err(until) => {
+                let _ = stream.read_message(PasswordRequest::decode).await;
+                let _ = PasswordResponse::new(false).write_to(stream).await;
+                return Err(Banned { until });
+            }
+        };
+
+        let output = self.auth.execute(stream, ctx).await;
+        attempt.record(output.is_authenticated());
+
+        Ok(output)
+    }
+
+    #[inline]
+    fn encapsulate(
+        &self,
+        stream: BufferedStream<T>,
+        ctx: &mut AuthContext,
+    ) -> BufferedStream<Self::Stream> {
+        self.auth.encapsulate(stream, ctx)
+    }
+}
+
+/// An attempt reserved in a `Lockout`, released if dropped before its result is recorded.
+struct Attempt<'a> {
+    lockout: &'a Lockout,
+    keys: &'a [BanKey],
+    recorded: bool,
+}
+
+impl Attempt<'_> {
+    fn record(mut self, is_authenticated: Option<bool>) {
+        match is_authenticated {
+            Some(true) => self.lockout.record_success(self.keys),
+            Some(false) => self.lockout.record_failure(self.keys),
+            None => self.lockout.release(self.keys),
+        }
+
+        self.recorded = true;
+    }
+}
+
+impl Drop for Attempt<'_> {
+    fn drop(&mut self) {
+        if !self.recorded {
+            self.lockout.release(self.keys);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    fn lockout() -> Lockout {
+        Lockout::new(LockoutConfig {
+            max_failures: 3,
+            failure_window: 200 * MS,
+            ban_duration: 100 * MS,
+            base_delay: 10 * MS,
+            max_delay: 30 * MS,
+            max_records: 16,
+        })
+    }
+
+    fn user(name: &str) -> Vec<BanKey> {
+        vec![BanKey::Username(name.as_bytes().to_vec())]
+    }
+
+    #[test]
+    fn delay_and_ban() {
+        let lockout = lockout();
+        let keys = user("alice");
+        let now = Instant::now();
+
+        for delay in [0, 10, 20] {
+            assert_eq!(lockout.check_at(&keys, now), Ok(delay * MS));
+            lockout.record_failure_at(&keys, now);
+        }
+
+        let until = lockout.check_at(&keys, now).unwrap_err();
+        assert_eq!(until, now + 100 * MS);
+        assert_eq!(lockout.bans_at(now), [(keys[0].clone(), until)]);
+        assert!(lockout.check_at(&keys, now + 99 * MS).is_err());
+
+        assert_eq!(lockout.check_at(&keys, now + 100 * MS), Ok(Duration::ZERO));
+        assert!(lockout.bans_at(now + 100 * MS).is_empty());
+    }
+
+    #[test]
+    fn failures_expire() {
+        let lockout = lockout();
+        let keys = user("alice");
+        let now = Instant::now();
+
+        for _ in 0..2 {
+            lockout.check_at(&keys, now).unwrap();
+            lockout.record_failure_at(&keys, now);
+        }
+
+        assert_eq!(lockout.check_at(&keys, now + 199 * MS), Ok(20 * MS));
+        lockout.release(&keys);
+
+        let later = now + 200 * MS;
+        assert_eq!(lockout.check_at(&keys, later), Ok(Duration::ZERO));
+        lockout.record_failure_at(&keys, later);
+        assert_eq!(lockout.check_at(&keys, later), Ok(10 * MS));
+    }
+
+    #[test]
+    fn success_resets() {
+        let lockout = lockout();
+        let keys = user("alice");
+        let now = Instant::now();
+
+        for _ in 0..2 {
+            lockout.check_at(&keys, now).unwrap();
+            lockout.record_failure_at(&keys, now);
+        }
+
+        lockout.check_at(&keys, now).unwrap();
+        lockout.record_success(&keys);
+        assert_eq!(lockout.check_at(&keys, now), Ok(Duration::ZERO));
+    }
+
+    #[test]
+    fn parallel_attempts() {
+        let lockout = lockout();
+        let keys = user("alice");
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            lockout.check_at(&keys, now).unwrap();
+        }
+
+        assert!(lockout.check_at(&keys, now).is_err());
+        assert!(lockout.bans_at(now).is_empty());
+
+        lockout.release(&keys);
+        assert!(lockout.check_at(&keys, now).is_ok());
+
+        for _ in 0..3 {
+            lockout.record_failure_at(&keys, now);
+        }
+
+        assert_eq!(lockout.bans_at(now).len(), 1);
+    }
+
+    #[test]
+    fn records_are_capped() {
+        let lockout = lockout();
+        let banned = user("banned");
+        let now = Instant::now();
+        lockout.ban_at(banned[0].clone(), Duration::from_secs(60), now);
+
+        for idx in 0..64 {
+            let keys = user(&idx.to_string());
+            lockout.check_at(&keys, now).unwrap();
+            lockout.record_failure_at(&keys, now);
+        }
+
+        let records = lockout.records.lock().unwrap();
+        assert_eq!(records.map.len(), 16);
+        assert!(records.map.contains_key(&banned[0]));
+        assert!(records.map.contains_key(&user("63")[0]));
+    }
+}
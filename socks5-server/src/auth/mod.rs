@@ -0,0 +1,384 @@
+//! This module defines trait [`Auth`](https://docs.rs/socks5-server/latest/socks5_server/auth/trait.Auth.html) and some pre-defined authentication adaptors.
+//!
+//! The process of SOCKS5 authentication can be customized by implementing [`Auth`](https://docs.rs/socks5-server/latest/socks5_server/auth/trait.Auth.html) trait on your own types.
+
+mod context;
+
+#[cfg(feature = "credentials")]
+mod credentials;
+
+#[cfg(feature = "lockout")]
+mod lockout;
+
+pub use self::context::{AuthContext, Extensions};
+
+#[cfg(feature = "credentials")]
+pub use self::credentials::{
+    Credential, CredentialStore, HtpasswdError, HtpasswdStore, MemoryStore, PasswordHash,
+    StoredPassword, UnsupportedHash,
+};
+
+#[cfg(feature = "lockout")]
+pub use self::lockout::{BanKey, Banned, Guarded, Lockout, LockoutConfig, PasswordOutput};
+
+use crate::{connection::BufferedStream, AuthAdaptor};
+use async_trait::async_trait;
+use socks5_proto::handshake::{
+    password::{Error as PasswordError, Request as PasswordRequest, Response as PasswordResponse},
+    Method,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+
+/// This trait is for defining the customized process of SOCKS5 authentication.
+///
+/// You can create your own authentication method by implementing this trait. Associate type `Output` indicates the result of authenticating. Note that this library will not implicitly close any connection even if the authentication failed.
+///
+/// Generic type `<T>` is the stream the authentication is performed on, which defaults to a tokio [`TcpStream`](https://docs.rs/tokio/latest/tokio/net/struct.TcpStream.html). Implement this trait generically over `T` to make an adaptor usable with any stream type.
+///
+/// An [`AuthContext`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.AuthContext.html) is passed alongside the stream, carrying the addresses of the connection, the methods offered by the client and a per-connection extension map. [`is_acceptable()`](https://docs.rs/socks5-server/latest/socks5_server/auth/trait.Auth.html#method.is_acceptable) can be overridden to offer a method only to some clients, e.g. based on their IP address.
+///
+/// The stream is wrapped in a [`BufferedStream`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.BufferedStream.html), which may already contain bytes pipelined by the client. Always read from the `BufferedStream` itself (e.g. with [`read_message()`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.BufferedStream.html#method.read_message)) rather than from the underlying stream.
+///
+/// RFC 1928 allows an authentication method to encapsulate all subsequent traffic. After `execute()`, the stream is passed to [`encapsulate()`](https://docs.rs/socks5-server/latest/socks5_server/auth/trait.Auth.html#tymethod.encapsulate), which returns the stream that the rest of the connection is carried on. Associate type `Stream` is the type of that stream. Methods without encapsulation simply return the stream as is with `Stream = T`.
+///
+/// # Example
+/// ```rust
+/// use async_trait::async_trait;
+/// use std::io::Result;
+/// use socks5_proto::handshake::Method;
+/// use socks5_server::{auth::AuthContext, connection::BufferedStream, Auth};
+/// use tokio::net::TcpStream;
+///
+/// pub struct MyAuth;
+///
+/// #[async_trait]
+/// impl Auth<TcpStream> for MyAuth {
+///     type Output = Result<usize>;
+///     type Stream = TcpStream;
+///
+///     fn as_handshake_method(&self) -> Method {
+///         Method(0xfe)
+///     }
+///
+///     fn is_acceptable(&self, ctx: &AuthContext) -> bool {
+///         // only offer this method to clients on the loopback interface
+///         ctx.peer_addr().is_some_and(|addr| addr.ip().is_loopback())
+///     }
+///
+///     async fn execute(
+///         &self,
+///         stream: &mut BufferedStream<TcpStream>,
+///         ctx: &mut AuthContext,
+///     ) -> Self::Output {
+///         // do something on stream
+///         Ok(1145141919810)
+///     }
+///
+///     fn encapsulate(
+///         &self,
+///         stream: BufferedStream<TcpStream>,
+///         ctx: &mut AuthContext,
+///     ) -> BufferedStream<TcpStream> {
+///         // no encapsulation
+///         stream
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait Auth<T = TcpStream> {
+    type Output;
+    type Stream;
+
+    fn as_handshake_method(&self) -> Method;
+
+    /// Returns whether this method can be chosen for the connection. By default, it is always acceptable.
+    fn is_acceptable(&self, _ctx: &AuthContext) -> bool {
+        true
+    }
+
+    async fn execute(&self, stream: &mut BufferedStream<T>, ctx: &mut AuthContext) -> Self::Output;
+
+    /// Converts the stream after `execute()` into the stream used by the rest of the connection.
+    ///
+    /// This is called regardless of the output of `execute()`. Data needed for the encapsulation (e.g. negotiated keys) can be passed from `execute()` through the extensions of the [`AuthContext`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.AuthContext.html).
+    fn encapsulate(
+        &self,
+        stream: BufferedStream<T>,
+        ctx: &mut AuthContext,
+    ) -> BufferedStream<Self::Stream>;
+}
+
+/// Not authenticate at all.
+pub struct NoAuth;
+
+impl NoAuth {
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[async_trait]
+impl<T> Auth<T> for NoAuth
+where
+    T: Send,
+{
+    type Output = ();
+    type Stream = T;
+
+    fn as_handshake_method(&self) -> Method {
+        Method::NONE
+    }
+
+    async fn execute(&self, _: &mut BufferedStream<T>, _: &mut AuthContext) -> Self::Output {}
+
+    #[inline]
+    fn encapsulate(&self, stream: BufferedStream<T>, _: &mut AuthContext) -> BufferedStream<T> {
+        stream
+    }
+}
+
+/// Using username and password to authenticate.
+///
+/// The boolean value in associate type `Auth::Output` indicates whether the authentication is successful.
+pub struct Password {
+    pub username: Vec<u8>,
+    pub password: Vec<u8>,
+}
+
+impl Password {
+    /// Create a new `Password` authentication adaptor.
+    pub fn new(username: Vec<u8>, password: Vec<u8>) -> Self {
+        Self { username, password }
+    }
+}
+
+#[async_trait]
+impl<T> Auth<T> for Password
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    type Output = Result<bool, PasswordError>;
+    type Stream = T;
+
+    fn as_handshake_method(&self) -> Method {
+        Method::PASSWORD
+    }
+
+    async fn execute(&self, stream: &mut BufferedStream<T>, _: &mut AuthContext) -> Self::Output {
+        let req = stream.read_message(PasswordRequest::decode).await?;
+
+        if (&req.username, &req.password) == (&self.username, &self.password) {
+            let resp = PasswordResponse::new(true);
+            resp.write_to(stream).await?;
+            Ok(true)
+        } else {
+            let resp = PasswordResponse::new(false);
+            resp.write_to(stream).await?;
+            Ok(false)
+        }
+    }
+
+    #[inline]
+    fn encapsulate(&self, stream: BufferedStream<T>, _: &mut AuthContext) -> BufferedStream<T> {
+        stream
+    }
+}
+
+/// Maps the output of an authentication adaptor with a function.
+///
+/// All adaptors registered in a [`Negotiator`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.Negotiator.html) share the same `Output` type. `MapOutput` can be used to convert the output of each adaptor into a common type.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_proto::handshake::password::Error as PasswordError;
+/// use socks5_server::auth::{MapOutput, NoAuth, Password};
+///
+/// let no_auth = MapOutput::new(NoAuth, |()| true);
+/// let password = MapOutput::new(
+///     Password::new(b"user".to_vec(), b"pass".to_vec()),
+///     |res: Result<bool, PasswordError>| matches!(res, Ok(true)),
+/// );
+/// ```
+pub struct MapOutput<A, F> {
+    auth: A,
+    f: F,
+}
+
+impl<A, F> MapOutput<A, F> {
+    /// Create a new `MapOutput` authentication adaptor.
+    pub fn new(auth: A, f: F) -> Self {
+        Self { auth, f }
+    }
+}
+
+#[async_trait]
+impl<T, A, F, O> Auth<T> for MapOutput<A, F>
+where
+    T: Send,
+    A: Auth<T> + Send + Sync,
+    F: Fn(A::Output) -> O + Send + Sync,
+{
+    type Output = O;
+    type Stream = A::Stream;
+
+    fn as_handshake_method(&self) -> Method {
+        self.auth.as_handshake_method()
+    }
+
+    fn is_acceptable(&self, ctx: &AuthContext) -> bool {
+        self.auth.is_acceptable(ctx)
+    }
+
+    async fn execute(&self, stream: &mut BufferedStream<T>, ctx: &mut AuthContext) -> Self::Output {
+        (self.f)(self.auth.execute(stream, ctx).await)
+    }
+
+    #[inline]
+    fn encapsulate(
+        &self,
+        stream: BufferedStream<T>,
+        ctx: &mut AuthContext,
+    ) -> BufferedStream<Self::Stream> {
+        self.auth.encapsulate(stream, ctx)
+    }
+}
+
+/// Maps the encapsulated stream of an authentication adaptor with a function.
+///
+/// All adaptors registered in a [`Negotiator`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.Negotiator.html) share the same `Stream` type. `MapStream` can be used to convert the stream of each adaptor into a common type, e.g. an enum of a plain stream and an encrypted stream.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::{auth::{MapStream, NoAuth}, connection::BufferedStream};
+/// use tokio::net::TcpStream;
+///
+/// enum MyStream {
+///     Plain(TcpStream),
+///     // Encrypted(...),
+/// }
+///
+/// let no_auth = MapStream::new(NoAuth, |stream: BufferedStream<TcpStream>| {
+///     let (stream, buf) = stream.into_parts();
+///     BufferedStream::with_buffer(MyStream::Plain(stream), buf)
+/// });
+/// ```
+pub struct MapStream<A, F> {
+    auth: A,
+    f: F,
+}
+
+impl<A, F> MapStream<A, F> {
+    /// Create a new `MapStream` authentication adaptor.
+    pub fn new(auth: A, f: F) -> Self {
+        Self { auth, f }
+    }
+}
+
+#[async_trait]
+impl<T, A, F, U> Auth<T> for MapStream<A, F>
+where
+    T: Send,
+    A: Auth<T> + Send + Sync,
+    F: Fn(BufferedStream<A::Stream>) -> BufferedStream<U> + Send + Sync,
+{
+    type Output = A::Output;
+    type Stream = U;
+
+    fn as_handshake_method(&self) -> Method {
+        self.auth.as_handshake_method()
+    }
+
+    fn is_acceptable(&self, ctx: &AuthContext) -> bool {
+        self.auth.is_acceptable(ctx)
+    }
+
+    async fn execute(&self, stream: &mut BufferedStream<T>, ctx: &mut AuthContext) -> Self::Output {
+        self.auth.execute(stream, ctx).await
+    }
+
+    #[inline]
+    fn encapsulate(&self, stream: BufferedStream<T>, ctx: &mut AuthContext) -> BufferedStream<U> {
+        (self.f)(self.auth.encapsulate(stream, ctx))
+    }
+}
+
+/// A set of authentication adaptors with a server-side preference order.
+///
+/// Generic type `<U>` is the stream type after [encapsulation](https://docs.rs/socks5-server/latest/socks5_server/auth/trait.Auth.html#tymethod.encapsulate), which is the same as the original stream type `<T>` unless an encapsulating method is used.
+///
+/// During the handshake, the first adaptor (in the order they were added) whose method is offered by the client and which [accepts](https://docs.rs/socks5-server/latest/socks5_server/auth/trait.Auth.html#method.is_acceptable) the connection is chosen.
+///
+/// A single [`AuthAdaptor`](https://docs.rs/socks5-server/latest/socks5_server/type.AuthAdaptor.html) can be converted into a `Negotiator` with `From` trait.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_proto::handshake::password::Error as PasswordError;
+/// use socks5_server::auth::{MapOutput, Negotiator, NoAuth, Password};
+/// use std::sync::Arc;
+///
+/// let mut negotiator: Negotiator<bool> = Negotiator::new();
+/// negotiator.push(Arc::new(MapOutput::new(
+///     Password::new(b"user".to_vec(), b"pass".to_vec()),
+///     |res: Result<bool, PasswordError>| matches!(res, Ok(true)),
+/// )));
+/// negotiator.push(Arc::new(MapOutput::new(NoAuth, |()| true)));
+/// ```
+pub struct Negotiator<O, T = TcpStream, U = T> {
+    adaptors: Vec<AuthAdaptor<O, T, U>>,
+}
+
+impl<O, T, U> Negotiator<O, T, U> {
+    /// Create an empty `Negotiator`, which rejects every client until an adaptor is added.
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self {
+            adaptors: Vec::new(),
+        }
+    }
+
+    /// Add an authentication adaptor with lower preference than all previously added ones.
+    pub fn push(&mut self, adaptor: AuthAdaptor<O, T, U>) {
+        self.adaptors.push(adaptor);
+    }
+
+    /// Returns the registered adaptors in preference order.
+    #[inline]
+    pub fn adaptors(&self) -> &[AuthAdaptor<O, T, U>] {
+        &self.adaptors
+    }
+
+    /// Returns the handshake methods of the registered adaptors in preference order.
+    pub fn methods(&self) -> impl Iterator<Item = Method> + '_ {
+        self.adaptors.iter().map(|auth| auth.as_handshake_method())
+    }
+
+    /// Select the most preferred adaptor whose method is among the methods offered by the client and which accepts the connection.
+    pub fn select(&self, ctx: &AuthContext) -> Option<&AuthAdaptor<O, T, U>> {
+        self.adaptors.iter().find(|auth| {
+            ctx.methods().contains(&auth.as_handshake_method()) && auth.is_acceptable(ctx)
+        })
+    }
+}
+
+impl<O, T, U> From<AuthAdaptor<O, T, U>> for Negotiator<O, T, U> {
+    #[inline]
+    fn from(adaptor: AuthAdaptor<O, T, U>) -> Self {
+        Self {
+            adaptors: vec![adaptor],
+        }
+    }
+}
+
+impl<O, T, U> From<Vec<AuthAdaptor<O, T, U>>> for Negotiator<O, T, U> {
+    #[inline]
+    fn from(adaptors: Vec<AuthAdaptor<O, T, U>>) -> Self {
+        Self { adaptors }
+    }
+}
@@ -2,33 +2,49 @@
 //!
 //! This module also provides an [`UdpSocket`](https://docs.rs/tokio/latest/tokio/net/struct.UdpSocket.html) wrapper [`AssociatedUdpSocket`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html), which can be used to send and receive UDP packets without dealing with the SOCKS5 protocol UDP header.
 
-use bytes::{Bytes, BytesMut};
-use socks5_proto::{Address, Error as Socks5Error, Reply, Response, UdpHeader};
+use super::BufferedStream;
+#[cfg(feature = "relay")]
+use crate::relay::{self, UdpRelayConfig, UdpRelayStats};
+use bytes::{BufMut, Bytes, BytesMut};
+use socks5_proto::{
+    Address, Error as Socks5Error, Fragmenter, Reassembler, Reply, Response, UdpHeader,
+};
 use std::{
-    io::{Cursor, Error},
+    io::{Error, ErrorKind},
     marker::PhantomData,
-    net::SocketAddr,
+    net::{IpAddr, SocketAddr},
     ops::{Deref, DerefMut},
     pin::Pin,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
     task::{Context, Poll},
-    time::Duration,
+    time::{Duration, Instant},
 };
 use tokio::{
     io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
     net::{TcpStream, UdpSocket},
 };
 
+#[cfg(all(feature = "batch", target_os = "linux"))]
+mod batch;
+
+#[cfg(all(feature = "batch", target_os = "linux"))]
+pub use self::batch::{RecvBatch, SendBatch};
+
 /// Socks5 command type `Associate`
 ///
 /// By [`wait_request()`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Authenticated.html#method.wait_request) on an [`Authenticated`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Authenticated.html) from socks5 client, you may get a `Associate<NeedReply>`. After replying the client using [`reply()`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Associate.html#method.reply), you will get a `Associate<Ready>`, which can be used as a regular async TCP stream.
 ///
-/// A `Associate<S>` can be converted to a regular tokio [`TcpStream`](https://docs.rs/tokio/latest/tokio/net/struct.TcpStream.html) by using the `From` trait.
+/// Generic type `<T>` is the underlying stream, which defaults to a tokio [`TcpStream`](https://docs.rs/tokio/latest/tokio/net/struct.TcpStream.html). Socket option helpers are only available when the stream is a `TcpStream`.
+///
+/// A `Associate<S>` can be converted to a regular tokio [`TcpStream`](https://docs.rs/tokio/latest/tokio/net/struct.TcpStream.html) by using the `From` trait, or into the underlying stream with `into_inner()`. Both discard the bytes already buffered, which [`into_buffered()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.Associate.html#method.into_buffered) keeps.
 ///
 /// This module also provides an [`UdpSocket`](https://docs.rs/tokio/latest/tokio/net/struct.UdpSocket.html) wrapper [`AssociatedUdpSocket`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html), which can be used to send and receive UDP packets without dealing with the SOCKS5 protocol UDP header.
 #[derive(Debug)]
-pub struct Associate<S> {
-    stream: TcpStream,
+pub struct Associate<S, T = TcpStream> {
+    stream: BufferedStream<T>,
     _state: PhantomData<S>,
 }
 
@@ -40,30 +56,35 @@ pub struct NeedReply;
 #[derive(Debug)]
 pub struct Ready;
 
-impl Associate<NeedReply> {
+impl<T> Associate<NeedReply, T> {
     #[inline]
-    pub(super) fn new(stream: TcpStream) -> Self {
+    pub(super) fn new(stream: BufferedStream<T>) -> Self {
         Self {
             stream,
             _state: PhantomData,
         }
     }
+}
 
+impl<T> Associate<NeedReply, T>
+where
+    T: AsyncWrite + Unpin,
+{
     /// Reply to the SOCKS5 client with the given reply and address.
     ///
-    /// If encountered an error while writing the reply, the error alongside the original `TcpStream` is returned.
+    /// If encountered an error while writing the reply, the error alongside the original stream is returned. Bytes already buffered are discarded, as the connection cannot be used for SOCKS5 anymore.
     pub async fn reply(
         mut self,
         reply: Reply,
         addr: Address,
-    ) -> Result<Associate<Ready>, (Error, TcpStream)> {
+    ) -> Result<Associate<Ready, T>, (Error, T)> {
         let resp = Response::new(reply, addr);
 
         if let Err(err) = resp.write_to(&mut self.stream).await {
-            return Err((err, self.stream));
+            return Err((err, self.stream.into_parts().0));
         }
 
-        Ok(Associate::<Ready>::new(self.stream))
+        Ok(Associate::<Ready, T>::new(self.stream))
     }
 
     /// Causes the other peer to receive a read of length 0, indicating that no more data will be sent. This only closes the stream in one direction.
@@ -71,17 +92,19 @@ impl Associate<NeedReply> {
     pub async fn shutdown(&mut self) -> Result<(), Error> {
         self.stream.shutdown().await
     }
+}
 
+impl Associate<NeedReply, TcpStream> {
     /// Returns the local address that this stream is bound to.
     #[inline]
     pub fn local_addr(&self) -> Result<SocketAddr, Error> {
-        self.stream.local_addr()
+        self.stream.get_ref().local_addr()
     }
 
     /// Returns the remote address that this stream is connected to.
     #[inline]
     pub fn peer_addr(&self) -> Result<SocketAddr, Error> {
-        self.stream.peer_addr()
+        self.stream.get_ref().peer_addr()
     }
 
     /// Reads the linger duration for this socket by getting the `SO_LINGER` option.
@@ -89,7 +112,7 @@ impl Associate<NeedReply> {
     /// For more information about this option, see [set_linger](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Connect.html#method.set_linger).
     #[inline]
     pub fn linger(&self) -> Result<Option<Duration>, Error> {
-        self.stream.linger()
+        self.stream.get_ref().linger()
     }
 
     /// Sets the linger duration of this socket by setting the `SO_LINGER` option.
@@ -98,8 +121,9 @@ impl Associate<NeedReply> {
     ///
     /// If `SO_LINGER` is not specified, and the stream is closed, the system handles the call in a way that allows the process to continue as quickly as possible.
     #[inline]
+    #[allow(deprecated)]
     pub fn set_linger(&self, dur: Option<Duration>) -> Result<(), Error> {
-        self.stream.set_linger(dur)
+        self.stream.get_ref().set_linger(dur)
     }
 
     /// Gets the value of the `TCP_NODELAY` option on this socket.
@@ -107,40 +131,45 @@ impl Associate<NeedReply> {
     /// For more information about this option, see [set_nodelay](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Connect.html#method.set_nodelay).
     #[inline]
     pub fn nodelay(&self) -> Result<bool, Error> {
-        self.stream.nodelay()
+        self.stream.get_ref().nodelay()
     }
 
     /// Sets the value of the `TCP_NODELAY` option on this socket.
     ///
     /// If set, this option disables the Nagle algorithm. This means that segments are always sent as soon as possible, even if there is only a small amount of data. When not set, data is buffered until there is a sufficient amount to send out, thereby avoiding the frequent sending of small packets.
     pub fn set_nodelay(&self, nodelay: bool) -> Result<(), Error> {
-        self.stream.set_nodelay(nodelay)
+        self.stream.get_ref().set_nodelay(nodelay)
     }
 
     /// Gets the value of the `IP_TTL` option for this socket.
     ///
     /// For more information about this option, see [set_ttl](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Connect.html#method.set_ttl).
     pub fn ttl(&self) -> Result<u32, Error> {
-        self.stream.ttl()
+        self.stream.get_ref().ttl()
     }
 
     /// Sets the value for the `IP_TTL` option on this socket.
     ///
     /// This value sets the time-to-live field that is used in every packet sent from this socket.
     pub fn set_ttl(&self, ttl: u32) -> Result<(), Error> {
-        self.stream.set_ttl(ttl)
+        self.stream.get_ref().set_ttl(ttl)
     }
 }
 
-impl Associate<Ready> {
+impl<T> Associate<Ready, T> {
     #[inline]
-    fn new(stream: TcpStream) -> Self {
+    fn new(stream: BufferedStream<T>) -> Self {
         Self {
             stream,
             _state: PhantomData,
         }
     }
+}
 
+impl<T> Associate<Ready, T>
+where
+    T: AsyncRead + Unpin,
+{
     /// Wait until the SOCKS5 client closes this TCP connection.
     ///
     /// Socks5 protocol defines that when the client closes the TCP connection used to send the associate command, the server should release the associated UDP socket.
@@ -153,25 +182,62 @@ impl Associate<Ready> {
             }
         }
     }
+
+    /// Relays the datagrams received on `socket` until the client closes this TCP connection. `client_ip` is the IP address of the client in the session.
+    ///
+    /// See [`relay::relay_udp()`](https://docs.rs/socks5-server/latest/socks5_server/relay/fn.relay_udp.html).
+    #[cfg(feature = "relay")]
+    #[inline]
+    pub async fn relay(
+        &mut self,
+        socket: &AssociatedUdpSocket,
+        client_ip: IpAddr,
+        config: UdpRelayConfig,
+    ) -> UdpRelayStats {
+        relay::relay_udp(self, socket, client_ip, config).await
+    }
+}
+
+impl<T> Associate<Ready, T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Relays the datagrams framed on this TCP connection until the client closes it. Bytes already buffered in the connection are relayed first.
+    ///
+    /// A client asks for UDP-over-TCP with the address [`UdpFrame::associate_address()`](https://docs.rs/socks5-proto/latest/socks5_proto/struct.UdpFrame.html#method.associate_address) in its request, which should be checked with [`UdpFrame::is_associate_address()`](https://docs.rs/socks5-proto/latest/socks5_proto/struct.UdpFrame.html#method.is_associate_address), and echoed in the reply.
+    ///
+    /// See [`relay::relay_udp_over_tcp()`](https://docs.rs/socks5-server/latest/socks5_server/relay/fn.relay_udp_over_tcp.html).
+    #[cfg(feature = "relay")]
+    #[inline]
+    pub async fn relay_over_tcp(
+        &mut self,
+        client_ip: IpAddr,
+        config: UdpRelayConfig,
+    ) -> UdpRelayStats {
+        relay::relay_udp_over_tcp(self, client_ip, config).await
+    }
 }
 
-impl Deref for Associate<Ready> {
-    type Target = TcpStream;
+impl<T> Deref for Associate<Ready, T> {
+    type Target = T;
 
     #[inline]
     fn deref(&self) -> &Self::Target {
-        &self.stream
+        self.stream.get_ref()
     }
 }
 
-impl DerefMut for Associate<Ready> {
+impl<T> DerefMut for Associate<Ready, T> {
     #[inline]
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.stream
+        self.stream.get_mut()
     }
 }
 
-impl AsyncRead for Associate<Ready> {
+impl<T> AsyncRead for Associate<Ready, T>
+where
+    T: AsyncRead + Unpin,
+{
     #[inline]
     fn poll_read(
         mut self: Pin<&mut Self>,
@@ -182,7 +248,10 @@ impl AsyncRead for Associate<Ready> {
     }
 }
 
-impl AsyncWrite for Associate<Ready> {
+impl<T> AsyncWrite for Associate<Ready, T>
+where
+    T: AsyncWrite + Unpin,
+{
     #[inline]
     fn poll_write(
         mut self: Pin<&mut Self>,
@@ -203,10 +272,45 @@ impl AsyncWrite for Associate<Ready> {
     }
 }
 
-impl<S> From<Associate<S>> for TcpStream {
+impl<S, T> Associate<S, T> {
+    /// Gets a reference to the underlying stream.
+    ///
+    /// Note that reading directly from the underlying stream skips the bytes already buffered during the handshake.
+    #[inline]
+    pub fn get_ref(&self) -> &T {
+        self.stream.get_ref()
+    }
+
+    /// Gets a mutable reference to the underlying stream.
+    ///
+    /// Note that reading directly from the underlying stream skips the bytes already buffered during the handshake.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.stream.get_mut()
+    }
+
+    /// Consumes the `Associate`, returning the underlying stream.
+    ///
+    /// Any bytes already buffered during the handshake are discarded. Use [`into_buffered()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.Associate.html#method.into_buffered) to keep them.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.stream.into_parts().0
+    }
+
+    /// Consumes the `Associate`, returning the underlying stream alongside the bytes already buffered during the handshake.
+    #[inline]
+    pub fn into_buffered(self) -> BufferedStream<T> {
+        self.stream
+    }
+}
+
+/// Converts the `Associate` into its `TcpStream`.
+///
+/// Like `into_inner()`, this discards any bytes the client sent after its request that are already buffered. Use [`into_buffered()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.Associate.html#method.into_buffered) to keep them.
+impl<S> From<Associate<S, TcpStream>> for TcpStream {
     #[inline]
-    fn from(conn: Associate<S>) -> Self {
-        conn.stream
+    fn from(conn: Associate<S, TcpStream>) -> Self {
+        conn.stream.into_parts().0
     }
 }
 
@@ -215,13 +319,147 @@ impl<S> From<Associate<S>> for TcpStream {
 /// `(UdpSocket, usize)` and `AssociatedUdpSocket` can be converted to each other with `From` trait, in which `usize` is the maximum receiving UDP packet size, with SOCKS5 UDP header included.
 ///
 /// It only provides handful of methods to send / receive UDP packets with SOCKS5 UDP header. However, the underlying `UdpSocket` can be accessed with `AsRef` and `AsMut` trait, so you can use all methods provided by `UdpSocket`.
+///
+/// RFC 1928 requires the relay to only accept datagrams from the client that requested the association. Use [`restrict_client()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.restrict_client) to make [`recv_from()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.recv_from) filter datagrams by their source address.
+///
+/// Fragmented datagrams are returned as is by default. Use [`enable_reassembly()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.enable_reassembly) to only receive complete datagrams, and [`send_fragmented_to()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.send_fragmented_to) to send payloads too large for a single packet.
 #[derive(Debug)]
 pub struct AssociatedUdpSocket {
     socket: UdpSocket,
     buf_size: AtomicUsize,
+    restriction: Mutex<Option<ClientRestriction>>,
+    reassembly: Mutex<Option<Reassembly>>,
+    #[cfg(all(feature = "batch", target_os = "linux"))]
+    offload: batch::Offload,
+}
+
+/// What [`AssociatedUdpSocket::recv_from()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.recv_from) does with datagrams from an address other than the associated client.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ForeignPacket {
+    /// Silently drop the datagram and keep receiving.
+    Drop,
+    /// Return an error of kind `PermissionDenied` alongside the raw datagram.
+    Report,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ClientRestriction {
+    ip: Option<IpAddr>,
+    port: Option<u16>,
+    policy: ForeignPacket,
+}
+
+#[derive(Debug)]
+struct Reassembly {
+    reassembler: Reassembler,
+    source: Option<SocketAddr>,
+}
+
+impl ClientRestriction {
+    fn accepts(&self, addr: SocketAddr) -> bool {
+        self.ip.is_none_or(|ip| ip == addr.ip().to_canonical())
+            && self.port.is_none_or(|port| port == addr.port())
+    }
 }
 
 impl AssociatedUdpSocket {
+    /// Only accept datagrams from the client that requested the association.
+    ///
+    /// `requested` is the address in the ASSOCIATE request, which is where the client expects to send datagrams from, and `peer_ip` is the IP address of the control TCP connection. The client may not know its address yet and send all zeros (or a domain name), in which case:
+    ///
+    /// - an unspecified IP address is replaced by `peer_ip`, or learned from the first valid datagram if `peer_ip` is `None`
+    /// - a zero port is learned from the first valid datagram
+    ///
+    /// Once learned, the socket is locked onto that client endpoint. Datagrams from any other source are handled according to `policy`.
+    pub fn restrict_client(
+        &self,
+        requested: &Address,
+        peer_ip: Option<IpAddr>,
+        policy: ForeignPacket,
+    ) {
+        let (ip, port) = match requested {
+            Address::SocketAddress(addr) => (
+                Some(addr.ip().to_canonical()).filter(|ip| !ip.is_unspecified()),
+                Some(addr.port()).filter(|port| *port != 0),
+            ),
+            Address::DomainAddress(_, port) => (None, Some(*port).filter(|port| *port != 0)),
+        };
+
+        *self.restriction.lock().unwrap() = Some(ClientRestriction {
+            ip: ip.or(peer_ip.map(|ip| ip.to_canonical())),
+            port,
+            policy,
+        });
+    }
+
+    /// Accept datagrams from any source again.
+    #[inline]
+    pub fn unrestrict_client(&self) {
+        *self.restriction.lock().unwrap() = None;
+    }
+
+    /// Returns the client endpoint this socket is locked onto, if the client is restricted and both its IP address and port are known.
+    pub fn client_addr(&self) -> Option<SocketAddr> {
+        let restriction = self.restriction.lock().unwrap();
+        let restriction = restriction.as_ref()?;
+        Some(SocketAddr::new(restriction.ip?, restriction.port?))
+    }
+
+    /// Checks the source of a datagram. Returns the policy to apply if it comes from a foreign address.
+    fn check_source(&self, addr: SocketAddr) -> Option<ForeignPacket> {
+        let restriction = self.restriction.lock().unwrap();
+
+        match &*restriction {
+            Some(restriction) if !restriction.accepts(addr) => Some(restriction.policy),
+            _ => None,
+        }
+    }
+
+    /// Locks onto the source of a valid datagram if the client endpoint is not fully known yet.
+    fn learn_source(&self, addr: SocketAddr) {
+        if let Some(restriction) = &mut *self.restriction.lock().unwrap() {
+            restriction.ip.get_or_insert(addr.ip().to_canonical());
+            restriction.port.get_or_insert(addr.port());
+        }
+    }
+
+    /// Reassemble fragmented datagrams before returning them from [`recv()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.recv) and [`recv_from()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.recv_from).
+    ///
+    /// Fragments are queued until the last one of a datagram arrives, and the reassembled payload is returned with a header of `FRAG` 0. Incomplete, out of order or oversized sequences are dropped according to `reassembler`. Only one datagram is reassembled at a time, and a fragment from another source abandons the queue.
+    pub fn enable_reassembly(&self, reassembler: Reassembler) {
+        *self.reassembly.lock().unwrap() = Some(Reassembly {
+            reassembler,
+            source: None,
+        });
+    }
+
+    /// Return fragmented datagrams as is again, dropping any queued fragment.
+    #[inline]
+    pub fn disable_reassembly(&self) {
+        *self.reassembly.lock().unwrap() = None;
+    }
+
+    /// Feeds a received packet to the reassembler, if enabled. Returns `None` if the packet was queued or dropped.
+    fn reassemble(
+        &self,
+        pkt: Bytes,
+        header: UdpHeader,
+        source: Option<SocketAddr>,
+    ) -> Option<(Bytes, UdpHeader)> {
+        let mut reassembly = self.reassembly.lock().unwrap();
+
+        let Some(reassembly) = &mut *reassembly else {
+            return Some((pkt, header));
+        };
+
+        if reassembly.source != source {
+            reassembly.reassembler.clear();
+            reassembly.source = source;
+        }
+
+        reassembly.reassembler.push(header, pkt, Instant::now())
+    }
+
     /// Get the maximum receiving UDP packet size, with SOCKS5 UDP header included.
     #[inline]
     pub fn get_max_pkt_size(&self) -> usize {
@@ -231,60 +469,129 @@ impl AssociatedUdpSocket {
     /// Set the maximum receiving UDP packet size, with SOCKS5 UDP header included, for adjusting the receiving buffer size.
     #[inline]
     pub fn set_max_pkt_size(&self, size: usize) {
-        self.buf_size.store(size, Ordering::Release);
+        self.buf_size.store(size, Ordering::Relaxed);
     }
 
     /// Receives a SOCKS5 UDP packet on the socket from the remote address which it is connected.
     ///
     /// On success, it returns the packet payload and the SOCKS5 UDP header. On error, it returns the error alongside an `Option<Vec<u8>>`. If the error occurs before / when receiving the raw UDP packet, the `Option<Vec<u8>>` will be `None`. Otherwise, it will be `Some(Vec<u8>)` containing the received raw UDP packet.
+    ///
+    /// If [reassembly](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.enable_reassembly) is enabled, it only returns complete datagrams.
+    ///
+    /// A new buffer is allocated for every packet. Use [`recv_buf()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.recv_buf) to reuse a buffer instead.
     pub async fn recv(&self) -> Result<(Bytes, UdpHeader), (Socks5Error, Option<Vec<u8>>)> {
-        let max_pkt_size = self.buf_size.load(Ordering::Acquire);
-        let mut buf = vec![0; max_pkt_size];
-
-        let len = match self.socket.recv(&mut buf).await {
-            Ok(len) => len,
-            Err(err) => return Err((Socks5Error::Io(err), None)),
-        };
-
-        buf.truncate(len);
-
-        let header = match UdpHeader::read_from(&mut Cursor::new(buf.as_slice())).await {
-            Ok(header) => header,
-            Err(err) => return Err((err, Some(buf))),
-        };
-
-        let pkt = Bytes::from(buf).slice(header.serialized_len()..);
-
-        Ok((pkt, header))
+        self.recv_buf(&mut BytesMut::new())
+            .await
+            .map_err(|(err, pkt)| (err, pkt.map(|pkt| pkt.to_vec())))
     }
 
     /// Receives a SOCKS5 UDP packet on the socket from a remote address.
     ///
     /// On success, it returns the packet payload, the SOCKS5 UDP header and the source address. On error, it returns the error alongside an `Option<Vec<u8>>`. If the error occurs before / when receiving the raw UDP packet, the `Option<Vec<u8>>` will be `None`. Otherwise, it will be `Some(Vec<u8>)` containing the received raw UDP packet.
+    ///
+    /// If the client is [restricted](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.restrict_client), datagrams from other sources are dropped or reported with an error of kind `PermissionDenied`. If [reassembly](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.enable_reassembly) is enabled, it only returns complete datagrams.
+    ///
+    /// A new buffer is allocated for every packet. Use [`recv_buf_from()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.recv_buf_from) to reuse a buffer instead.
     pub async fn recv_from(
         &self,
     ) -> Result<(Bytes, UdpHeader, SocketAddr), (Socks5Error, Option<Vec<u8>>)> {
-        let max_pkt_size = self.buf_size.load(Ordering::Acquire);
-        let mut buf = vec![0; max_pkt_size];
+        self.recv_buf_from(&mut BytesMut::new())
+            .await
+            .map_err(|(err, pkt)| (err, pkt.map(|pkt| pkt.to_vec())))
+    }
 
-        let (len, addr) = match self.socket.recv_from(&mut buf).await {
-            Ok(res) => res,
-            Err(err) => return Err((Socks5Error::Io(err), None)),
-        };
+    /// Receives a SOCKS5 UDP packet on the socket from the remote address which it is connected, into a reusable buffer.
+    ///
+    /// This behaves like [`recv()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.recv), except that the packet is received into `buf`, and the returned payload (or raw packet on error) shares its allocation. Once the returned `Bytes` is dropped, the next call reclaims the allocation, so receiving in a loop with the same `buf` does no heap allocation in the steady state.
+    ///
+    /// Fragments queued for [reassembly](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.enable_reassembly) keep their part of the allocation alive until the datagram is complete.
+    pub async fn recv_buf(
+        &self,
+        buf: &mut BytesMut,
+    ) -> Result<(Bytes, UdpHeader), (Socks5Error, Option<Bytes>)> {
+        loop {
+            let max_pkt_size = self.buf_size.load(Ordering::Relaxed);
+            buf.clear();
+            buf.reserve(max_pkt_size);
+
+            if let Err(err) = self
+                .socket
+                .recv_buf(&mut (&mut *buf).limit(max_pkt_size))
+                .await
+            {
+                return Err((Socks5Error::Io(err), None));
+            }
+
+            let (pkt, header) = decode_packet(buf.split())?;
 
-        buf.truncate(len);
+            if let Some(res) = self.reassemble(pkt, header, None) {
+                return Ok(res);
+            }
+        }
+    }
 
-        let header = match UdpHeader::read_from(&mut Cursor::new(buf.as_slice())).await {
-            Ok(header) => header,
-            Err(err) => return Err((err, Some(buf))),
+    /// Receives a SOCKS5 UDP packet on the socket from a remote address, into a reusable buffer.
+    ///
+    /// This behaves like [`recv_from()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.recv_from), except that the packet is received into `buf`, and the returned payload (or raw packet on error) shares its allocation. Once the returned `Bytes` is dropped, the next call reclaims the allocation, so receiving in a loop with the same `buf` does no heap allocation in the steady state.
+    ///
+    /// Fragments queued for [reassembly](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.enable_reassembly) keep their part of the allocation alive until the datagram is complete.
+    pub async fn recv_buf_from(
+        &self,
+        buf: &mut BytesMut,
+    ) -> Result<(Bytes, UdpHeader, SocketAddr), (Socks5Error, Option<Bytes>)> {
+        loop {
+            let max_pkt_size = self.buf_size.load(Ordering::Relaxed);
+            buf.clear();
+            buf.reserve(max_pkt_size);
+
+            let addr = match self
+                .socket
+                .recv_buf_from(&mut (&mut *buf).limit(max_pkt_size))
+                .await
+            {
+                Ok((_, addr)) => addr,
+                Err(err) => return Err((Socks5Error::Io(err), None)),
+            };
+
+            if let Some(res) = self.process_packet(buf.split(), addr) {
+                return res;
+            }
+        }
+    }
+
+    /// Filters, decodes and reassembles a raw packet received from `addr`. Returns `None` if the packet was dropped or queued for reassembly.
+    #[allow(clippy::type_complexity)]
+    fn process_packet(
+        &self,
+        buf: BytesMut,
+        addr: SocketAddr,
+    ) -> Option<Result<(Bytes, UdpHeader, SocketAddr), (Socks5Error, Option<Bytes>)>> {
+        match self.check_source(addr) {
+            None => {}
+            Some(ForeignPacket::Drop) => return None,
+            Some(ForeignPacket::Report) => {
+                let err = Error::new(
+                    ErrorKind::PermissionDenied,
+                    format!("datagram from unexpected source {addr}"),
+                );
+                return Some(Err((Socks5Error::Io(err), Some(buf.freeze()))));
+            }
+        }
+
+        let (pkt, header) = match decode_packet(buf) {
+            Ok(res) => res,
+            Err(err) => return Some(Err(err)),
         };
 
-        let pkt = Bytes::from(buf).slice(header.serialized_len()..);
+        self.learn_source(addr);
 
-        Ok((pkt, header, addr))
+        self.reassemble(pkt, header, Some(addr))
+            .map(|(pkt, header)| Ok((pkt, header, addr)))
     }
 
     /// Sends a UDP packet to the remote address which it is connected. The SOCKS5 UDP header will be added to the packet.
+    ///
+    /// The header and the payload are copied into a new buffer. Use [`send_with_headroom()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.send_with_headroom) to avoid it.
     pub async fn send<P: AsRef<[u8]>>(&self, pkt: P, header: &UdpHeader) -> Result<usize, Error> {
         let mut buf = BytesMut::with_capacity(header.serialized_len() + pkt.as_ref().len());
         header.write_to_buf(&mut buf);
@@ -297,6 +604,8 @@ impl AssociatedUdpSocket {
     }
 
     /// Sends a UDP packet to a specified remote address. The SOCKS5 UDP header will be added to the packet.
+    ///
+    /// The header and the payload are copied into a new buffer. Use [`send_to_with_headroom()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.send_to_with_headroom) to avoid it.
     pub async fn send_to<P: AsRef<[u8]>>(
         &self,
         pkt: P,
@@ -312,6 +621,96 @@ impl AssociatedUdpSocket {
             .await
             .map(|len| len - header.serialized_len())
     }
+
+    /// Sends a UDP packet to the remote address which it is connected, writing the SOCKS5 UDP header in place.
+    ///
+    /// The payload is `buf[headroom..]`. The header is written right before it, at the end of `buf[..headroom]`, so neither is copied. A headroom of [`UdpHeader::MAX_SERIALIZED_LEN`](https://docs.rs/socks5-proto/latest/socks5_proto/struct.UdpHeader.html#associatedconstant.MAX_SERIALIZED_LEN) bytes fits any header. If the header does not fit, an error of kind `InvalidInput` is returned.
+    pub async fn send_with_headroom(
+        &self,
+        buf: &mut [u8],
+        headroom: usize,
+        header: &UdpHeader,
+    ) -> Result<usize, Error> {
+        let start = write_header(buf, headroom, header)?;
+
+        self.socket
+            .send(&buf[start..])
+            .await
+            .map(|len| len - header.serialized_len())
+    }
+
+    /// Sends a UDP packet to a specified remote address, writing the SOCKS5 UDP header in place.
+    ///
+    /// The payload is `buf[headroom..]`. The header is written right before it, at the end of `buf[..headroom]`, so neither is copied. A headroom of [`UdpHeader::MAX_SERIALIZED_LEN`](https://docs.rs/socks5-proto/latest/socks5_proto/struct.UdpHeader.html#associatedconstant.MAX_SERIALIZED_LEN) bytes fits any header. If the header does not fit, an error of kind `InvalidInput` is returned.
+    pub async fn send_to_with_headroom(
+        &self,
+        buf: &mut [u8],
+        headroom: usize,
+        header: &UdpHeader,
+        addr: SocketAddr,
+    ) -> Result<usize, Error> {
+        let start = write_header(buf, headroom, header)?;
+
+        self.socket
+            .send_to(&buf[start..], addr)
+            .await
+            .map(|len| len - header.serialized_len())
+    }
+
+    /// Sends a payload to a specified remote address, split into as many SOCKS5 UDP fragments as needed by `fragmenter`. The SOCKS5 UDP headers will be added to the packets.
+    ///
+    /// Returns the number of payload bytes sent. An error of kind `InvalidInput` is returned if the payload can not be fragmented, i.e. it needs more than 127 fragments.
+    pub async fn send_fragmented_to(
+        &self,
+        pkt: Bytes,
+        address: Address,
+        addr: SocketAddr,
+        fragmenter: Fragmenter,
+    ) -> Result<usize, Error> {
+        let Some(fragments) = fragmenter.fragment(pkt, address) else {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "payload too large to be fragmented",
+            ));
+        };
+
+        let mut len = 0;
+
+        for (pkt, header) in fragments {
+            len += self.send_to(pkt, &header, addr).await?;
+        }
+
+        Ok(len)
+    }
+}
+
+/// Splits the SOCKS5 UDP header off a raw packet.
+fn decode_packet(mut buf: BytesMut) -> Result<(Bytes, UdpHeader), (Socks5Error, Option<Bytes>)> {
+    match UdpHeader::decode(&mut buf) {
+        Ok(Some(header)) => Ok((buf.freeze(), header)),
+        Ok(None) => Err((
+            Socks5Error::Io(Error::from(ErrorKind::UnexpectedEof)),
+            Some(buf.freeze()),
+        )),
+        Err(err) => Err((err, Some(buf.freeze()))),
+    }
+}
+
+/// Writes the SOCKS5 UDP header at the end of the headroom. Returns where the packet starts.
+fn write_header(buf: &mut [u8], headroom: usize, header: &UdpHeader) -> Result<usize, Error> {
+    let start = headroom
+        .checked_sub(header.serialized_len())
+        .filter(|_| headroom <= buf.len())
+        .ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                "not enough headroom for the SOCKS5 UDP header",
+            )
+        })?;
+
+    header.write_to_buf(&mut &mut buf[start..headroom]);
+
+    Ok(start)
 }
 
 impl From<(UdpSocket, usize)> for AssociatedUdpSocket {
@@ -320,6 +719,10 @@ impl From<(UdpSocket, usize)> for AssociatedUdpSocket {
         AssociatedUdpSocket {
             socket: from.0,
             buf_size: AtomicUsize::new(from.1),
+            restriction: Mutex::new(None),
+            reassembly: Mutex::new(None),
+            #[cfg(all(feature = "batch", target_os = "linux"))]
+            offload: batch::Offload::default(),
         }
     }
 }
@@ -0,0 +1,721 @@
+use super::AssociatedUdpSocket;
+use bytes::{Bytes, BytesMut};
+use socks5_proto::{Error as Socks5Error, UdpHeader};
+use std::{
+    fmt::{Debug, Formatter, Result as FmtResult},
+    io::Error,
+    mem,
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6},
+    ops::Range,
+    os::fd::{AsRawFd, RawFd},
+    ptr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use tokio::io::Interest;
+
+/// Maximum number of messages passed to a single `sendmmsg(2)` call.
+const MAX_MESSAGES: usize = 1024;
+
+/// Maximum number of segments in a single GSO message.
+const MAX_SEGMENTS: usize = 64;
+
+/// Maximum length of a single GSO message.
+const MAX_GSO_LEN: usize = 65507;
+
+/// Room for one `UDP_GRO` / `UDP_SEGMENT` control message.
+type Control = [u64; 8];
+
+#[derive(Debug, Default)]
+pub(super) struct Offload {
+    gro: AtomicBool,
+    gso: AtomicBool,
+}
+
+impl AssociatedUdpSocket {
+    /// Enables or disables UDP generic receive offload (`UDP_GRO`), available since Linux 5.0.
+    ///
+    /// With GRO enabled, the kernel may coalesce consecutive datagrams from the same source into a single buffer, which [`recv_batch_from()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.recv_batch_from) splits back into SOCKS5 packets. Each slot of a [`RecvBatch`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.RecvBatch.html) then needs 64 KiB instead of the maximum packet size.
+    pub fn set_gro(&self, enabled: bool) -> Result<(), Error> {
+        setsockopt(
+            self.socket.as_raw_fd(),
+            libc::UDP_GRO,
+            libc::c_int::from(enabled),
+        )?;
+        self.offload.gro.store(enabled, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Returns whether UDP generic receive offload is enabled.
+    #[inline]
+    pub fn gro(&self) -> bool {
+        self.offload.gro.load(Ordering::Relaxed)
+    }
+
+    /// Enables or disables UDP generic segmentation offload (`UDP_SEGMENT`), available since Linux 4.18.
+    ///
+    /// With GSO enabled, [`send_batch()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.send_batch) passes consecutive packets of the same size to the same destination to the kernel as a single message. If the kernel can not segment them, GSO is disabled again and the packets are sent one message each.
+    pub fn set_gso(&self, enabled: bool) -> Result<(), Error> {
+        if enabled {
+            // probe for kernel support, a zero segment size leaves GSO off for plain sends
+            setsockopt(self.socket.as_raw_fd(), libc::UDP_SEGMENT, 0)?;
+        }
+
+        self.offload.gso.store(enabled, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Returns whether UDP generic segmentation offload is enabled.
+    #[inline]
+    pub fn gso(&self) -> bool {
+        self.offload.gso.load(Ordering::Relaxed)
+    }
+
+    /// Receives SOCKS5 UDP packets into `batch` with a single `recvmmsg(2)` call, waiting until at least one is available.
+    ///
+    /// Returns the number of packets in the batch, which can then be taken with [`RecvBatch::drain()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.RecvBatch.html#method.drain). Every packet is handled like in [`recv_buf_from()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.recv_buf_from): client restriction and reassembly apply, malformed packets are returned as errors alongside the raw packet, and the buffers of the batch are reused once the returned payloads are dropped.
+    ///
+    /// Datagrams larger than the maximum packet size are dropped, and counted in [`RecvBatch::truncated()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.RecvBatch.html#method.truncated).
+    ///
+    /// An error is only returned if receiving fails.
+    pub async fn recv_batch_from(&self, batch: &mut RecvBatch) -> Result<usize, Error> {
+        let fd = self.socket.as_raw_fd();
+
+        loop {
+            let slot_size = if self.gro() {
+                u16::MAX as usize
+            } else {
+                self.buf_size.load(Ordering::Relaxed)
+            };
+
+            batch.packets.clear();
+            batch.prepare(slot_size);
+
+            let len = self
+                .socket
+                .async_io(Interest::READABLE, || batch.recvmmsg(fd))
+                .await?;
+
+            for idx in 0..len {
+                let hdr = &batch.msgs[idx].msg_hdr;
+
+                // the datagram did not fit in the slot, and the rest of it is lost
+                if hdr.msg_flags & libc::MSG_TRUNC != 0 {
+                    batch.truncated += 1;
+                    continue;
+                }
+
+                let pkt_len = (batch.msgs[idx].msg_len as usize).min(slot_size);
+                let segment_size = unsafe { gro_segment_size(hdr) }
+                    .filter(|size| *size > 0)
+                    .unwrap_or(pkt_len);
+                let addr = from_sockaddr(&batch.addrs[idx]);
+
+                // the kernel initialized the first `pkt_len` bytes of the spare capacity
+                unsafe { batch.bufs[idx].set_len(pkt_len) };
+                let mut buf = batch.bufs[idx].split();
+
+                let Some(addr) = addr else {
+                    continue;
+                };
+
+                while !buf.is_empty() {
+                    let segment = buf.split_to(segment_size.min(buf.len()));
+
+                    if let Some(res) = self.process_packet(segment, addr) {
+                        batch.packets.push(res);
+                    }
+                }
+            }
+
+            if !batch.packets.is_empty() {
+                return Ok(batch.packets.len());
+            }
+        }
+    }
+
+    /// Sends all packets in `batch` with as few `sendmmsg(2)` calls as possible, clearing it.
+    ///
+    /// Returns the number of packets sent. If sending fails after some packets were sent, their number is returned, and the packets not sent yet are left in the batch, so the next call retries them and returns the error if it persists. If nothing could be sent, the error is returned, the packets of the message that failed, i.e. the packets to one destination, are dropped, and the packets after them are left in the batch to be sent by another call.
+    pub async fn send_batch(&self, batch: &mut SendBatch) -> Result<usize, Error> {
+        let fd = self.socket.as_raw_fd();
+        let mut gso = self.gso();
+        let mut sent = 0;
+
+        while !batch.packets.is_empty() {
+            batch.prepare(gso);
+
+            let res = self
+                .socket
+                .async_io(Interest::WRITABLE, || batch.sendmmsg(fd))
+                .await;
+
+            match res {
+                Ok(len) => {
+                    let count: usize = batch.groups[..len].iter().map(|group| group.0).sum();
+                    batch.packets.drain(..count);
+                    sent += count;
+                }
+                // the error is about the first message, only retry it unsegmented if segmentation failed
+                Err(err)
+                    if batch.groups[0].0 > 1
+                        && matches!(err.raw_os_error(), Some(libc::EIO | libc::EINVAL)) =>
+                {
+                    // the device can not segment the packets, stop trying
+                    if err.raw_os_error() == Some(libc::EIO) {
+                        self.offload.gso.store(false, Ordering::Relaxed);
+                    }
+
+                    gso = false;
+                }
+                Err(_) if sent > 0 => return Ok(sent),
+                Err(err) => {
+                    batch.packets.drain(..batch.groups[0].0);
+
+                    if batch.packets.is_empty() {
+                        batch.headers.clear();
+                    }
+
+                    return Err(err);
+                }
+            }
+        }
+
+        batch.headers.clear();
+
+        Ok(sent)
+    }
+}
+
+/// Reusable buffers for receiving SOCKS5 UDP packets in batches with [`AssociatedUdpSocket::recv_batch_from()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.recv_batch_from).
+///
+/// A batch has a fixed number of slots, each receiving a datagram, or several coalesced datagrams when GRO is enabled.
+pub struct RecvBatch {
+    bufs: Vec<BytesMut>,
+    addrs: Vec<libc::sockaddr_storage>,
+    controls: Vec<Control>,
+    iovecs: Vec<libc::iovec>,
+    msgs: Vec<libc::mmsghdr>,
+    #[allow(clippy::type_complexity)]
+    packets: Vec<Result<(Bytes, UdpHeader, SocketAddr), (Socks5Error, Option<Bytes>)>>,
+    truncated: u64,
+}
+
+// the raw pointers only refer to the buffers of the batch itself, and are rebuilt before every call
+unsafe impl Send for RecvBatch {}
+unsafe impl Sync for RecvBatch {}
+
+impl RecvBatch {
+    /// Creates a new `RecvBatch` receiving up to `slots` datagrams per call.
+    pub fn new(slots: usize) -> Self {
+        let slots = slots.clamp(1, MAX_MESSAGES);
+
+        Self {
+            bufs: (0..slots).map(|_| BytesMut::new()).collect(),
+            addrs: vec![unsafe { mem::zeroed() }; slots],
+            controls: vec![[0; 8]; slots],
+            iovecs: vec![unsafe { mem::zeroed() }; slots],
+            msgs: vec![unsafe { mem::zeroed() }; slots],
+            packets: Vec::with_capacity(slots),
+            truncated: 0,
+        }
+    }
+
+    /// Returns the number of slots.
+    #[inline]
+    pub fn slots(&self) -> usize {
+        self.bufs.len()
+    }
+
+    /// Returns the number of received packets not taken yet.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    /// Returns `true` if there is no received packet left.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    /// Returns the number of datagrams dropped because they were larger than a slot, since the batch was created.
+    #[inline]
+    pub fn truncated(&self) -> u64 {
+        self.truncated
+    }
+
+    /// Takes the received packets, in the same form as the result of [`AssociatedUdpSocket::recv_buf_from()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.recv_buf_from).
+    #[allow(clippy::type_complexity)]
+    #[inline]
+    pub fn drain(
+        &mut self,
+    ) -> impl Iterator<Item = Result<(Bytes, UdpHeader, SocketAddr), (Socks5Error, Option<Bytes>)>> + '_
+    {
+        self.packets.drain(..)
+    }
+
+    fn prepare(&mut self, slot_size: usize) {
+        for (idx, buf) in self.bufs.iter_mut().enumerate() {
+            buf.clear();
+            buf.reserve(slot_size);
+
+            self.iovecs[idx] = libc::iovec {
+                iov_base: buf.spare_capacity_mut().as_mut_ptr().cast(),
+                iov_len: slot_size,
+            };
+
+            let msg = &mut self.msgs[idx];
+            msg.msg_hdr.msg_name = ptr::addr_of_mut!(self.addrs[idx]).cast();
+            msg.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as _;
+            msg.msg_hdr.msg_iov = &mut self.iovecs[idx];
+            msg.msg_hdr.msg_iovlen = 1;
+            msg.msg_hdr.msg_control = self.controls[idx].as_mut_ptr().cast();
+            msg.msg_hdr.msg_controllen = mem::size_of::<Control>() as _;
+            msg.msg_hdr.msg_flags = 0;
+            msg.msg_len = 0;
+        }
+    }
+
+    fn recvmmsg(&mut self, fd: RawFd) -> Result<usize, Error> {
+        let res = unsafe {
+            libc::recvmmsg(
+                fd,
+                self.msgs.as_mut_ptr(),
+                self.msgs.len() as _,
+                0,
+                ptr::null_mut(),
+            )
+        };
+
+        if res < 0 {
+            Err(Error::last_os_error())
+        } else {
+            Ok(res as usize)
+        }
+    }
+}
+
+impl Debug for RecvBatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("RecvBatch")
+            .field("slots", &self.bufs.len())
+            .field("packets", &self.packets)
+            .field("truncated", &self.truncated)
+            .finish()
+    }
+}
+
+/// Queued SOCKS5 UDP packets to be sent in batches with [`AssociatedUdpSocket::send_batch()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.send_batch).
+///
+/// Payloads are not copied. The headers are serialized into a buffer reused across batches.
+pub struct SendBatch {
+    packets: Vec<(Bytes, Range<usize>, SocketAddr)>,
+    headers: Vec<u8>,
+    groups: Vec<(usize, usize)>,
+    addrs: Vec<libc::sockaddr_storage>,
+    controls: Vec<Control>,
+    iovecs: Vec<libc::iovec>,
+    msgs: Vec<libc::mmsghdr>,
+}
+
+// the raw pointers only refer to the buffers of the batch itself, and are rebuilt before every call
+unsafe impl Send for SendBatch {}
+unsafe impl Sync for SendBatch {}
+
+impl SendBatch {
+    /// Creates a new empty `SendBatch`.
+    pub fn new() -> Self {
+        Self {
+            packets: Vec::new(),
+            headers: Vec::new(),
+            groups: Vec::new(),
+            addrs: Vec::new(),
+            controls: Vec::new(),
+            iovecs: Vec::new(),
+            msgs: Vec::new(),
+        }
+    }
+
+    /// Queues a packet to be sent to `addr`. The SOCKS5 UDP header will be added to the packet.
+    pub fn push(&mut self, pkt: Bytes, header: &UdpHeader, addr: SocketAddr) {
+        let start = self.headers.len();
+        header.write_to_buf(&mut self.headers);
+        self.packets.push((pkt, start..self.headers.len(), addr));
+    }
+
+    /// Returns the number of queued packets.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.packets.len()
+    }
+
+    /// Returns `true` if there is no queued packet.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.packets.is_empty()
+    }
+
+    /// Drops all queued packets.
+    pub fn clear(&mut self) {
+        self.packets.clear();
+        self.headers.clear();
+    }
+
+    /// Groups the packets into messages, and builds the messages for the first `MAX_MESSAGES` groups.
+    fn prepare(&mut self, gso: bool) {
+        self.groups.clear();
+        let mut idx = 0;
+
+        while idx < self.packets.len() && self.groups.len() < MAX_MESSAGES {
+            let (pkt, header, addr) = &self.packets[idx];
+            let segment_size = header.len() + pkt.len();
+            let mut count = 1;
+
+            if gso {
+                let mut total = segment_size;
+
+                // all segments but the last one must have the same size
+                while let Some((pkt, header, next_addr)) = self.packets.get(idx + count) {
+                    let size = header.len() + pkt.len();
+
+                    if count == MAX_SEGMENTS
+                        || next_addr != addr
+                        || size > segment_size
+                        || total + size > MAX_GSO_LEN
+                    {
+                        break;
+                    }
+
+                    total += size;
+                    count += 1;
+
+                    if size < segment_size {
+                        break;
+                    }
+                }
+            }
+
+            self.groups.push((count, segment_size));
+            idx += count;
+        }
+
+        self.iovecs.clear();
+
+        for (pkt, header, _) in &self.packets[..idx] {
+            self.iovecs.push(libc::iovec {
+                iov_base: self.headers[header.clone()].as_ptr() as *mut _,
+                iov_len: header.len(),
+            });
+            self.iovecs.push(libc::iovec {
+                iov_base: pkt.as_ptr() as *mut _,
+                iov_len: pkt.len(),
+            });
+        }
+
+        let len = self.groups.len();
+        self.addrs.resize(len, unsafe { mem::zeroed() });
+        self.controls.resize(len, [0; 8]);
+        self.msgs.resize(len, unsafe { mem::zeroed() });
+
+        let mut iov_idx = 0;
+
+        for (idx, (count, segment_size)) in self.groups.iter().enumerate() {
+            let addr_len = to_sockaddr(self.packets[iov_idx / 2].2, &mut self.addrs[idx]);
+
+            let msg = &mut self.msgs[idx];
+            msg.msg_hdr.msg_name = ptr::addr_of_mut!(self.addrs[idx]).cast();
+            msg.msg_hdr.msg_namelen = addr_len;
+            msg.msg_hdr.msg_iov = &mut self.iovecs[iov_idx];
+            msg.msg_hdr.msg_iovlen = (count * 2) as _;
+            msg.msg_hdr.msg_control = ptr::null_mut();
+            msg.msg_hdr.msg_controllen = 0;
+            msg.msg_hdr.msg_flags = 0;
+            msg.msg_len = 0;
+
+            if *count > 1 {
+                unsafe {
+                    let data_len = mem::size_of::<u16>() as libc::c_uint;
+
+                    msg.msg_hdr.msg_control = self.controls[idx].as_mut_ptr().cast();
+                    msg.msg_hdr.msg_controllen = libc::CMSG_SPACE(data_len) as _;
+
+                    let cmsg = libc::CMSG_FIRSTHDR(&msg.msg_hdr);
+                    (*cmsg).cmsg_level = libc::SOL_UDP;
+                    (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+                    (*cmsg).cmsg_len = libc::CMSG_LEN(data_len) as _;
+                    ptr::write_unaligned(libc::CMSG_DATA(cmsg).cast(), *segment_size as u16);
+                }
+            }
+
+            iov_idx += count * 2;
+        }
+    }
+
+    fn sendmmsg(&mut self, fd: RawFd) -> Result<usize, Error> {
+        let res = unsafe { libc::sendmmsg(fd, self.msgs.as_mut_ptr(), self.msgs.len() as _, 0) };
+
+        if res < 0 {
+            Err(Error::last_os_error())
+        } else {
+            Ok(res as usize)
+        }
+    }
+}
+
+impl Default for SendBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debug for SendBatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FmtResult {
+        f.debug_struct("SendBatch")
+            .field("packets", &self.packets.len())
+            .finish()
+    }
+}
+
+fn setsockopt(fd: RawFd, name: libc::c_int, value: libc::c_int) -> Result<(), Error> {
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_UDP,
+            name,
+            ptr::addr_of!(value).cast(),
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+
+    if res < 0 {
+        Err(Error::last_os_error())
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns the segment size of coalesced datagrams, if the kernel attached one.
+unsafe fn gro_segment_size(hdr: &libc::msghdr) -> Option<usize> {
+    let mut cmsg = libc::CMSG_FIRSTHDR(hdr);
+
+    while !cmsg.is_null() {
+        if (*cmsg).cmsg_level == libc::SOL_UDP && (*cmsg).cmsg_type == libc::UDP_GRO {
+            let size = ptr::read_unaligned(libc::CMSG_DATA(cmsg).cast::<libc::c_int>());
+            return usize::try_from(size).ok();
+        }
+
+        cmsg = libc::CMSG_NXTHDR(hdr, cmsg);
+    }
+
+    None
+}
+
+fn from_sockaddr(addr: &libc::sockaddr_storage) -> Option<SocketAddr> {
+    match addr.ss_family as libc::c_int {
+        libc::AF_INET => {
+            let addr =
+                unsafe { &*(addr as *const libc::sockaddr_storage).cast::<libc::sockaddr_in>() };
+            let ip = Ipv4Addr::from(addr.sin_addr.s_addr.to_ne_bytes());
+            Some(SocketAddr::V4(SocketAddrV4::new(
+                ip,
+                u16::from_be(addr.sin_port),
+            )))
+        }
+        libc::AF_INET6 => {
+            let addr =
+                unsafe { &*(addr as *const libc::sockaddr_storage).cast::<libc::sockaddr_in6>() };
+            let ip = Ipv6Addr::from(addr.sin6_addr.s6_addr);
+            Some(SocketAddr::V6(SocketAddrV6::new(
+                ip,
+                u16::from_be(addr.sin6_port),
+                addr.sin6_flowinfo,
+                addr.sin6_scope_id,
+            )))
+        }
+        _ => None,
+    }
+}
+
+fn to_sockaddr(addr: SocketAddr, storage: &mut libc::sockaddr_storage) -> libc::socklen_t {
+    *storage = unsafe { mem::zeroed() };
+
+    match addr {
+        SocketAddr::V4(addr) => {
+            let sin = unsafe {
+                &mut *(storage as *mut libc::sockaddr_storage).cast::<libc::sockaddr_in>()
+            };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_port = addr.port().to_be();
+            sin.sin_addr.s_addr = u32::from_ne_bytes(addr.ip().octets());
+            mem::size_of::<libc::sockaddr_in>() as libc::socklen_t
+        }
+        SocketAddr::V6(addr) => {
+            let sin6 = unsafe {
+                &mut *(storage as *mut libc::sockaddr_storage).cast::<libc::sockaddr_in6>()
+            };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_port = addr.port().to_be();
+            sin6.sin6_flowinfo = addr.flowinfo();
+            sin6.sin6_addr.s6_addr = addr.ip().octets();
+            sin6.sin6_scope_id = addr.scope_id();
+            mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use socks5_proto::Address;
+    use std::time::Duration;
+    use tokio::{net::UdpSocket, time};
+
+    async fn socket(max_pkt_size: usize) -> (AssociatedUdpSocket, SocketAddr) {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        (AssociatedUdpSocket::from((socket, max_pkt_size)), addr)
+    }
+
+    fn payload(idx: usize, len: usize) -> Bytes {
+        (0..len).map(|byte| (idx + byte) as u8).collect()
+    }
+
+    fn header(idx: usize) -> UdpHeader {
+        UdpHeader::new(
+            0,
+            Address::SocketAddress(([10, 0, 0, 1], idx as u16).into()),
+        )
+    }
+
+    /// Receives `count` packets on `socket`, returning their payloads and header addresses in order.
+    async fn recv_all(socket: &AssociatedUdpSocket, count: usize) -> Vec<(Bytes, Address)> {
+        let mut batch = RecvBatch::new(8);
+        let mut pkts = Vec::new();
+
+        while pkts.len() < count {
+            time::timeout(Duration::from_secs(5), socket.recv_batch_from(&mut batch))
+                .await
+                .unwrap()
+                .unwrap();
+
+            pkts.extend(batch.drain().map(|res| {
+                let (pkt, header, _) = res.unwrap();
+                (pkt, header.address)
+            }));
+        }
+
+        assert_eq!(pkts.len(), count);
+        pkts
+    }
+
+    /// Sends packets of the given sizes from `tx` to the sockets in `rx` in turn, and checks each one receives its packets in order.
+    async fn round_trip(
+        tx: &AssociatedUdpSocket,
+        rx: &[(AssociatedUdpSocket, SocketAddr)],
+        sizes: &[usize],
+    ) {
+        let mut batch = SendBatch::new();
+
+        for (idx, size) in sizes.iter().enumerate() {
+            batch.push(payload(idx, *size), &header(idx), rx[idx % rx.len()].1);
+        }
+
+        assert_eq!(tx.send_batch(&mut batch).await.unwrap(), sizes.len());
+        assert!(batch.is_empty());
+
+        for (rx_idx, (socket, _)) in rx.iter().enumerate() {
+            let expected = (0..sizes.len())
+                .filter(|idx| idx % rx.len() == rx_idx)
+                .map(|idx| (payload(idx, sizes[idx]), header(idx).address))
+                .collect::<Vec<_>>();
+
+            let pkts = recv_all(socket, expected.len()).await;
+            assert_eq!(pkts, expected);
+        }
+    }
+
+    /// Packets of mixed sizes, with runs of the same size to be segmented with GSO.
+    fn sizes() -> Vec<usize> {
+        let mut sizes = vec![1, 1200, 1200, 1200, 700, 0, 1400];
+        sizes.extend([100; 80]);
+        sizes.extend([100, 100, 10]);
+        sizes
+    }
+
+    #[tokio::test]
+    async fn round_trip_without_offload() {
+        let (tx, _) = socket(2048).await;
+        let rx = [socket(2048).await, socket(2048).await];
+
+        round_trip(&tx, &rx[..1], &sizes()).await;
+        round_trip(&tx, &rx, &sizes()).await;
+    }
+
+    #[tokio::test]
+    async fn round_trip_with_offload() {
+        let (tx, _) = socket(2048).await;
+        let rx = [socket(2048).await, socket(2048).await];
+
+        // offload is not available on older kernels
+        if tx.set_gso(true).is_err() || rx.iter().any(|(socket, _)| socket.set_gro(true).is_err()) {
+            return;
+        }
+
+        round_trip(&tx, &rx[..1], &sizes()).await;
+        round_trip(&tx, &rx, &sizes()).await;
+        assert!(tx.gso());
+    }
+
+    #[tokio::test]
+    async fn oversized_datagram() {
+        let (tx, _) = socket(2048).await;
+        let (rx, rx_addr) = socket(64).await;
+
+        tx.send_to(payload(0, 100), &header(0), rx_addr)
+            .await
+            .unwrap();
+        tx.send_to(payload(1, 10), &header(1), rx_addr)
+            .await
+            .unwrap();
+
+        let mut batch = RecvBatch::new(8);
+        let mut pkts = Vec::new();
+
+        while pkts.is_empty() {
+            rx.recv_batch_from(&mut batch).await.unwrap();
+            pkts.extend(batch.drain().map(|res| res.unwrap().0));
+        }
+
+        assert_eq!(pkts, [payload(1, 10)]);
+        assert_eq!(batch.truncated(), 1);
+    }
+
+    #[tokio::test]
+    async fn partial_send() {
+        let (tx, _) = socket(2048).await;
+        let (rx, rx_addr) = socket(2048).await;
+
+        // an IPv6 destination can not be reached from an IPv4 socket
+        let mut batch = SendBatch::new();
+        batch.push(payload(0, 10), &header(0), rx_addr);
+        batch.push(payload(1, 10), &header(1), "[::1]:1".parse().unwrap());
+        batch.push(payload(2, 10), &header(2), rx_addr);
+
+        assert_eq!(tx.send_batch(&mut batch).await.unwrap(), 1);
+        assert_eq!(batch.len(), 2);
+
+        assert!(tx.send_batch(&mut batch).await.is_err());
+        assert_eq!(batch.len(), 1);
+
+        assert_eq!(tx.send_batch(&mut batch).await.unwrap(), 1);
+        assert!(batch.is_empty());
+
+        let pkts = recv_all(&rx, 2).await;
+        assert!(
+            pkts == [
+                (payload(0, 10), header(0).address),
+                (payload(2, 10), header(2).address)
+            ]
+        );
+    }
+}
@@ -1,5 +1,6 @@
 //! Socks5 command type `Bind`
 
+use super::BufferedStream;
 use socks5_proto::{Address, Reply, Response};
 use std::{
     io::Error,
@@ -19,10 +20,12 @@ use tokio::{
 ///
 /// By [`wait_request()`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Authenticated.html#method.wait_request) on an [`Authenticated`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Authenticated.html) from SOCKS5 client, you may get a `Bind<NeedFirstReply>`. After replying the client 2 times using [`reply()`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Bind.html#method.reply), you will get a `Bind<Ready>`, which can be used as a regular async TCP stream.
 ///
-/// A `Bind<S>` can be converted to a regular tokio [`TcpStream`](https://docs.rs/tokio/latest/tokio/net/struct.TcpStream.html) by using the `From` trait.
+/// Generic type `<T>` is the underlying stream, which defaults to a tokio [`TcpStream`](https://docs.rs/tokio/latest/tokio/net/struct.TcpStream.html). Socket option helpers are only available when the stream is a `TcpStream`.
+///
+/// A `Bind<S>` can be converted to a regular tokio [`TcpStream`](https://docs.rs/tokio/latest/tokio/net/struct.TcpStream.html) by using the `From` trait, or into the underlying stream with `into_inner()`. Both discard the bytes already buffered, which [`into_buffered()`](https://docs.rs/socks5-server/latest/socks5_server/connection/bind/struct.Bind.html#method.into_buffered) keeps.
 #[derive(Debug)]
-pub struct Bind<S> {
-    stream: TcpStream,
+pub struct Bind<S, T = TcpStream> {
+    stream: BufferedStream<T>,
     _state: PhantomData<S>,
 }
 
@@ -38,30 +41,35 @@ pub struct NeedSecondReply;
 #[derive(Debug)]
 pub struct Ready;
 
-impl Bind<NeedFirstReply> {
+impl<T> Bind<NeedFirstReply, T> {
     #[inline]
-    pub(super) fn new(stream: TcpStream) -> Self {
+    pub(super) fn new(stream: BufferedStream<T>) -> Self {
         Self {
             stream,
             _state: PhantomData,
         }
     }
+}
 
+impl<T> Bind<NeedFirstReply, T>
+where
+    T: AsyncWrite + Unpin,
+{
     /// Reply to the SOCKS5 client with the given reply and address.
     ///
-    /// If encountered an error while writing the reply, the error alongside the original `TcpStream` is returned.
+    /// If encountered an error while writing the reply, the error alongside the original stream is returned. Bytes already buffered are discarded, as the connection cannot be used for SOCKS5 anymore.
     pub async fn reply(
         mut self,
         reply: Reply,
         addr: Address,
-    ) -> Result<Bind<NeedSecondReply>, (Error, TcpStream)> {
+    ) -> Result<Bind<NeedSecondReply, T>, (Error, T)> {
         let resp = Response::new(reply, addr);
 
         if let Err(err) = resp.write_to(&mut self.stream).await {
-            return Err((err, self.stream));
+            return Err((err, self.stream.into_parts().0));
         }
 
-        Ok(Bind::<NeedSecondReply>::new(self.stream))
+        Ok(Bind::<NeedSecondReply, T>::new(self.stream))
     }
 
     /// Causes the other peer to receive a read of length 0, indicating that no more data will be sent. This only closes the stream in one direction.
@@ -69,17 +77,19 @@ impl Bind<NeedFirstReply> {
     pub async fn shutdown(&mut self) -> Result<(), Error> {
         self.stream.shutdown().await
     }
+}
 
+impl Bind<NeedFirstReply, TcpStream> {
     /// Returns the local address that this stream is bound to.
     #[inline]
     pub fn local_addr(&self) -> Result<SocketAddr, Error> {
-        self.stream.local_addr()
+        self.stream.get_ref().local_addr()
     }
 
     /// Returns the remote address that this stream is connected to.
     #[inline]
     pub fn peer_addr(&self) -> Result<SocketAddr, Error> {
-        self.stream.peer_addr()
+        self.stream.get_ref().peer_addr()
     }
 
     /// Reads the linger duration for this socket by getting the `SO_LINGER` option.
@@ -87,7 +97,7 @@ impl Bind<NeedFirstReply> {
     /// For more information about this option, see [set_linger](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Bind.html#method.set_linger).
     #[inline]
     pub fn linger(&self) -> Result<Option<Duration>, Error> {
-        self.stream.linger()
+        self.stream.get_ref().linger()
     }
 
     /// Sets the linger duration of this socket by setting the `SO_LINGER` option.
@@ -96,8 +106,9 @@ impl Bind<NeedFirstReply> {
     ///
     /// If `SO_LINGER` is not specified, and the stream is closed, the system handles the call in a way that allows the process to continue as quickly as possible.
     #[inline]
+    #[allow(deprecated)]
     pub fn set_linger(&self, dur: Option<Duration>) -> Result<(), Error> {
-        self.stream.set_linger(dur)
+        self.stream.get_ref().set_linger(dur)
     }
 
     /// Gets the value of the `TCP_NODELAY` option on this socket.
@@ -105,55 +116,60 @@ impl Bind<NeedFirstReply> {
     /// For more information about this option, see [set_nodelay](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Bind.html#method.set_nodelay).
     #[inline]
     pub fn nodelay(&self) -> Result<bool, Error> {
-        self.stream.nodelay()
+        self.stream.get_ref().nodelay()
     }
 
     /// Sets the value of the `TCP_NODELAY` option on this socket.
     ///
     /// If set, this option disables the Nagle algorithm. This means that segments are always sent as soon as possible, even if there is only a small amount of data. When not set, data is buffered until there is a sufficient amount to send out, thereby avoiding the frequent sending of small packets.
     pub fn set_nodelay(&self, nodelay: bool) -> Result<(), Error> {
-        self.stream.set_nodelay(nodelay)
+        self.stream.get_ref().set_nodelay(nodelay)
     }
 
     /// Gets the value of the `IP_TTL` option for this socket.
     ///
     /// For more information about this option, see [set_ttl](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Bind.html#method.set_ttl).
     pub fn ttl(&self) -> Result<u32, Error> {
-        self.stream.ttl()
+        self.stream.get_ref().ttl()
     }
 
     /// Sets the value for the `IP_TTL` option on this socket.
     ///
     /// This value sets the time-to-live field that is used in every packet sent from this socket.
     pub fn set_ttl(&self, ttl: u32) -> Result<(), Error> {
-        self.stream.set_ttl(ttl)
+        self.stream.get_ref().set_ttl(ttl)
     }
 }
 
-impl Bind<NeedSecondReply> {
+impl<T> Bind<NeedSecondReply, T> {
     #[inline]
-    fn new(stream: TcpStream) -> Self {
+    fn new(stream: BufferedStream<T>) -> Self {
         Self {
             stream,
             _state: PhantomData,
         }
     }
+}
 
+impl<T> Bind<NeedSecondReply, T>
+where
+    T: AsyncWrite + Unpin,
+{
     /// Reply to the SOCKS5 client with the given reply and address.
     ///
-    /// If encountered an error while writing the reply, the error alongside the original `TcpStream` is returned.
+    /// If encountered an error while writing the reply, the error alongside the original stream is returned. Bytes already buffered are discarded, as the connection cannot be used for SOCKS5 anymore.
     pub async fn reply(
         mut self,
         reply: Reply,
         addr: Address,
-    ) -> Result<Bind<Ready>, (Error, TcpStream)> {
+    ) -> Result<Bind<Ready, T>, (Error, T)> {
         let resp = Response::new(reply, addr);
 
         if let Err(err) = resp.write_to(&mut self.stream).await {
-            return Err((err, self.stream));
+            return Err((err, self.stream.into_parts().0));
         }
 
-        Ok(Bind::<Ready>::new(self.stream))
+        Ok(Bind::<Ready, T>::new(self.stream))
     }
 
     /// Causes the other peer to receive a read of length 0, indicating that no more data will be sent. This only closes the stream in one direction.
@@ -161,17 +177,19 @@ impl Bind<NeedSecondReply> {
     pub async fn shutdown(&mut self) -> Result<(), Error> {
         self.stream.shutdown().await
     }
+}
 
+impl Bind<NeedSecondReply, TcpStream> {
     /// Returns the local address that this stream is bound to.
     #[inline]
     pub fn local_addr(&self) -> Result<SocketAddr, Error> {
-        self.stream.local_addr()
+        self.stream.get_ref().local_addr()
     }
 
     /// Returns the remote address that this stream is connected to.
     #[inline]
     pub fn peer_addr(&self) -> Result<SocketAddr, Error> {
-        self.stream.peer_addr()
+        self.stream.get_ref().peer_addr()
     }
 
     /// Reads the linger duration for this socket by getting the `SO_LINGER` option.
@@ -179,7 +197,7 @@ impl Bind<NeedSecondReply> {
     /// For more information about this option, see [set_linger](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Bind.html#method.set_linger).
     #[inline]
     pub fn linger(&self) -> Result<Option<Duration>, Error> {
-        self.stream.linger()
+        self.stream.get_ref().linger()
     }
 
     /// Sets the linger duration of this socket by setting the `SO_LINGER` option.
@@ -188,8 +206,9 @@ impl Bind<NeedSecondReply> {
     ///
     /// If `SO_LINGER` is not specified, and the stream is closed, the system handles the call in a way that allows the process to continue as quickly as possible.
     #[inline]
+    #[allow(deprecated)]
     pub fn set_linger(&self, dur: Option<Duration>) -> Result<(), Error> {
-        self.stream.set_linger(dur)
+        self.stream.get_ref().set_linger(dur)
     }
 
     /// Gets the value of the `TCP_NODELAY` option on this socket.
@@ -197,34 +216,34 @@ impl Bind<NeedSecondReply> {
     /// For more information about this option, see [set_nodelay](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Bind.html#method.set_nodelay).
     #[inline]
     pub fn nodelay(&self) -> Result<bool, Error> {
-        self.stream.nodelay()
+        self.stream.get_ref().nodelay()
     }
 
     /// Sets the value of the `TCP_NODELAY` option on this socket.
     ///
     /// If set, this option disables the Nagle algorithm. This means that segments are always sent as soon as possible, even if there is only a small amount of data. When not set, data is buffered until there is a sufficient amount to send out, thereby avoiding the frequent sending of small packets.
     pub fn set_nodelay(&self, nodelay: bool) -> Result<(), Error> {
-        self.stream.set_nodelay(nodelay)
+        self.stream.get_ref().set_nodelay(nodelay)
     }
 
     /// Gets the value of the `IP_TTL` option for this socket.
     ///
     /// For more information about this option, see [set_ttl](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Bind.html#method.set_ttl).
     pub fn ttl(&self) -> Result<u32, Error> {
-        self.stream.ttl()
+        self.stream.get_ref().ttl()
     }
 
     /// Sets the value for the `IP_TTL` option on this socket.
     ///
     /// This value sets the time-to-live field that is used in every packet sent from this socket.
     pub fn set_ttl(&self, ttl: u32) -> Result<(), Error> {
-        self.stream.set_ttl(ttl)
+        self.stream.get_ref().set_ttl(ttl)
     }
 }
 
-impl Bind<Ready> {
+impl<T> Bind<Ready, T> {
     #[inline]
-    fn new(stream: TcpStream) -> Self {
+    fn new(stream: BufferedStream<T>) -> Self {
         Self {
             stream,
             _state: PhantomData,
@@ -232,23 +251,26 @@ impl Bind<Ready> {
     }
 }
 
-impl Deref for Bind<Ready> {
-    type Target = TcpStream;
+impl<T> Deref for Bind<Ready, T> {
+    type Target = T;
 
     #[inline]
     fn deref(&self) -> &Self::Target {
-        &self.stream
+        self.stream.get_ref()
     }
 }
 
-impl DerefMut for Bind<Ready> {
+impl<T> DerefMut for Bind<Ready, T> {
     #[inline]
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.stream
+        self.stream.get_mut()
     }
 }
 
-impl AsyncRead for Bind<Ready> {
+impl<T> AsyncRead for Bind<Ready, T>
+where
+    T: AsyncRead + Unpin,
+{
     #[inline]
     fn poll_read(
         mut self: Pin<&mut Self>,
@@ -259,7 +281,10 @@ impl AsyncRead for Bind<Ready> {
     }
 }
 
-impl AsyncWrite for Bind<Ready> {
+impl<T> AsyncWrite for Bind<Ready, T>
+where
+    T: AsyncWrite + Unpin,
+{
     #[inline]
     fn poll_write(
         mut self: Pin<&mut Self>,
@@ -280,9 +305,44 @@ impl AsyncWrite for Bind<Ready> {
     }
 }
 
-impl<S> From<Bind<S>> for TcpStream {
+impl<S, T> Bind<S, T> {
+    /// Gets a reference to the underlying stream.
+    ///
+    /// Note that reading directly from the underlying stream skips the bytes already buffered during the handshake.
+    #[inline]
+    pub fn get_ref(&self) -> &T {
+        self.stream.get_ref()
+    }
+
+    /// Gets a mutable reference to the underlying stream.
+    ///
+    /// Note that reading directly from the underlying stream skips the bytes already buffered during the handshake.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.stream.get_mut()
+    }
+
+    /// Consumes the `Bind`, returning the underlying stream.
+    ///
+    /// Any bytes already buffered during the handshake are discarded. Use [`into_buffered()`](https://docs.rs/socks5-server/latest/socks5_server/connection/bind/struct.Bind.html#method.into_buffered) to keep them.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.stream.into_parts().0
+    }
+
+    /// Consumes the `Bind`, returning the underlying stream alongside the bytes already buffered during the handshake.
+    #[inline]
+    pub fn into_buffered(self) -> BufferedStream<T> {
+        self.stream
+    }
+}
+
+/// Converts the `Bind` into its `TcpStream`.
+///
+/// Like `into_inner()`, this discards any bytes the client sent after its request that are already buffered. Use [`into_buffered()`](https://docs.rs/socks5-server/latest/socks5_server/connection/bind/struct.Bind.html#method.into_buffered) to keep them.
+impl<S> From<Bind<S, TcpStream>> for TcpStream {
     #[inline]
-    fn from(conn: Bind<S>) -> Self {
-        conn.stream
+    fn from(conn: Bind<S, TcpStream>) -> Self {
+        conn.stream.into_parts().0
     }
 }
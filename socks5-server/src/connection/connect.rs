@@ -1,5 +1,10 @@
 //! Socks5 command type `Connect`
 
+use super::BufferedStream;
+#[cfg(feature = "relay")]
+use crate::relay::{self, RelayConfig, RelayStats};
+#[cfg(feature = "upstream")]
+use crate::upstream::{self, Upstream};
 use socks5_proto::{Address, Reply, Response};
 use std::{
     io::Error,
@@ -19,10 +24,12 @@ use tokio::{
 ///
 /// By [`wait_request()`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Authenticated.html#method.wait_request) on an [`Authenticated`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Authenticated.html) from SOCKS5 client, you may get a `Connect<NeedReply>`. After replying the client using [`reply()`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Connect.html#method.reply), you will get a `Connect<Ready>`, which can be used as a regular async TCP stream.
 ///
-/// A `Connect<S>` can be converted to a regular tokio [`TcpStream`](https://docs.rs/tokio/latest/tokio/net/struct.TcpStream.html) by using the `From` trait.
+/// Generic type `<T>` is the underlying stream, which defaults to a tokio [`TcpStream`](https://docs.rs/tokio/latest/tokio/net/struct.TcpStream.html). Socket option helpers are only available when the stream is a `TcpStream`.
+///
+/// A `Connect<S>` can be converted to a regular tokio [`TcpStream`](https://docs.rs/tokio/latest/tokio/net/struct.TcpStream.html) by using the `From` trait, or into the underlying stream with `into_inner()`. Both discard the bytes already buffered, which [`into_buffered()`](https://docs.rs/socks5-server/latest/socks5_server/connection/connect/struct.Connect.html#method.into_buffered) keeps.
 #[derive(Debug)]
-pub struct Connect<S> {
-    stream: TcpStream,
+pub struct Connect<S, T = TcpStream> {
+    stream: BufferedStream<T>,
     _state: PhantomData<S>,
 }
 
@@ -34,30 +41,35 @@ pub struct NeedReply;
 #[derive(Debug)]
 pub struct Ready;
 
-impl Connect<NeedReply> {
+impl<T> Connect<NeedReply, T> {
     #[inline]
-    pub(super) fn new(stream: TcpStream) -> Self {
+    pub(super) fn new(stream: BufferedStream<T>) -> Self {
         Self {
             stream,
             _state: PhantomData,
         }
     }
+}
 
+impl<T> Connect<NeedReply, T>
+where
+    T: AsyncWrite + Unpin,
+{
     /// Reply to the SOCKS5 client with the given reply and address.
     ///
-    /// If encountered an error while writing the reply, the error alongside the original `TcpStream` is returned.
+    /// If encountered an error while writing the reply, the error alongside the original stream is returned. Bytes already buffered are discarded, as the connection cannot be used for SOCKS5 anymore.
     pub async fn reply(
         mut self,
         reply: Reply,
         addr: Address,
-    ) -> Result<Connect<Ready>, (Error, TcpStream)> {
+    ) -> Result<Connect<Ready, T>, (Error, T)> {
         let resp = Response::new(reply, addr);
 
         if let Err(err) = resp.write_to(&mut self.stream).await {
-            return Err((err, self.stream));
+            return Err((err, self.stream.into_parts().0));
         }
 
-        Ok(Connect::<Ready>::new(self.stream))
+        Ok(Connect::<Ready, T>::new(self.stream))
     }
 
     /// Causes the other peer to receive a read of length 0, indicating that no more data will be sent. This only closes the stream in one direction.
@@ -66,16 +78,34 @@ impl Connect<NeedReply> {
         self.stream.shutdown().await
     }
 
+    /// Connects to `addr` through `upstream`, and replies to the client with the outcome.
+    ///
+    /// See [`upstream::connect()`](https://docs.rs/socks5-server/latest/socks5_server/upstream/fn.connect.html).
+    #[cfg(feature = "upstream")]
+    #[inline]
+    pub async fn connect_upstream<U>(
+        self,
+        addr: Address,
+        upstream: &U,
+    ) -> Result<(Connect<Ready, T>, U::Stream), (Error, T)>
+    where
+        U: Upstream + ?Sized,
+    {
+        upstream::connect(self, addr, upstream).await
+    }
+}
+
+impl Connect<NeedReply, TcpStream> {
     /// Returns the local address that this stream is bound to.
     #[inline]
     pub fn local_addr(&self) -> Result<SocketAddr, Error> {
-        self.stream.local_addr()
+        self.stream.get_ref().local_addr()
     }
 
     /// Returns the remote address that this stream is connected to.
     #[inline]
     pub fn peer_addr(&self) -> Result<SocketAddr, Error> {
-        self.stream.peer_addr()
+        self.stream.get_ref().peer_addr()
     }
 
     /// Reads the linger duration for this socket by getting the `SO_LINGER` option.
@@ -83,7 +113,7 @@ impl Connect<NeedReply> {
     /// For more information about this option, see [set_linger](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Connect.html#method.set_linger).
     #[inline]
     pub fn linger(&self) -> Result<Option<Duration>, Error> {
-        self.stream.linger()
+        self.stream.get_ref().linger()
     }
 
     /// Sets the linger duration of this socket by setting the `SO_LINGER` option.
@@ -92,8 +122,9 @@ impl Connect<NeedReply> {
     ///
     /// If `SO_LINGER` is not specified, and the stream is closed, the system handles the call in a way that allows the process to continue as quickly as possible.
     #[inline]
+    #[allow(deprecated)]
     pub fn set_linger(&self, dur: Option<Duration>) -> Result<(), Error> {
-        self.stream.set_linger(dur)
+        self.stream.get_ref().set_linger(dur)
     }
 
     /// Gets the value of the `TCP_NODELAY` option on this socket.
@@ -101,34 +132,34 @@ impl Connect<NeedReply> {
     /// For more information about this option, see [set_nodelay](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Connect.html#method.set_nodelay).
     #[inline]
     pub fn nodelay(&self) -> Result<bool, Error> {
-        self.stream.nodelay()
+        self.stream.get_ref().nodelay()
     }
 
     /// Sets the value of the `TCP_NODELAY` option on this socket.
     ///
     /// If set, this option disables the Nagle algorithm. This means that segments are always sent as soon as possible, even if there is only a small amount of data. When not set, data is buffered until there is a sufficient amount to send out, thereby avoiding the frequent sending of small packets.
     pub fn set_nodelay(&self, nodelay: bool) -> Result<(), Error> {
-        self.stream.set_nodelay(nodelay)
+        self.stream.get_ref().set_nodelay(nodelay)
     }
 
     /// Gets the value of the `IP_TTL` option for this socket.
     ///
     /// For more information about this option, see [set_ttl](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Connect.html#method.set_ttl).
     pub fn ttl(&self) -> Result<u32, Error> {
-        self.stream.ttl()
+        self.stream.get_ref().ttl()
     }
 
     /// Sets the value for the `IP_TTL` option on this socket.
     ///
     /// This value sets the time-to-live field that is used in every packet sent from this socket.
     pub fn set_ttl(&self, ttl: u32) -> Result<(), Error> {
-        self.stream.set_ttl(ttl)
+        self.stream.get_ref().set_ttl(ttl)
     }
 }
 
-impl Connect<Ready> {
+impl<T> Connect<Ready, T> {
     #[inline]
-    fn new(stream: TcpStream) -> Self {
+    fn new(stream: BufferedStream<T>) -> Self {
         Self {
             stream,
             _state: PhantomData,
@@ -136,23 +167,60 @@ impl Connect<Ready> {
     }
 }
 
-impl Deref for Connect<Ready> {
-    type Target = TcpStream;
+#[cfg(feature = "relay")]
+impl<T> Connect<Ready, T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Relays data between the client and `remote` until both directions are finished, an error occurs or the idle timeout elapses.
+    ///
+    /// Returns the number of bytes transferred in each direction and why the relay ended. See [`relay::relay()`](https://docs.rs/socks5-server/latest/socks5_server/relay/fn.relay.html).
+    #[inline]
+    pub async fn relay<R>(&mut self, remote: &mut R, config: RelayConfig) -> RelayStats
+    where
+        R: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    {
+        relay::relay(self, remote, config).await
+    }
+}
+
+#[cfg(feature = "splice")]
+impl<T> Connect<Ready, T>
+where
+    T: AsyncRead + AsyncWrite + Unpin + 'static,
+{
+    /// Relays data between the client and `remote` with `splice(2)` if both are TCP streams on Linux, or with a buffered copy otherwise.
+    ///
+    /// See [`relay::splice()`](https://docs.rs/socks5-server/latest/socks5_server/relay/fn.splice.html).
+    #[inline]
+    pub async fn splice<R>(&mut self, remote: &mut R, config: RelayConfig) -> RelayStats
+    where
+        R: AsyncRead + AsyncWrite + Unpin + 'static,
+    {
+        relay::splice(self, remote, config).await
+    }
+}
+
+impl<T> Deref for Connect<Ready, T> {
+    type Target = T;
 
     #[inline]
     fn deref(&self) -> &Self::Target {
-        &self.stream
+        self.stream.get_ref()
     }
 }
 
-impl DerefMut for Connect<Ready> {
+impl<T> DerefMut for Connect<Ready, T> {
     #[inline]
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.stream
+        self.stream.get_mut()
     }
 }
 
-impl AsyncRead for Connect<Ready> {
+impl<T> AsyncRead for Connect<Ready, T>
+where
+    T: AsyncRead + Unpin,
+{
     #[inline]
     fn poll_read(
         mut self: Pin<&mut Self>,
@@ -163,7 +231,10 @@ impl AsyncRead for Connect<Ready> {
     }
 }
 
-impl AsyncWrite for Connect<Ready> {
+impl<T> AsyncWrite for Connect<Ready, T>
+where
+    T: AsyncWrite + Unpin,
+{
     #[inline]
     fn poll_write(
         mut self: Pin<&mut Self>,
@@ -184,9 +255,50 @@ impl AsyncWrite for Connect<Ready> {
     }
 }
 
-impl<S> From<Connect<S>> for TcpStream {
+impl<S, T> Connect<S, T> {
+    #[cfg(all(feature = "splice", target_os = "linux"))]
+    #[inline]
+    pub(crate) fn stream_mut(&mut self) -> &mut BufferedStream<T> {
+        &mut self.stream
+    }
+
+    /// Gets a reference to the underlying stream.
+    ///
+    /// Note that reading directly from the underlying stream skips the bytes already buffered during the handshake.
+    #[inline]
+    pub fn get_ref(&self) -> &T {
+        self.stream.get_ref()
+    }
+
+    /// Gets a mutable reference to the underlying stream.
+    ///
+    /// Note that reading directly from the underlying stream skips the bytes already buffered during the handshake.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.stream.get_mut()
+    }
+
+    /// Consumes the `Connect`, returning the underlying stream.
+    ///
+    /// Any bytes already buffered during the handshake are discarded. Use [`into_buffered()`](https://docs.rs/socks5-server/latest/socks5_server/connection/connect/struct.Connect.html#method.into_buffered) to keep them.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.stream.into_parts().0
+    }
+
+    /// Consumes the `Connect`, returning the underlying stream alongside the bytes already buffered during the handshake.
+    #[inline]
+    pub fn into_buffered(self) -> BufferedStream<T> {
+        self.stream
+    }
+}
+
+/// Converts the `Connect` into its `TcpStream`.
+///
+/// Like `into_inner()`, this discards any bytes the client sent after its request that are already buffered. Use [`into_buffered()`](https://docs.rs/socks5-server/latest/socks5_server/connection/connect/struct.Connect.html#method.into_buffered) to keep them.
+impl<S> From<Connect<S, TcpStream>> for TcpStream {
     #[inline]
-    fn from(conn: Connect<S>) -> Self {
-        conn.stream
+    fn from(conn: Connect<S, TcpStream>) -> Self {
+        conn.stream.into_parts().0
     }
 }
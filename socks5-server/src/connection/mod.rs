@@ -3,72 +3,141 @@
 //! [`accept()`](https://docs.rs/socks5-server/latest/socks5_server/struct.Server.html#method.accept) on a [`Server`](https://docs.rs/socks5-server/latest/socks5_server/struct.Server.html) creates a [`IncomingConnection`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.IncomingConnection.html), which is the entry point of processing a SOCKS5 connection.
 
 use self::{associate::Associate, bind::Bind, connect::Connect};
-use crate::AuthAdaptor;
+use crate::auth::{AuthContext, Negotiator};
 use socks5_proto::{
     handshake::{
         Method as HandshakeMethod, Request as HandshakeRequest, Response as HandshakeResponse,
     },
     Address, Command as ProtocolCommand, Error, ProtocolError, Request,
 };
-use std::{io::Error as IoError, net::SocketAddr, time::Duration};
-use tokio::{io::AsyncWriteExt, net::TcpStream};
+use std::{io::Error as IoError, net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+
+mod stream;
 
 pub mod associate;
 pub mod bind;
 pub mod connect;
 
-/// A freshly established TCP connection.
+pub use self::stream::BufferedStream;
+
+/// A freshly established connection.
 ///
 /// This may not be a valid SOCKS5 connection. You should call [`authenticate()`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.IncomingConnection.html#method.authenticate) to perform a SOCKS5 authentication handshake.
 ///
-/// It can also be converted back into a raw tokio [`TcpStream`](https://docs.rs/tokio/latest/tokio/net/struct.TcpStream.html) with `From` trait.
-pub struct IncomingConnection<O> {
-    stream: TcpStream,
-    auth: AuthAdaptor<O>,
+/// Generic type `<T>` is the underlying stream, which defaults to a tokio [`TcpStream`](https://docs.rs/tokio/latest/tokio/net/struct.TcpStream.html). Any `AsyncRead + AsyncWrite + Unpin` stream (TLS, Unix socket, in-memory duplex, etc.) can be used. Socket option helpers are only available when the stream is a `TcpStream`.
+///
+/// Generic type `<U>` is the stream after the chosen authentication method [encapsulates](https://docs.rs/socks5-server/latest/socks5_server/auth/trait.Auth.html#tymethod.encapsulate) it, which is the same as `<T>` by default.
+///
+/// It can also be converted back into a raw tokio [`TcpStream`](https://docs.rs/tokio/latest/tokio/net/struct.TcpStream.html) with `From` trait, or into the underlying stream with [`into_inner()`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.IncomingConnection.html#method.into_inner).
+pub struct IncomingConnection<O, T = TcpStream, U = T> {
+    stream: BufferedStream<T>,
+    auth: Arc<Negotiator<O, T, U>>,
+    context: AuthContext,
 }
 
-impl<O> IncomingConnection<O> {
+impl<O, T, U> IncomingConnection<O, T, U> {
+    /// Creates a new `IncomingConnection` from an established stream and a set of authentication adaptors.
+    ///
+    /// This is useful when the stream is not accepted by a [`Server`](https://docs.rs/socks5-server/latest/socks5_server/struct.Server.html), e.g. a TLS stream or a Unix socket.
+    #[inline]
+    pub fn new(stream: T, auth: Arc<Negotiator<O, T, U>>) -> Self {
+        Self {
+            stream: BufferedStream::new(stream),
+            auth,
+            context: AuthContext::new(),
+        }
+    }
+
+    /// Returns the context passed to the [`Auth`](https://docs.rs/socks5-server/latest/socks5_server/auth/trait.Auth.html) adaptors during authentication.
     #[inline]
-    pub(crate) fn new(stream: TcpStream, auth: AuthAdaptor<O>) -> Self {
-        Self { stream, auth }
+    pub fn context(&self) -> &AuthContext {
+        &self.context
     }
 
-    /// Perform a SOCKS5 authentication handshake using the given [`Auth`](https://docs.rs/socks5-server/latest/socks5_server/auth/trait.Auth.html) adapter.
+    /// Returns a mutable reference to the context passed to the [`Auth`](https://docs.rs/socks5-server/latest/socks5_server/auth/trait.Auth.html) adaptors during authentication.
     ///
-    /// If the handshake succeeds, an [`Authenticated`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Authenticated.html) alongs with the output of the [`Auth`](https://docs.rs/socks5-server/latest/socks5_server/auth/trait.Auth.html) adapter is returned. Otherwise, the error and the original [`TcpStream`](https://docs.rs/tokio/latest/tokio/net/struct.TcpStream.html) is returned.
+    /// This can be used to fill in the connection addresses of a non-TCP stream, or to insert extensions before authenticating.
+    #[inline]
+    pub fn context_mut(&mut self) -> &mut AuthContext {
+        &mut self.context
+    }
+
+    /// Gets a reference to the underlying stream.
+    #[inline]
+    pub fn get_ref(&self) -> &T {
+        self.stream.get_ref()
+    }
+
+    /// Gets a mutable reference to the underlying stream.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.stream.get_mut()
+    }
+
+    /// Consumes the `IncomingConnection`, returning the underlying stream.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.stream.into_parts().0
+    }
+}
+
+impl<O, T, U> IncomingConnection<O, T, U>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Perform a SOCKS5 authentication handshake using the registered [`Auth`](https://docs.rs/socks5-server/latest/socks5_server/auth/trait.Auth.html) adapters.
+    ///
+    /// The most preferred adaptor whose method is offered by the client is chosen. See [`Negotiator`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.Negotiator.html).
+    ///
+    /// If the handshake succeeds, an [`Authenticated`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Authenticated.html) alongs with the chosen handshake method and the output of the chosen [`Auth`](https://docs.rs/socks5-server/latest/socks5_server/auth/trait.Auth.html) adapter is returned. Otherwise, the error and the original stream is returned.
+    ///
+    /// The returned `Authenticated` holds the stream [encapsulated](https://docs.rs/socks5-server/latest/socks5_server/auth/trait.Auth.html#tymethod.encapsulate) by the chosen adapter, so the request and all following data are carried on it.
     ///
     /// Note that this method will not implicitly close the connection even if the handshake failed.
-    pub async fn authenticate(mut self) -> Result<(Authenticated, O), (Error, TcpStream)> {
-        let req = match HandshakeRequest::read_from(&mut self.stream).await {
+    pub async fn authenticate(
+        mut self,
+    ) -> Result<(Authenticated<U>, HandshakeMethod, O), (Error, T)> {
+        let req = match self.stream.read_message(HandshakeRequest::decode).await {
             Ok(req) => req,
-            Err(err) => return Err((err, self.stream)),
+            Err(err) => return Err((err, self.stream.into_parts().0)),
         };
-        let chosen_method = self.auth.as_handshake_method();
 
-        if req.methods.contains(&chosen_method) {
+        self.context.set_methods(req.methods.clone());
+
+        if let Some(auth) = self.auth.select(&self.context) {
+            let chosen_method = auth.as_handshake_method();
             let resp = HandshakeResponse::new(chosen_method);
 
             if let Err(err) = resp.write_to(&mut self.stream).await {
-                return Err((Error::Io(err), self.stream));
+                return Err((Error::Io(err), self.stream.into_parts().0));
             }
 
-            let output = self.auth.execute(&mut self.stream).await;
+            let output = auth.execute(&mut self.stream, &mut self.context).await;
+            let stream = auth.encapsulate(self.stream, &mut self.context);
 
-            Ok((Authenticated::new(self.stream), output))
+            Ok((
+                Authenticated::new(stream, self.context),
+                chosen_method,
+                output,
+            ))
         } else {
             let resp = HandshakeResponse::new(HandshakeMethod::UNACCEPTABLE);
 
             if let Err(err) = resp.write_to(&mut self.stream).await {
-                return Err((Error::Io(err), self.stream));
+                return Err((Error::Io(err), self.stream.into_parts().0));
             }
 
             Err((
                 Error::Protocol(ProtocolError::NoAcceptableHandshakeMethod {
                     version: socks5_proto::SOCKS_VERSION,
-                    chosen_method,
+                    chosen_method: HandshakeMethod::UNACCEPTABLE,
                     methods: req.methods,
                 }),
-                self.stream,
+                self.stream.into_parts().0,
             ))
         }
     }
@@ -78,17 +147,19 @@ impl<O> IncomingConnection<O> {
     pub async fn shutdown(&mut self) -> Result<(), IoError> {
         self.stream.shutdown().await
     }
+}
 
+impl<O, U> IncomingConnection<O, TcpStream, U> {
     /// Returns the local address that this stream is bound to.
     #[inline]
     pub fn local_addr(&self) -> Result<SocketAddr, IoError> {
-        self.stream.local_addr()
+        self.stream.get_ref().local_addr()
     }
 
     /// Returns the remote address that this stream is connected to.
     #[inline]
     pub fn peer_addr(&self) -> Result<SocketAddr, IoError> {
-        self.stream.peer_addr()
+        self.stream.get_ref().peer_addr()
     }
 
     /// Reads the linger duration for this socket by getting the `SO_LINGER` option.
@@ -96,7 +167,7 @@ impl<O> IncomingConnection<O> {
     /// For more information about this option, see [set_linger](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.IncomingConnection.html#method.set_linger).
     #[inline]
     pub fn linger(&self) -> Result<Option<Duration>, IoError> {
-        self.stream.linger()
+        self.stream.get_ref().linger()
     }
 
     /// Sets the linger duration of this socket by setting the `SO_LINGER` option.
@@ -105,8 +176,9 @@ impl<O> IncomingConnection<O> {
     ///
     /// If `SO_LINGER` is not specified, and the stream is closed, the system handles the call in a way that allows the process to continue as quickly as possible.
     #[inline]
+    #[allow(deprecated)]
     pub fn set_linger(&self, dur: Option<Duration>) -> Result<(), IoError> {
-        self.stream.set_linger(dur)
+        self.stream.get_ref().set_linger(dur)
     }
 
     /// Gets the value of the `TCP_NODELAY` option on this socket.
@@ -114,75 +186,124 @@ impl<O> IncomingConnection<O> {
     /// For more information about this option, see [set_nodelay](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.IncomingConnection.html#method.set_nodelay).
     #[inline]
     pub fn nodelay(&self) -> Result<bool, IoError> {
-        self.stream.nodelay()
+        self.stream.get_ref().nodelay()
     }
 
     /// Sets the value of the `TCP_NODELAY` option on this socket.
     ///
     /// If set, this option disables the Nagle algorithm. This means that segments are always sent as soon as possible, even if there is only a small amount of data. When not set, data is buffered until there is a sufficient amount to send out, thereby avoiding the frequent sending of small packets.
     pub fn set_nodelay(&self, nodelay: bool) -> Result<(), IoError> {
-        self.stream.set_nodelay(nodelay)
+        self.stream.get_ref().set_nodelay(nodelay)
     }
 
     /// Gets the value of the `IP_TTL` option for this socket.
     ///
     /// For more information about this option, see [set_ttl](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.IncomingConnection.html#method.set_ttl).
     pub fn ttl(&self) -> Result<u32, IoError> {
-        self.stream.ttl()
+        self.stream.get_ref().ttl()
     }
 
     /// Sets the value for the `IP_TTL` option on this socket.
     ///
     /// This value sets the time-to-live field that is used in every packet sent from this socket.
     pub fn set_ttl(&self, ttl: u32) -> Result<(), IoError> {
-        self.stream.set_ttl(ttl)
+        self.stream.get_ref().set_ttl(ttl)
     }
 }
 
-impl<O> From<IncomingConnection<O>> for TcpStream {
+impl<O, U> From<IncomingConnection<O, TcpStream, U>> for TcpStream {
     #[inline]
-    fn from(conn: IncomingConnection<O>) -> Self {
-        conn.stream
+    fn from(conn: IncomingConnection<O, TcpStream, U>) -> Self {
+        conn.stream.into_parts().0
     }
 }
 
-/// A TCP stream that has been authenticated.
+/// A stream that has been authenticated.
 ///
 /// To get the command from the SOCKS5 client, use [`wait_request`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Authenticated.html#method.wait_request).
 ///
-/// It can also be converted back into a raw [`tokio::TcpStream`](https://docs.rs/tokio/latest/tokio/net/struct.TcpStream.html) with `From` trait.
-pub struct Authenticated(TcpStream);
+/// The [`AuthContext`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.AuthContext.html) used during authentication is kept, so data stored in it by the [`Auth`](https://docs.rs/socks5-server/latest/socks5_server/auth/trait.Auth.html) adaptor can be retrieved with [`context()`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Authenticated.html#method.context).
+///
+/// It can also be converted back into a raw [`tokio::TcpStream`](https://docs.rs/tokio/latest/tokio/net/struct.TcpStream.html) with `From` trait, or into the underlying stream with [`into_inner()`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Authenticated.html#method.into_inner). Both discard the bytes already buffered, which [`into_buffered()`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Authenticated.html#method.into_buffered) keeps.
+pub struct Authenticated<T = TcpStream>(BufferedStream<T>, AuthContext);
 
-impl Authenticated {
+impl<T> Authenticated<T> {
     #[inline]
-    fn new(stream: TcpStream) -> Self {
-        Self(stream)
+    fn new(stream: BufferedStream<T>, context: AuthContext) -> Self {
+        Self(stream, context)
     }
 
+    /// Returns the context used during authentication.
+    #[inline]
+    pub fn context(&self) -> &AuthContext {
+        &self.1
+    }
+
+    /// Returns a mutable reference to the context used during authentication.
+    #[inline]
+    pub fn context_mut(&mut self) -> &mut AuthContext {
+        &mut self.1
+    }
+
+    /// Gets a reference to the underlying stream.
+    ///
+    /// Note that reading directly from the underlying stream skips the bytes already buffered during the handshake.
+    #[inline]
+    pub fn get_ref(&self) -> &T {
+        self.0.get_ref()
+    }
+
+    /// Gets a mutable reference to the underlying stream.
+    ///
+    /// Note that reading directly from the underlying stream skips the bytes already buffered during the handshake.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        self.0.get_mut()
+    }
+
+    /// Consumes the `Authenticated`, returning the underlying stream.
+    ///
+    /// Any bytes already buffered during the handshake are discarded. Use [`into_buffered()`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Authenticated.html#method.into_buffered) to keep them.
+    #[inline]
+    pub fn into_inner(self) -> T {
+        self.0.into_parts().0
+    }
+
+    /// Consumes the `Authenticated`, returning the underlying stream alongside the bytes already buffered during the handshake.
+    #[inline]
+    pub fn into_buffered(self) -> BufferedStream<T> {
+        self.0
+    }
+}
+
+impl<T> Authenticated<T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
     /// Waits the SOCKS5 client to send a request.
     ///
     /// This method will return a [`Command`](https://docs.rs/socks5-server/latest/socks5_server/connection/enum.Command.html) if the client sends a valid command.
     ///
-    /// When encountering an error, the stream will be returned alongside the error.
+    /// When encountering an error, the stream will be returned alongside the error. Bytes already buffered are discarded, as the connection cannot be used for SOCKS5 anymore.
     ///
     /// Note that this method will not implicitly close the connection even if the client sends an invalid request.
-    pub async fn wait_request(mut self) -> Result<Command, (Error, TcpStream)> {
-        let req = match Request::read_from(&mut self.0).await {
+    pub async fn wait_request(mut self) -> Result<Command<T>, (Error, T)> {
+        let req = match self.0.read_message(Request::decode).await {
             Ok(req) => req,
-            Err(err) => return Err((err, self.0)),
+            Err(err) => return Err((err, self.0.into_parts().0)),
         };
 
         match req.command {
             ProtocolCommand::Associate => Ok(Command::Associate(
-                Associate::<associate::NeedReply>::new(self.0),
+                Associate::<associate::NeedReply, T>::new(self.0),
                 req.address,
             )),
             ProtocolCommand::Bind => Ok(Command::Bind(
-                Bind::<bind::NeedFirstReply>::new(self.0),
+                Bind::<bind::NeedFirstReply, T>::new(self.0),
                 req.address,
             )),
             ProtocolCommand::Connect => Ok(Command::Connect(
-                Connect::<connect::NeedReply>::new(self.0),
+                Connect::<connect::NeedReply, T>::new(self.0),
                 req.address,
             )),
         }
@@ -193,17 +314,19 @@ impl Authenticated {
     pub async fn shutdown(&mut self) -> Result<(), IoError> {
         self.0.shutdown().await
     }
+}
 
+impl Authenticated<TcpStream> {
     /// Returns the local address that this stream is bound to.
     #[inline]
     pub fn local_addr(&self) -> Result<SocketAddr, IoError> {
-        self.0.local_addr()
+        self.0.get_ref().local_addr()
     }
 
     /// Returns the remote address that this stream is connected to.
     #[inline]
     pub fn peer_addr(&self) -> Result<SocketAddr, IoError> {
-        self.0.peer_addr()
+        self.0.get_ref().peer_addr()
     }
 
     /// Reads the linger duration for this socket by getting the `SO_LINGER` option.
@@ -211,7 +334,7 @@ impl Authenticated {
     /// For more information about this option, see [set_linger](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Authenticated.html#method.set_linger).
     #[inline]
     pub fn linger(&self) -> Result<Option<Duration>, IoError> {
-        self.0.linger()
+        self.0.get_ref().linger()
     }
 
     /// Sets the linger duration of this socket by setting the `SO_LINGER` option.
@@ -220,8 +343,9 @@ impl Authenticated {
     ///
     /// If `SO_LINGER` is not specified, and the stream is closed, the system handles the call in a way that allows the process to continue as quickly as possible.
     #[inline]
+    #[allow(deprecated)]
     pub fn set_linger(&self, dur: Option<Duration>) -> Result<(), IoError> {
-        self.0.set_linger(dur)
+        self.0.get_ref().set_linger(dur)
     }
 
     /// Gets the value of the `TCP_NODELAY` option on this socket.
@@ -229,41 +353,86 @@ impl Authenticated {
     /// For more information about this option, see [set_nodelay](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Authenticated.html#method.set_nodelay).
     #[inline]
     pub fn nodelay(&self) -> Result<bool, IoError> {
-        self.0.nodelay()
+        self.0.get_ref().nodelay()
     }
 
     /// Sets the value of the `TCP_NODELAY` option on this socket.
     ///
     /// If set, this option disables the Nagle algorithm. This means that segments are always sent as soon as possible, even if there is only a small amount of data. When not set, data is buffered until there is a sufficient amount to send out, thereby avoiding the frequent sending of small packets.
     pub fn set_nodelay(&self, nodelay: bool) -> Result<(), IoError> {
-        self.0.set_nodelay(nodelay)
+        self.0.get_ref().set_nodelay(nodelay)
     }
 
     /// Gets the value of the `IP_TTL` option for this socket.
     ///
     /// For more information about this option, see [set_ttl](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Authenticated.html#method.set_ttl).
     pub fn ttl(&self) -> Result<u32, IoError> {
-        self.0.ttl()
+        self.0.get_ref().ttl()
     }
 
     /// Sets the value for the `IP_TTL` option on this socket.
     ///
     /// This value sets the time-to-live field that is used in every packet sent from this socket.
     pub fn set_ttl(&self, ttl: u32) -> Result<(), IoError> {
-        self.0.set_ttl(ttl)
+        self.0.get_ref().set_ttl(ttl)
     }
 }
 
-impl From<Authenticated> for TcpStream {
+/// Converts the `Authenticated` into its `TcpStream`.
+///
+/// Like `into_inner()`, this discards the bytes already buffered, such as the request pipelined by the client. Use [`into_buffered()`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.Authenticated.html#method.into_buffered) to keep them.
+impl From<Authenticated<TcpStream>> for TcpStream {
     #[inline]
-    fn from(conn: Authenticated) -> Self {
-        conn.0
+    fn from(conn: Authenticated<TcpStream>) -> Self {
+        conn.0.into_parts().0
     }
 }
 
 /// A command sent from the SOCKS5 client.
-pub enum Command {
-    Associate(Associate<associate::NeedReply>, Address),
-    Bind(Bind<bind::NeedFirstReply>, Address),
-    Connect(Connect<connect::NeedReply>, Address),
+pub enum Command<T = TcpStream> {
+    Associate(Associate<associate::NeedReply, T>, Address),
+    Bind(Bind<bind::NeedFirstReply, T>, Address),
+    Connect(Connect<connect::NeedReply, T>, Address),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{auth::NoAuth, AuthAdaptor};
+    use socks5_proto::Reply;
+    use tokio::io::{self, AsyncReadExt, DuplexStream};
+
+    #[tokio::test]
+    async fn pipelined_bytes_survive_reply() {
+        let (mut client, server) = io::duplex(1024);
+
+        // handshake, CONNECT 1.2.3.4:80, and the first bytes of the tunnel in a single write
+        client
+            .write_all(&[0x05, 0x01, 0x00, 0x05, 0x01, 0x00, 0x01, 1, 2, 3, 4, 0, 80])
+            .await
+            .unwrap();
+        client.write_all(b"pipelined").await.unwrap();
+
+        let auth: AuthAdaptor<(), DuplexStream> = Arc::new(NoAuth);
+        let conn = IncomingConnection::new(server, Arc::new(Negotiator::from(auth)));
+        let (conn, _, ()) = conn.authenticate().await.unwrap();
+
+        let Command::Connect(connect, addr) = conn.wait_request().await.unwrap() else {
+            panic!("expected a CONNECT request");
+        };
+        assert_eq!(addr, Address::SocketAddress(([1, 2, 3, 4], 80).into()));
+
+        let mut connect = connect
+            .reply(Reply::Succeeded, Address::unspecified())
+            .await
+            .unwrap();
+
+        let mut buf = [0; 9];
+        connect.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"pipelined");
+
+        let mut resp = [0; 12];
+        client.read_exact(&mut resp).await.unwrap();
+        assert_eq!(resp[..4], [0x05, 0x00, 0x05, 0x00]);
+    }
 }
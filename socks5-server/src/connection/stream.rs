@@ -0,0 +1,155 @@
+use bytes::{Buf, BytesMut};
+use std::{
+    io::{Error, ErrorKind, IoSlice},
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
+
+/// A stream with a read buffer, used to read SOCKS5 handshake messages.
+///
+/// Handshake messages are read in chunks instead of byte by byte, so a client may have sent more than the message being parsed (e.g. the method selection, the authentication and the request in one packet, or even application data before the reply). Those bytes are kept in the buffer and are delivered first by `poll_read()` before reading from the underlying stream again.
+///
+/// All connection types in this crate hold a `BufferedStream`, so the bytes pipelined by the client are carried all the way into [`Connect<Ready>`](https://docs.rs/socks5-server/latest/socks5_server/connection/connect/struct.Connect.html), [`Bind<Ready>`](https://docs.rs/socks5-server/latest/socks5_server/connection/bind/struct.Bind.html) and [`Associate<Ready>`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.Associate.html).
+#[derive(Debug)]
+pub struct BufferedStream<T> {
+    stream: T,
+    buf: BytesMut,
+}
+
+impl<T> BufferedStream<T> {
+    const READ_CHUNK_SIZE: usize = 512;
+
+    /// Creates a new `BufferedStream` with an empty buffer.
+    #[inline]
+    pub fn new(stream: T) -> Self {
+        Self::with_buffer(stream, BytesMut::new())
+    }
+
+    /// Creates a new `BufferedStream` with bytes already read from the stream.
+    #[inline]
+    pub fn with_buffer(stream: T, buf: BytesMut) -> Self {
+        Self { stream, buf }
+    }
+
+    /// Returns the bytes that have been read from the underlying stream but not yet consumed.
+    #[inline]
+    pub fn buffer(&self) -> &[u8] {
+        &self.buf
+    }
+
+    /// Gets a reference to the underlying stream.
+    ///
+    /// Note that reading directly from the underlying stream skips the buffered bytes.
+    #[inline]
+    pub fn get_ref(&self) -> &T {
+        &self.stream
+    }
+
+    /// Gets a mutable reference to the underlying stream.
+    ///
+    /// Note that reading directly from the underlying stream skips the buffered bytes.
+    #[inline]
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.stream
+    }
+
+    /// Takes the bytes not yet consumed out of the buffer.
+    #[cfg(all(feature = "splice", target_os = "linux"))]
+    #[inline]
+    pub(crate) fn take_buffer(&mut self) -> BytesMut {
+        self.buf.split()
+    }
+
+    /// Consumes the `BufferedStream`, returning the underlying stream and the bytes not yet consumed.
+    #[inline]
+    pub fn into_parts(self) -> (T, BytesMut) {
+        (self.stream, self.buf)
+    }
+}
+
+impl<T> BufferedStream<T>
+where
+    T: AsyncRead + Unpin,
+{
+    /// Reads a message with the given decode function, e.g. [`Request::decode`](https://docs.rs/socks5-proto/latest/socks5_proto/struct.Request.html#method.decode).
+    ///
+    /// The decode function is called on the buffered bytes first. The underlying stream is only read when the buffer does not contain a complete message yet. Bytes following the message are kept in the buffer.
+    ///
+    /// If the stream reaches EOF before a complete message is read, an `UnexpectedEof` error is returned.
+    pub async fn read_message<M, E, F>(&mut self, mut decode: F) -> Result<M, E>
+    where
+        F: FnMut(&mut BytesMut) -> Result<Option<M>, E>,
+        E: From<Error>,
+    {
+        loop {
+            if let Some(msg) = decode(&mut self.buf)? {
+                return Ok(msg);
+            }
+
+            self.buf.reserve(Self::READ_CHUNK_SIZE);
+
+            if self.stream.read_buf(&mut self.buf).await? == 0 {
+                return Err(E::from(Error::from(ErrorKind::UnexpectedEof)));
+            }
+        }
+    }
+}
+
+impl<T> AsyncRead for BufferedStream<T>
+where
+    T: AsyncRead + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), Error>> {
+        if !self.buf.is_empty() {
+            let len = self.buf.len().min(buf.remaining());
+            buf.put_slice(&self.buf[..len]);
+            self.buf.advance(len);
+            return Poll::Ready(Ok(()));
+        }
+
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl<T> AsyncWrite for BufferedStream<T>
+where
+    T: AsyncWrite + Unpin,
+{
+    #[inline]
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    #[inline]
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, Error>> {
+        Pin::new(&mut self.stream).poll_write_vectored(cx, bufs)
+    }
+
+    #[inline]
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    #[inline]
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
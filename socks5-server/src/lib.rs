@@ -6,37 +6,51 @@ use std::{
     sync::Arc,
     task::{Context, Poll},
 };
-use tokio::net::TcpListener;
+use tokio::net::{TcpListener, TcpStream};
 
 pub mod auth;
 pub mod connection;
 
+#[cfg(feature = "relay")]
+pub mod relay;
+
+#[cfg(feature = "upstream")]
+pub mod upstream;
+
 pub use crate::{
-    auth::Auth,
+    auth::{Auth, Negotiator},
     connection::{
         associate::{Associate, AssociatedUdpSocket},
         bind::Bind,
         connect::Connect,
-        Authenticated, Command, IncomingConnection,
+        Authenticated, BufferedStream, Command, IncomingConnection,
     },
 };
 
-pub(crate) type AuthAdaptor<O> = Arc<dyn Auth<Output = O> + Send + Sync>;
+/// A shared, type-erased authentication adaptor.
+///
+/// Generic type `<T>` is the stream the authentication is performed on, and `<U>` is the stream after [encapsulation](https://docs.rs/socks5-server/latest/socks5_server/auth/trait.Auth.html#tymethod.encapsulate).
+pub type AuthAdaptor<O, T = TcpStream, U = T> =
+    Arc<dyn Auth<T, Output = O, Stream = U> + Send + Sync>;
 
 /// A SOCKS5 server listener
 ///
 /// This server listens on a socket and treats incoming connections as SOCKS5 connections.
 ///
-/// A `(TcpListener, Arc<dyn Auth<Output = O> + Send + Sync>)` can be converted into a `Server<O>` with `From` trait. Also, a `Server<O>` can be converted back.
+/// A `(TcpListener, Arc<dyn Auth<Output = O> + Send + Sync>)` or a `(TcpListener, Negotiator<O>)` can be converted into a `Server<O>` with `From` trait. Also, a `Server<O>` can be converted back into a `(TcpListener, Arc<Negotiator<O>>)`.
+///
+/// Use a [`Negotiator`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.Negotiator.html) to offer multiple authentication methods with a server-side preference order.
 ///
 /// Generic type `<O>` is the output type of the authentication adapter. See trait [`Auth`](https://docs.rs/socks5-server/latest/socks5_server/auth/trait.Auth.html).
 ///
+/// Generic type `<U>` is the stream type after the authentication adapter [encapsulates](https://docs.rs/socks5-server/latest/socks5_server/auth/trait.Auth.html#tymethod.encapsulate) the accepted `TcpStream`. It is `TcpStream` unless an encapsulating method is used.
+///
 /// # Example
 ///
 /// ```rust
 /// use socks5_server::{auth::NoAuth, Server};
 /// use std::sync::Arc;
-/// use tokio::net::TcpListener;
+/// use tokio::net::{TcpListener, TcpStream};
 ///
 /// async fn listen() {
 ///     let listener = TcpListener::bind("127.0.0.1:5000").await.unwrap();
@@ -51,19 +65,20 @@ pub(crate) type AuthAdaptor<O> = Arc<dyn Auth<Output = O> + Send + Sync>;
 ///     }
 /// }
 /// ```
-pub struct Server<O> {
+pub struct Server<O, U = TcpStream> {
     listener: TcpListener,
-    auth: AuthAdaptor<O>,
+    auth: Arc<Negotiator<O, TcpStream, U>>,
+    id: Option<usize>,
 }
 
-impl<O> Server<O> {
+impl<O, U> Server<O, U> {
     /// Accept an [`IncomingConnection<O>`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.IncomingConnection.html).
     ///
     /// The connection is only a freshly created TCP connection and may not be a valid SOCKS5 connection. You should call [`IncomingConnection::authenticate()`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.IncomingConnection.html#method.authenticate) to perform a SOCKS5 authentication handshake.
     #[inline]
-    pub async fn accept(&self) -> Result<(IncomingConnection<O>, SocketAddr), Error> {
+    pub async fn accept(&self) -> Result<(IncomingConnection<O, TcpStream, U>, SocketAddr), Error> {
         let (stream, addr) = self.listener.accept().await?;
-        Ok((IncomingConnection::new(stream, self.auth.clone()), addr))
+        Ok((self.incoming(stream, addr), addr))
     }
 
     /// Polls to accept an [`IncomingConnection<O>`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.IncomingConnection.html).
@@ -72,13 +87,40 @@ impl<O> Server<O> {
     ///
     /// If there is no connection to accept, Poll::Pending is returned and the current task will be notified by a waker. Note that on multiple calls to poll_accept, only the Waker from the Context passed to the most recent call is scheduled to receive a wakeup.
     #[inline]
+    #[allow(clippy::type_complexity)]
     pub fn poll_accept(
         &self,
         cx: &mut Context<'_>,
-    ) -> Poll<Result<(IncomingConnection<O>, SocketAddr), Error>> {
+    ) -> Poll<Result<(IncomingConnection<O, TcpStream, U>, SocketAddr), Error>> {
         self.listener
             .poll_accept(cx)
-            .map_ok(|(stream, addr)| (IncomingConnection::new(stream, self.auth.clone()), addr))
+            .map_ok(|(stream, addr)| (self.incoming(stream, addr), addr))
+    }
+
+    fn incoming(&self, stream: TcpStream, addr: SocketAddr) -> IncomingConnection<O, TcpStream, U> {
+        let local_addr = stream.local_addr().ok();
+        let mut conn = IncomingConnection::new(stream, self.auth.clone());
+
+        let ctx = conn.context_mut();
+        ctx.set_peer_addr(Some(addr));
+        ctx.set_local_addr(local_addr);
+        ctx.set_listener_id(self.id);
+
+        conn
+    }
+
+    /// Returns the ID of this server, if set.
+    #[inline]
+    pub fn listener_id(&self) -> Option<usize> {
+        self.id
+    }
+
+    /// Sets an ID for this server.
+    ///
+    /// The ID is passed to the authentication adaptors of every accepted connection through [`AuthContext::listener_id()`](https://docs.rs/socks5-server/latest/socks5_server/auth/struct.AuthContext.html#method.listener_id), which is useful to tell connections apart when the same adaptors are shared by multiple listeners.
+    #[inline]
+    pub fn set_listener_id(&mut self, id: Option<usize>) {
+        self.id = id;
     }
 
     /// Returns the local address that this server is bound to.
@@ -106,16 +148,31 @@ impl<O> Server<O> {
     }
 }
 
-impl<O> From<(TcpListener, AuthAdaptor<O>)> for Server<O> {
+impl<O, U> From<(TcpListener, AuthAdaptor<O, TcpStream, U>)> for Server<O, U> {
+    #[inline]
+    fn from((listener, auth): (TcpListener, AuthAdaptor<O, TcpStream, U>)) -> Self {
+        Self {
+            listener,
+            auth: Arc::new(Negotiator::from(auth)),
+            id: None,
+        }
+    }
+}
+
+impl<O, U> From<(TcpListener, Negotiator<O, TcpStream, U>)> for Server<O, U> {
     #[inline]
-    fn from((listener, auth): (TcpListener, AuthAdaptor<O>)) -> Self {
-        Self { listener, auth }
+    fn from((listener, auth): (TcpListener, Negotiator<O, TcpStream, U>)) -> Self {
+        Self {
+            listener,
+            auth: Arc::new(auth),
+            id: None,
+        }
     }
 }
 
-impl<O> From<Server<O>> for (TcpListener, AuthAdaptor<O>) {
+impl<O, U> From<Server<O, U>> for (TcpListener, Arc<Negotiator<O, TcpStream, U>>) {
     #[inline]
-    fn from(server: Server<O>) -> Self {
+    fn from(server: Server<O, U>) -> Self {
         (server.listener, server.auth)
     }
 }
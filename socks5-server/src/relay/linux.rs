@@ -0,0 +1,329 @@
+use super::{CloseReason, RelayConfig, RelayStats, TransferError};
+use crate::connection::connect::{Connect, Ready};
+use std::{
+    future::{self, Future},
+    io::{Error, ErrorKind},
+    os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+    ptr,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncWriteExt, Interest},
+    net::TcpStream,
+    time::{self, Instant},
+};
+
+pub(super) async fn splice_connect(
+    client: &mut Connect<Ready, TcpStream>,
+    remote: &mut TcpStream,
+    config: RelayConfig,
+) -> RelayStats {
+    // bytes sent by the client before the reply never reach the socket buffer
+    let buf = client.stream_mut().take_buffer();
+
+    if let Err(err) = remote.write_all(&buf).await {
+        return RelayStats {
+            client_to_remote: 0,
+            remote_to_client: 0,
+            reason: CloseReason::RemoteError(err),
+        };
+    }
+
+    let mut stats = splice_tcp(client.get_mut(), remote, config).await;
+    stats.client_to_remote += buf.len() as u64;
+    stats
+}
+
+pub(super) async fn splice_tcp(
+    client: &mut TcpStream,
+    remote: &mut TcpStream,
+    config: RelayConfig,
+) -> RelayStats {
+    let (mut upload, mut download) = match (
+        Transfer::new(config.client_buffer_size),
+        Transfer::new(config.remote_buffer_size),
+    ) {
+        (Ok(upload), Ok(download)) => (upload, download),
+        (Err(err), _) | (_, Err(err)) => {
+            return RelayStats {
+                client_to_remote: 0,
+                remote_to_client: 0,
+                reason: CloseReason::LocalError(err),
+            }
+        }
+    };
+
+    let mut idle = config
+        .idle_timeout
+        .map(|timeout| (timeout, Box::pin(time::sleep(timeout))));
+
+    let reason = future::poll_fn(|cx| {
+        let transferred = upload.amt + download.amt;
+
+        if !upload.done {
+            match upload.poll_transfer(cx, client, remote) {
+                Poll::Ready(Ok(())) => upload.done = true,
+                Poll::Ready(Err(TransferError::Read(err))) => {
+                    return Poll::Ready(CloseReason::ClientError(err))
+                }
+                Poll::Ready(Err(TransferError::Write(err))) => {
+                    return Poll::Ready(CloseReason::RemoteError(err))
+                }
+                Poll::Pending => {}
+            }
+        }
+
+        if !download.done {
+            match download.poll_transfer(cx, remote, client) {
+                Poll::Ready(Ok(())) => download.done = true,
+                Poll::Ready(Err(TransferError::Read(err))) => {
+                    return Poll::Ready(CloseReason::RemoteError(err))
+                }
+                Poll::Ready(Err(TransferError::Write(err))) => {
+                    return Poll::Ready(CloseReason::ClientError(err))
+                }
+                Poll::Pending => {}
+            }
+        }
+
+        if upload.done && download.done {
+            return Poll::Ready(CloseReason::Completed);
+        }
+
+        if let Some((timeout, sleep)) = &mut idle {
+            if upload.amt + download.amt != transferred {
+                sleep.as_mut().reset(Instant::now() + *timeout);
+            }
+
+            if sleep.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(CloseReason::IdleTimeout);
+            }
+        }
+
+        Poll::Pending
+    })
+    .await;
+
+    RelayStats {
+        client_to_remote: upload.amt,
+        remote_to_client: download.amt,
+        reason,
+    }
+}
+
+/// One direction of a relay, moving data from a socket to another through a pipe.
+struct Transfer {
+    pipe_rx: OwnedFd,
+    pipe_tx: OwnedFd,
+    pipe_size: usize,
+    in_pipe: usize,
+    amt: u64,
+    read_done: bool,
+    done: bool,
+}
+
+impl Transfer {
+    fn new(buf_size: usize) -> Result<Self, Error> {
+        let mut fds = [0; 2];
+
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } < 0 {
+            return Err(Error::last_os_error());
+        }
+
+        let (pipe_rx, pipe_tx) =
+            unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+
+        // the kernel rounds the size up, and may refuse it if it exceeds the limit of the user
+        // a slot holds a page however little data is in it, so keep at least the default 16 slots
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(4096) as usize;
+        let size = buf_size.max(16 * page_size);
+        let size = libc::c_int::try_from(size).unwrap_or(libc::c_int::MAX);
+        unsafe { libc::fcntl(pipe_tx.as_raw_fd(), libc::F_SETPIPE_SZ, size) };
+        let pipe_size = unsafe { libc::fcntl(pipe_tx.as_raw_fd(), libc::F_GETPIPE_SZ) };
+
+        if pipe_size <= 0 {
+            return Err(Error::last_os_error());
+        }
+
+        Ok(Self {
+            pipe_rx,
+            pipe_tx,
+            pipe_size: pipe_size as usize,
+            in_pipe: 0,
+            amt: 0,
+            read_done: false,
+            done: false,
+        })
+    }
+
+    fn poll_transfer(
+        &mut self,
+        cx: &mut Context<'_>,
+        reader: &TcpStream,
+        writer: &TcpStream,
+    ) -> Poll<Result<(), TransferError>> {
+        loop {
+            // only splice into an empty pipe, so EAGAIN means the socket is drained, not the pipe full
+            if !self.read_done && self.in_pipe == 0 {
+                match reader.poll_read_ready(cx) {
+                    Poll::Ready(Ok(())) => {
+                        let res = reader.try_io(Interest::READABLE, || {
+                            splice(reader.as_raw_fd(), self.pipe_tx.as_raw_fd(), self.pipe_size)
+                        });
+
+                        match res {
+                            Ok(0) => self.read_done = true,
+                            Ok(n) => self.in_pipe += n,
+                            Err(err) if err.kind() == ErrorKind::WouldBlock => continue,
+                            Err(err) => return Poll::Ready(Err(TransferError::Read(err))),
+                        }
+                    }
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(TransferError::Read(err))),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+
+            if self.in_pipe > 0 {
+                match writer.poll_write_ready(cx) {
+                    Poll::Ready(Ok(())) => {
+                        let res = writer.try_io(Interest::WRITABLE, || {
+                            splice(self.pipe_rx.as_raw_fd(), writer.as_raw_fd(), self.in_pipe)
+                        });
+
+                        match res {
+                            Ok(0) => {
+                                return Poll::Ready(Err(TransferError::Write(Error::from(
+                                    ErrorKind::WriteZero,
+                                ))))
+                            }
+                            Ok(n) => {
+                                self.in_pipe -= n;
+                                self.amt += n as u64;
+                            }
+                            Err(err) if err.kind() == ErrorKind::WouldBlock => {}
+                            Err(err) => return Poll::Ready(Err(TransferError::Write(err))),
+                        }
+                    }
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(TransferError::Write(err))),
+                    Poll::Pending => return Poll::Pending,
+                }
+
+                continue;
+            }
+
+            if self.read_done {
+                // propagate the half-close
+                if unsafe { libc::shutdown(writer.as_raw_fd(), libc::SHUT_WR) } < 0 {
+                    return Poll::Ready(Err(TransferError::Write(Error::last_os_error())));
+                }
+
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+}
+
+fn splice(fd_in: RawFd, fd_out: RawFd, len: usize) -> Result<usize, Error> {
+    let res = unsafe {
+        libc::splice(
+            fd_in,
+            ptr::null_mut(),
+            fd_out,
+            ptr::null_mut(),
+            len,
+            libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK,
+        )
+    };
+
+    if res < 0 {
+        Err(Error::last_os_error())
+    } else {
+        Ok(res as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::{Negotiator, NoAuth},
+        connection::{Command, IncomingConnection},
+        AuthAdaptor,
+    };
+    use socks5_proto::{Address, Reply};
+    use std::sync::Arc;
+    use tokio::{io::AsyncReadExt, net::TcpListener};
+
+    fn payload() -> Vec<u8> {
+        (0..4 * 1024 * 1024).map(|idx| (idx % 251) as u8).collect()
+    }
+
+    #[tokio::test]
+    async fn pipelined_bytes_are_sent_first() {
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        let target_addr = target.local_addr().unwrap();
+
+        let client = tokio::spawn(async move {
+            let mut stream = TcpStream::connect(proxy_addr).await.unwrap();
+
+            // handshake, CONNECT and the first bytes of the tunnel before any reply
+            let mut req = vec![0x05, 0x01, 0x00, 0x05, 0x01, 0x00, 0x01, 127, 0, 0, 1];
+            req.extend_from_slice(&target_addr.port().to_be_bytes());
+            req.extend_from_slice(b"first");
+            stream.write_all(&req).await.unwrap();
+
+            let mut resp = [0; 12];
+            stream.read_exact(&mut resp).await.unwrap();
+            assert_eq!(resp[..4], [0x05, 0x00, 0x05, 0x00]);
+
+            stream.write_all(&payload()).await.unwrap();
+            stream.shutdown().await.unwrap();
+
+            let mut buf = Vec::new();
+            stream.read_to_end(&mut buf).await.unwrap();
+            buf
+        });
+
+        let remote = tokio::spawn(async move {
+            let (mut stream, _) = target.accept().await.unwrap();
+
+            let mut buf = Vec::new();
+            stream.read_to_end(&mut buf).await.unwrap();
+
+            stream.write_all(&payload()).await.unwrap();
+            stream.shutdown().await.unwrap();
+            buf
+        });
+
+        let (stream, _) = proxy.accept().await.unwrap();
+        let auth: AuthAdaptor<()> = Arc::new(NoAuth);
+        let conn = IncomingConnection::new(stream, Arc::new(Negotiator::from(auth)));
+        let (conn, _, ()) = conn.authenticate().await.unwrap();
+
+        let Command::Connect(connect, Address::SocketAddress(addr)) =
+            conn.wait_request().await.unwrap()
+        else {
+            panic!("expected a CONNECT request");
+        };
+
+        let mut remote_stream = TcpStream::connect(addr).await.unwrap();
+        let mut connect = connect
+            .reply(Reply::Succeeded, Address::unspecified())
+            .await
+            .unwrap();
+        assert_eq!(connect.stream_mut().buffer(), b"first");
+
+        let stats =
+            super::super::splice(&mut connect, &mut remote_stream, RelayConfig::default()).await;
+        assert!(matches!(stats.reason, CloseReason::Completed));
+
+        let mut expected = b"first".to_vec();
+        expected.extend_from_slice(&payload());
+        assert!(remote.await.unwrap() == expected);
+        assert!(client.await.unwrap() == payload());
+        assert_eq!(stats.client_to_remote, expected.len() as u64);
+        assert_eq!(stats.remote_to_client, payload().len() as u64);
+    }
+}
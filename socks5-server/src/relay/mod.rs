@@ -0,0 +1,448 @@
+//! Relaying data between a SOCKS5 client and a remote stream.
+//!
+//! [`relay()`](https://docs.rs/socks5-server/latest/socks5_server/relay/fn.relay.html) copies data in both directions until both sides are closed, propagating half-closes, and returns the number of bytes transferred in each direction alongside the reason the relay ended. It is also available as [`Connect::relay()`](https://docs.rs/socks5-server/latest/socks5_server/connection/connect/struct.Connect.html#method.relay).
+//!
+//! [`relay_udp()`](https://docs.rs/socks5-server/latest/socks5_server/relay/fn.relay_udp.html) relays the datagrams of a UDP association until the client closes the control TCP connection. Associations and their per-destination mappings can be tracked and limited with a shared [`SessionTable`](https://docs.rs/socks5-server/latest/socks5_server/relay/struct.SessionTable.html). Where UDP is blocked, [`relay_udp_over_tcp()`](https://docs.rs/socks5-server/latest/socks5_server/relay/fn.relay_udp_over_tcp.html) relays datagrams framed on a TCP connection instead.
+//!
+//! With feature `splice`, [`splice()`](https://docs.rs/socks5-server/latest/socks5_server/relay/fn.splice.html) relays TCP connections on Linux without copying data into userspace.
+
+mod session;
+mod udp;
+
+#[cfg(all(feature = "splice", target_os = "linux"))]
+mod linux;
+
+pub use self::{
+    session::{LimitExceeded, Session, SessionConfig, SessionStats, SessionTable},
+    udp::{relay_udp, relay_udp_over_tcp, NatFiltering, NatMapping, UdpRelayConfig, UdpRelayStats},
+};
+
+#[cfg(all(feature = "splice", target_os = "linux"))]
+use crate::connection::connect::{Connect, Ready};
+#[cfg(all(feature = "splice", target_os = "linux"))]
+use std::any::Any;
+#[cfg(all(feature = "splice", target_os = "linux"))]
+use tokio::net::TcpStream;
+
+use std::{
+    future::{self, Future},
+    io::{Error, ErrorKind},
+    pin::Pin,
+    task::{ready, Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{self, Instant},
+};
+
+/// Options of a relay.
+#[derive(Clone, Copy, Debug)]
+pub struct RelayConfig {
+    /// Size of the buffer used to copy data from the client to the remote. Defaults to 8 KiB.
+    pub client_buffer_size: usize,
+    /// Size of the buffer used to copy data from the remote to the client. Defaults to 8 KiB.
+    pub remote_buffer_size: usize,
+    /// Ends the relay if no data is transferred in either direction for this long. Defaults to `None`, which never times out.
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self {
+            client_buffer_size: 8 * 1024,
+            remote_buffer_size: 8 * 1024,
+            idle_timeout: None,
+        }
+    }
+}
+
+/// Why a relay ended.
+#[derive(Debug)]
+pub enum CloseReason {
+    /// Both sides closed their write half and all data has been transferred.
+    Completed,
+    /// No data has been transferred for [`idle_timeout`](https://docs.rs/socks5-server/latest/socks5_server/relay/struct.RelayConfig.html#structfield.idle_timeout), or no datagram for [`UdpRelayConfig::idle_timeout`](https://docs.rs/socks5-server/latest/socks5_server/relay/struct.UdpRelayConfig.html#structfield.idle_timeout).
+    IdleTimeout,
+    /// Reading from or writing to the client failed.
+    ClientError(Error),
+    /// Reading from or writing to the remote failed.
+    RemoteError(Error),
+    /// Setting up the relay failed locally, e.g. creating the pipes of a `splice(2)` relay.
+    LocalError(Error),
+}
+
+/// The result of a relay.
+#[derive(Debug)]
+pub struct RelayStats {
+    /// Bytes written to the remote.
+    pub client_to_remote: u64,
+    /// Bytes written to the client.
+    pub remote_to_client: u64,
+    /// Why the relay ended.
+    pub reason: CloseReason,
+}
+
+/// Relays data between `client` and `remote` until both directions are finished, an error occurs or the idle timeout elapses.
+///
+/// When one side reaches EOF, the write half of the other side is shut down, and the opposite direction keeps being relayed. The streams are not closed when the relay ends on an error or a timeout.
+///
+/// Bytes already buffered in a [`Connect<Ready>`](https://docs.rs/socks5-server/latest/socks5_server/connection/connect/struct.Connect.html) (sent by the client before the reply) are relayed first.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::{
+///     connection::connect::{Connect, Ready},
+///     relay::{self, RelayConfig},
+/// };
+/// use std::time::Duration;
+/// use tokio::net::TcpStream;
+///
+/// async fn relay(mut conn: Connect<Ready>, mut target: TcpStream) {
+///     let config = RelayConfig {
+///         idle_timeout: Some(Duration::from_secs(300)),
+///         ..Default::default()
+///     };
+///
+///     let stats = relay::relay(&mut conn, &mut target, config).await;
+///     println!(
+///         "{} bytes up, {} bytes down, {:?}",
+///         stats.client_to_remote, stats.remote_to_client, stats.reason,
+///     );
+/// }
+/// ```
+pub async fn relay<A, B>(client: &mut A, remote: &mut B, config: RelayConfig) -> RelayStats
+where
+    A: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    B: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    let mut upload = Transfer::new(config.client_buffer_size);
+    let mut download = Transfer::new(config.remote_buffer_size);
+    let mut idle = config
+        .idle_timeout
+        .map(|timeout| (timeout, Box::pin(time::sleep(timeout))));
+
+    let reason = future::poll_fn(|cx| {
+        let transferred = upload.amt + download.amt;
+
+        if !upload.done {
+            match upload.poll_transfer(cx, Pin::new(&mut *client), Pin::new(&mut *remote)) {
+                Poll::Ready(Ok(())) => upload.done = true,
+                Poll::Ready(Err(TransferError::Read(err))) => {
+                    return Poll::Ready(CloseReason::ClientError(err))
+                }
+                Poll::Ready(Err(TransferError::Write(err))) => {
+                    return Poll::Ready(CloseReason::RemoteError(err))
+                }
+                Poll::Pending => {}
+            }
+        }
+
+        if !download.done {
+            match download.poll_transfer(cx, Pin::new(&mut *remote), Pin::new(&mut *client)) {
+                Poll::Ready(Ok(())) => download.done = true,
+                Poll::Ready(Err(TransferError::Read(err))) => {
+                    return Poll::Ready(CloseReason::RemoteError(err))
+                }
+                Poll::Ready(Err(TransferError::Write(err))) => {
+                    return Poll::Ready(CloseReason::ClientError(err))
+                }
+                Poll::Pending => {}
+            }
+        }
+
+        if upload.done && download.done {
+            return Poll::Ready(CloseReason::Completed);
+        }
+
+        if let Some((timeout, sleep)) = &mut idle {
+            if upload.amt + download.amt != transferred {
+                sleep.as_mut().reset(Instant::now() + *timeout);
+            }
+
+            if sleep.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(CloseReason::IdleTimeout);
+            }
+        }
+
+        Poll::Pending
+    })
+    .await;
+
+    RelayStats {
+        client_to_remote: upload.amt,
+        remote_to_client: download.amt,
+        reason,
+    }
+}
+
+/// Relays data between `client` and `remote` with `splice(2)` if possible, or with [`relay()`](https://docs.rs/socks5-server/latest/socks5_server/relay/fn.relay.html) otherwise.
+///
+/// On Linux, if `remote` is a tokio `TcpStream` and `client` is a `TcpStream` or a [`Connect<Ready, TcpStream>`](https://docs.rs/socks5-server/latest/socks5_server/connection/connect/struct.Connect.html), data is moved between the sockets through a pair of pipes, without being copied into userspace. Bytes already buffered in the `Connect<Ready>` are written to the remote first. The pipe sizes follow the buffer sizes in `config`, with a minimum of 16 pages and subject to the limits of the kernel. For other stream types (e.g. TLS) and on other platforms, this falls back to the buffered copy.
+///
+/// The statistics and the close reason are the same as [`relay()`](https://docs.rs/socks5-server/latest/socks5_server/relay/fn.relay.html).
+#[cfg(feature = "splice")]
+pub async fn splice<A, B>(client: &mut A, remote: &mut B, config: RelayConfig) -> RelayStats
+where
+    A: AsyncRead + AsyncWrite + Unpin + 'static,
+    B: AsyncRead + AsyncWrite + Unpin + 'static,
+{
+    #[cfg(target_os = "linux")]
+    {
+        let client = client as &mut dyn Any;
+        let remote = remote as &mut dyn Any;
+
+        if remote.is::<TcpStream>() {
+            let remote = remote.downcast_mut::<TcpStream>().unwrap();
+
+            if client.is::<Connect<Ready, TcpStream>>() {
+                let client = client.downcast_mut::<Connect<Ready, TcpStream>>().unwrap();
+                return linux::splice_connect(client, remote, config).await;
+            } else if client.is::<TcpStream>() {
+                let client = client.downcast_mut::<TcpStream>().unwrap();
+                return linux::splice_tcp(client, remote, config).await;
+            }
+        }
+    }
+
+    relay(client, remote, config).await
+}
+
+enum TransferError {
+    Read(Error),
+    Write(Error),
+}
+
+struct Transfer {
+    buf: Box<[u8]>,
+    pos: usize,
+    cap: usize,
+    amt: u64,
+    read_done: bool,
+    need_flush: bool,
+    done: bool,
+}
+
+impl Transfer {
+    fn new(buf_size: usize) -> Self {
+        Self {
+            buf: vec![0; buf_size.max(1)].into_boxed_slice(),
+            pos: 0,
+            cap: 0,
+            amt: 0,
+            read_done: false,
+            need_flush: false,
+            done: false,
+        }
+    }
+
+    fn poll_transfer<R, W>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut reader: Pin<&mut R>,
+        mut writer: Pin<&mut W>,
+    ) -> Poll<Result<(), TransferError>>
+    where
+        R: AsyncRead + ?Sized,
+        W: AsyncWrite + ?Sized,
+    {
+        loop {
+            if self.pos == self.cap && !self.read_done {
+                let mut buf = ReadBuf::new(&mut self.buf);
+
+                match reader.as_mut().poll_read(cx, &mut buf) {
+                    Poll::Ready(Ok(())) => {
+                        let n = buf.filled().len();
+                        self.pos = 0;
+                        self.cap = n;
+                        self.read_done = n == 0;
+                    }
+                    Poll::Ready(Err(err)) => return Poll::Ready(Err(TransferError::Read(err))),
+                    Poll::Pending => {
+                        // flush what has been written before waiting for more data
+                        if self.need_flush {
+                            ready!(writer.as_mut().poll_flush(cx)).map_err(TransferError::Write)?;
+                            self.need_flush = false;
+                        }
+
+                        return Poll::Pending;
+                    }
+                }
+            }
+
+            while self.pos < self.cap {
+                let n = ready!(writer
+                    .as_mut()
+                    .poll_write(cx, &self.buf[self.pos..self.cap]))
+                .map_err(TransferError::Write)?;
+
+                if n == 0 {
+                    return Poll::Ready(Err(TransferError::Write(Error::from(
+                        ErrorKind::WriteZero,
+                    ))));
+                }
+
+                self.pos += n;
+                self.amt += n as u64;
+                self.need_flush = true;
+            }
+
+            if self.read_done {
+                // propagate the half-close
+                ready!(writer.as_mut().poll_flush(cx)).map_err(TransferError::Write)?;
+                ready!(writer.as_mut().poll_shutdown(cx)).map_err(TransferError::Write)?;
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{self, AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    /// A stream whose writes fail, and whose reads fail or never complete.
+    struct Broken {
+        read_fails: bool,
+    }
+
+    impl AsyncRead for Broken {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            _: &mut ReadBuf<'_>,
+        ) -> Poll<Result<(), Error>> {
+            if self.read_fails {
+                Poll::Ready(Err(Error::from(ErrorKind::ConnectionReset)))
+            } else {
+                Poll::Pending
+            }
+        }
+    }
+
+    impl AsyncWrite for Broken {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            _: &[u8],
+        ) -> Poll<Result<usize, Error>> {
+            Poll::Ready(Err(Error::from(ErrorKind::BrokenPipe)))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Error>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn config() -> RelayConfig {
+        RelayConfig {
+            client_buffer_size: 4,
+            remote_buffer_size: 4,
+            idle_timeout: Some(Duration::from_secs(10)),
+        }
+    }
+
+    async fn peer_with(data: &[u8]) -> DuplexStream {
+        let (mut peer, stream) = io::duplex(64);
+        peer.write_all(data).await.unwrap();
+        // keep the peer open, so the stream neither ends nor fails
+        tokio::spawn(async move {
+            let mut buf = Vec::new();
+            let _ = peer.read_to_end(&mut buf).await;
+        });
+        stream
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn completed_with_half_close() {
+        let (mut client, mut client_side) = io::duplex(64);
+        let (mut remote_side, mut remote) = io::duplex(64);
+
+        let relay =
+            tokio::spawn(async move { relay(&mut client_side, &mut remote_side, config()).await });
+
+        client.write_all(b"hello, remote").await.unwrap();
+        client.shutdown().await.unwrap();
+
+        // the client's half-close reaches the remote, which can still answer
+        let mut buf = Vec::new();
+        remote.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"hello, remote");
+
+        remote.write_all(b"hello, client!").await.unwrap();
+        remote.shutdown().await.unwrap();
+
+        let mut buf = Vec::new();
+        client.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, b"hello, client!");
+
+        let stats = relay.await.unwrap();
+        assert!(matches!(stats.reason, CloseReason::Completed));
+        assert_eq!(stats.client_to_remote, 13);
+        assert_eq!(stats.remote_to_client, 14);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn idle_timeout() {
+        let (mut client, mut client_side) = io::duplex(64);
+        let (mut remote_side, mut remote) = io::duplex(64);
+
+        let start = Instant::now();
+        let relay =
+            tokio::spawn(async move { relay(&mut client_side, &mut remote_side, config()).await });
+
+        // traffic resets the timer
+        for _ in 0..3 {
+            time::sleep(Duration::from_secs(6)).await;
+            client.write_all(b"ping").await.unwrap();
+            let mut buf = [0; 4];
+            remote.read_exact(&mut buf).await.unwrap();
+        }
+
+        let stats = relay.await.unwrap();
+        assert!(matches!(stats.reason, CloseReason::IdleTimeout));
+        assert_eq!(stats.client_to_remote, 12);
+        assert_eq!(start.elapsed(), Duration::from_secs(28));
+    }
+
+    #[tokio::test]
+    async fn client_error() {
+        // reading from the client fails
+        let mut remote = peer_with(b"").await;
+        let stats = relay(&mut Broken { read_fails: true }, &mut remote, config()).await;
+        assert!(
+            matches!(stats.reason, CloseReason::ClientError(err) if err.kind() == ErrorKind::ConnectionReset)
+        );
+
+        // writing to the client fails
+        let mut remote = peer_with(b"data").await;
+        let stats = relay(&mut Broken { read_fails: false }, &mut remote, config()).await;
+        assert!(
+            matches!(stats.reason, CloseReason::ClientError(err) if err.kind() == ErrorKind::BrokenPipe)
+        );
+    }
+
+    #[tokio::test]
+    async fn remote_error() {
+        // reading from the remote fails
+        let mut client = peer_with(b"").await;
+        let stats = relay(&mut client, &mut Broken { read_fails: true }, config()).await;
+        assert!(
+            matches!(stats.reason, CloseReason::RemoteError(err) if err.kind() == ErrorKind::ConnectionReset)
+        );
+
+        // writing to the remote fails
+        let mut client = peer_with(b"data").await;
+        let stats = relay(&mut client, &mut Broken { read_fails: false }, config()).await;
+        assert!(
+            matches!(stats.reason, CloseReason::RemoteError(err) if err.kind() == ErrorKind::BrokenPipe)
+        );
+        assert_eq!(stats.client_to_remote, 0);
+    }
+}
@@ -0,0 +1,360 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+/// Limits of a [`SessionTable`](https://docs.rs/socks5-server/latest/socks5_server/relay/struct.SessionTable.html).
+#[derive(Clone, Copy, Debug)]
+pub struct SessionConfig {
+    /// A mapping expires if no datagram is sent to or received from its destination for this long. Defaults to 2 minutes, as recommended by RFC 4787.
+    pub mapping_idle_timeout: Duration,
+    /// Maximum number of live mappings of a single association. Defaults to 256.
+    pub max_mappings_per_association: usize,
+    /// Maximum number of live mappings of all associations of a client IP. Defaults to 1024.
+    pub max_mappings_per_client: usize,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            mapping_idle_timeout: Duration::from_secs(120),
+            max_mappings_per_association: 256,
+            max_mappings_per_client: 1024,
+        }
+    }
+}
+
+/// Counters of a [`SessionTable`](https://docs.rs/socks5-server/latest/socks5_server/relay/struct.SessionTable.html).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SessionStats {
+    /// Open associations.
+    pub associations: usize,
+    /// Mappings currently counted against the limits. Idle mappings are only removed when expired.
+    pub mappings: usize,
+    /// Mappings created since the table was created.
+    pub mappings_created: u64,
+    /// Mappings expired since the table was created.
+    pub mappings_expired: u64,
+    /// Mappings refused because of a limit since the table was created.
+    pub mappings_rejected: u64,
+}
+
+/// The error returned by [`Session::map()`](https://docs.rs/socks5-server/latest/socks5_server/relay/struct.Session.html#method.map) when a new mapping would exceed a limit.
+#[derive(Clone, Copy, Debug, Error, PartialEq, Eq)]
+pub enum LimitExceeded {
+    #[error("Too many mappings for the association")]
+    Association,
+    #[error("Too many mappings for the client IP")]
+    Client,
+}
+
+/// The UDP associations of a server and their per-destination NAT mappings.
+///
+/// An association [opens](https://docs.rs/socks5-server/latest/socks5_server/relay/struct.SessionTable.html#method.open) a [`Session`](https://docs.rs/socks5-server/latest/socks5_server/relay/struct.Session.html), which is removed from the table when dropped. Every destination a session sends datagrams to gets a mapping, which expires after [`mapping_idle_timeout`](https://docs.rs/socks5-server/latest/socks5_server/relay/struct.SessionConfig.html#structfield.mapping_idle_timeout) without traffic. The number of live mappings is capped per association and per client IP.
+///
+/// Expired mappings are removed lazily when the session is used, or when a limit is reached. [`expire()`](https://docs.rs/socks5-server/latest/socks5_server/relay/struct.SessionTable.html#method.expire) can be called periodically to remove them eagerly.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_server::relay::{SessionTable, UdpRelayConfig};
+/// use std::sync::Arc;
+///
+/// let sessions = Arc::new(SessionTable::default());
+///
+/// let config = UdpRelayConfig {
+///     sessions: Some(sessions.clone()),
+///     ..Default::default()
+/// };
+///
+/// // later, e.g. from a metrics endpoint
+/// let stats = sessions.stats();
+/// ```
+#[derive(Debug)]
+pub struct SessionTable {
+    config: SessionConfig,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    next_id: u64,
+    associations: HashMap<u64, Association>,
+    clients: HashMap<IpAddr, usize>,
+    created: u64,
+    expired: u64,
+    rejected: u64,
+}
+
+#[derive(Debug)]
+struct Association {
+    client: IpAddr,
+    mappings: HashMap<SocketAddr, Instant>,
+}
+
+impl State {
+    /// Removes the expired mappings of an association. Returns the number of mappings removed.
+    fn expire_association(&mut self, id: u64, now: Instant, timeout: Duration) -> usize {
+        let Some(assoc) = self.associations.get_mut(&id) else {
+            return 0;
+        };
+
+        let before = assoc.mappings.len();
+        assoc
+            .mappings
+            .retain(|_, last_active| now.duration_since(*last_active) < timeout);
+        let expired = before - assoc.mappings.len();
+
+        if expired > 0 {
+            let client = assoc.client;
+            self.release(client, expired);
+            self.expired += expired as u64;
+        }
+
+        expired
+    }
+
+    fn release(&mut self, client: IpAddr, count: usize) {
+        if let Some(mappings) = self.clients.get_mut(&client) {
+            *mappings -= count;
+
+            if *mappings == 0 {
+                self.clients.remove(&client);
+            }
+        }
+    }
+}
+
+impl SessionTable {
+    /// Creates a new `SessionTable` with the given limits.
+    pub fn new(config: SessionConfig) -> Self {
+        Self {
+            config,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// Returns the limits of this table.
+    #[inline]
+    pub fn config(&self) -> &SessionConfig {
+        &self.config
+    }
+
+    /// Opens a session for an association of the client `client`.
+    pub fn open(self: &Arc<Self>, client: IpAddr) -> Session {
+        let client = client.to_canonical();
+        let mut state = self.state.lock().unwrap();
+
+        let id = state.next_id;
+        state.next_id += 1;
+
+        state.associations.insert(
+            id,
+            Association {
+                client,
+                mappings: HashMap::new(),
+            },
+        );
+
+        Session {
+            table: self.clone(),
+            id,
+            client,
+        }
+    }
+
+    /// Removes all expired mappings. Returns the number of mappings removed.
+    pub fn expire(&self) -> usize {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        let ids = state.associations.keys().copied().collect::<Vec<_>>();
+
+        ids.into_iter()
+            .map(|id| state.expire_association(id, now, self.config.mapping_idle_timeout))
+            .sum()
+    }
+
+    /// Returns the number of mappings of a client IP currently counted against the limit.
+    pub fn client_mappings(&self, client: IpAddr) -> usize {
+        let state = self.state.lock().unwrap();
+        state
+            .clients
+            .get(&client.to_canonical())
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Returns the counters of this table.
+    pub fn stats(&self) -> SessionStats {
+        let state = self.state.lock().unwrap();
+
+        SessionStats {
+            associations: state.associations.len(),
+            mappings: state.clients.values().sum(),
+            mappings_created: state.created,
+            mappings_expired: state.expired,
+            mappings_rejected: state.rejected,
+        }
+    }
+}
+
+impl Default for SessionTable {
+    fn default() -> Self {
+        Self::new(SessionConfig::default())
+    }
+}
+
+/// An association in a [`SessionTable`](https://docs.rs/socks5-server/latest/socks5_server/relay/struct.SessionTable.html), removed from the table with its mappings when dropped.
+#[derive(Debug)]
+pub struct Session {
+    table: Arc<SessionTable>,
+    id: u64,
+    client: IpAddr,
+}
+
+impl Session {
+    /// Returns the client IP of this association.
+    #[inline]
+    pub fn client(&self) -> IpAddr {
+        self.client
+    }
+
+    /// Returns the table this session belongs to.
+    #[inline]
+    pub fn table(&self) -> &Arc<SessionTable> {
+        &self.table
+    }
+
+    /// Records an outbound datagram to `dst`, creating its mapping if needed or refreshing it otherwise. Returns whether the mapping was created.
+    ///
+    /// A new mapping is refused if the association or the client IP already has the maximum number of live mappings.
+    pub fn map(&self, dst: SocketAddr) -> Result<bool, LimitExceeded> {
+        let config = &self.table.config;
+        let now = Instant::now();
+        let mut state = self.table.state.lock().unwrap();
+
+        let Some(assoc) = state.associations.get_mut(&self.id) else {
+            return Ok(false);
+        };
+
+        if let Some(last_active) = assoc.mappings.get_mut(&dst) {
+            if now.duration_since(*last_active) < config.mapping_idle_timeout {
+                *last_active = now;
+                return Ok(false);
+            }
+        }
+
+        state.expire_association(self.id, now, config.mapping_idle_timeout);
+
+        if state.associations[&self.id].mappings.len() >= config.max_mappings_per_association {
+            state.rejected += 1;
+            return Err(LimitExceeded::Association);
+        }
+
+        if state.clients.get(&self.client).copied().unwrap_or(0) >= config.max_mappings_per_client {
+            // other associations of the client may hold expired mappings
+            let ids = state
+                .associations
+                .iter()
+                .filter(|(_, assoc)| assoc.client == self.client)
+                .map(|(id, _)| *id)
+                .collect::<Vec<_>>();
+
+            for id in ids {
+                state.expire_association(id, now, config.mapping_idle_timeout);
+            }
+
+            if state.clients.get(&self.client).copied().unwrap_or(0)
+                >= config.max_mappings_per_client
+            {
+                state.rejected += 1;
+                return Err(LimitExceeded::Client);
+            }
+        }
+
+        state
+            .associations
+            .get_mut(&self.id)
+            .unwrap()
+            .mappings
+            .insert(dst, now);
+        *state.clients.entry(self.client).or_insert(0) += 1;
+        state.created += 1;
+
+        Ok(true)
+    }
+
+    /// Records an inbound datagram from `src`, refreshing its mapping. Returns whether `src` has a live mapping.
+    pub fn refresh(&self, src: SocketAddr) -> bool {
+        let config = &self.table.config;
+        let now = Instant::now();
+        let mut state = self.table.state.lock().unwrap();
+
+        let Some(assoc) = state.associations.get_mut(&self.id) else {
+            return false;
+        };
+
+        match assoc.mappings.get_mut(&src) {
+            Some(last_active) if now.duration_since(*last_active) < config.mapping_idle_timeout => {
+                *last_active = now;
+                true
+            }
+            Some(_) => {
+                state.expire_association(self.id, now, config.mapping_idle_timeout);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// Records an inbound datagram from the IP address `ip`, refreshing the mappings with this address. Returns whether any of them is live.
+    pub fn refresh_address(&self, ip: IpAddr) -> bool {
+        let config = &self.table.config;
+        let now = Instant::now();
+        let mut state = self.table.state.lock().unwrap();
+
+        let Some(assoc) = state.associations.get_mut(&self.id) else {
+            return false;
+        };
+
+        let mut is_mapped = false;
+
+        for (dst, last_active) in &mut assoc.mappings {
+            if dst.ip() == ip && now.duration_since(*last_active) < config.mapping_idle_timeout {
+                *last_active = now;
+                is_mapped = true;
+            }
+        }
+
+        is_mapped
+    }
+
+    /// Returns the destinations of the live mappings of this association.
+    pub fn mappings(&self) -> Vec<SocketAddr> {
+        let config = &self.table.config;
+        let now = Instant::now();
+        let state = self.table.state.lock().unwrap();
+
+        state.associations[&self.id]
+            .mappings
+            .iter()
+            .filter(|(_, last_active)| {
+                now.duration_since(**last_active) < config.mapping_idle_timeout
+            })
+            .map(|(dst, _)| *dst)
+            .collect()
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let mut state = self.table.state.lock().unwrap();
+
+        if let Some(assoc) = state.associations.remove(&self.id) {
+            state.release(assoc.client, assoc.mappings.len());
+        }
+    }
+}
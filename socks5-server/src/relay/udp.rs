@@ -0,0 +1,806 @@
+use super::{CloseReason, Session, SessionConfig, SessionTable};
+use crate::connection::associate::{Associate, AssociatedUdpSocket, Ready};
+use bytes::{Bytes, BytesMut};
+use socks5_proto::{Address, UdpFrame, UdpHeader};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    future,
+    io::Error,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    task::Poll,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf},
+    net::{self, UdpSocket},
+    task::{JoinError, JoinSet},
+    time::{self, Instant, Interval, MissedTickBehavior},
+};
+
+/// How the datagrams of an association to different destinations are mapped to outbound sockets, as defined in RFC 4787.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NatMapping {
+    /// One outbound socket per address family for all destinations of the association, so remotes see the same source endpoint regardless of the destination. Peer-to-peer protocols, e.g. in games and WebRTC, rely on it.
+    #[default]
+    EndpointIndependent,
+    /// One outbound socket per destination, so remotes can not correlate the endpoints of an association.
+    AddressAndPortDependent,
+}
+
+/// Which remotes are allowed to send datagrams to an association, as defined in RFC 4787.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum NatFiltering {
+    /// Any remote, i.e. a full-cone NAT.
+    #[default]
+    EndpointIndependent,
+    /// Remotes with an IP address the association sent a datagram to, i.e. a restricted-cone NAT.
+    AddressDependent,
+    /// Remotes with an address and port the association sent a datagram to, i.e. a port-restricted-cone NAT.
+    AddressAndPortDependent,
+}
+
+/// Options of a UDP relay.
+#[derive(Clone, Debug)]
+pub struct UdpRelayConfig {
+    /// Local address of the outbound socket used for IPv4 destinations. Defaults to `0.0.0.0:0`.
+    pub outbound_v4: SocketAddr,
+    /// Local address of the outbound socket used for IPv6 destinations. Defaults to `[::]:0`.
+    pub outbound_v6: SocketAddr,
+    /// Ends the relay if no datagram is relayed in either direction for this long, dropped datagrams not included. Defaults to `None`, which never times out.
+    pub idle_timeout: Option<Duration>,
+    /// The table tracking the association and its per-destination mappings. Defaults to `None`, which does not limit the destinations.
+    pub sessions: Option<Arc<SessionTable>>,
+    /// How destinations are mapped to outbound sockets. Defaults to [`NatMapping::EndpointIndependent`](https://docs.rs/socks5-server/latest/socks5_server/relay/enum.NatMapping.html#variant.EndpointIndependent).
+    pub mapping: NatMapping,
+    /// Which remotes may send datagrams to the client. Defaults to [`NatFiltering::EndpointIndependent`](https://docs.rs/socks5-server/latest/socks5_server/relay/enum.NatFiltering.html#variant.EndpointIndependent).
+    pub filtering: NatFiltering,
+}
+
+impl Default for UdpRelayConfig {
+    fn default() -> Self {
+        Self {
+            outbound_v4: SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            outbound_v6: SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+            idle_timeout: None,
+            sessions: None,
+            mapping: NatMapping::default(),
+            filtering: NatFiltering::default(),
+        }
+    }
+}
+
+/// The result of a UDP relay.
+#[derive(Debug)]
+pub struct UdpRelayStats {
+    /// Datagrams sent to remotes.
+    pub client_to_remote_packets: u64,
+    /// Payload bytes sent to remotes.
+    pub client_to_remote_bytes: u64,
+    /// Datagrams sent to the client.
+    pub remote_to_client_packets: u64,
+    /// Payload bytes sent to the client.
+    pub remote_to_client_bytes: u64,
+    /// Datagrams that were dropped, e.g. malformed, fragmented, unresolvable, exceeding a mapping limit, filtered or failed to be sent.
+    pub dropped_packets: u64,
+    /// Why the relay ended. [`CloseReason::Completed`](https://docs.rs/socks5-server/latest/socks5_server/relay/enum.CloseReason.html#variant.Completed) means that the client closed the control TCP connection.
+    pub reason: CloseReason,
+}
+
+/// Relays UDP datagrams of an association until the client closes the control TCP connection, or the idle timeout elapses.
+///
+/// Datagrams received on `socket` are sent to the destination in their SOCKS5 UDP header. Domain names are resolved in spawned tasks, so a slow lookup does not hold up other datagrams, and datagrams are dropped while 64 lookups of the association are pending. Datagrams received from remotes are sent back to the client with the source address of the remote in the header. Replies are sent to the address the client last sent a datagram from. Fragmented datagrams are dropped, unless [reassembly](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.enable_reassembly) is enabled on `socket`, in which case reassembled datagrams are relayed.
+///
+/// Outbound sockets are bound lazily and closed when the relay ends. With [`NatMapping::EndpointIndependent`](https://docs.rs/socks5-server/latest/socks5_server/relay/enum.NatMapping.html#variant.EndpointIndependent), there is one for IPv4 and one for IPv6 destinations. With [`NatMapping::AddressAndPortDependent`](https://docs.rs/socks5-server/latest/socks5_server/relay/enum.NatMapping.html#variant.AddressAndPortDependent), there is one per destination, closed within a [`mapping_idle_timeout`](https://docs.rs/socks5-server/latest/socks5_server/relay/struct.SessionConfig.html#structfield.mapping_idle_timeout) after its mapping expires, and replies from the destination carry the address the client sent to, domain name included, in their header.
+///
+/// Every destination gets a mapping in a [`Session`](https://docs.rs/socks5-server/latest/socks5_server/relay/struct.Session.html) of `client_ip`, which should be the IP address of the control TCP connection's peer. Datagrams from remotes are dropped according to [`filtering`](https://docs.rs/socks5-server/latest/socks5_server/relay/struct.UdpRelayConfig.html#structfield.filtering) and the live mappings. With a [`SessionTable`](https://docs.rs/socks5-server/latest/socks5_server/relay/struct.SessionTable.html) configured, datagrams to a new destination are also dropped once the association or the client IP reaches its mapping limit. Otherwise, a private table without limits is used.
+///
+/// To only relay datagrams from the client that requested the association, call [`AssociatedUdpSocket::restrict_client()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.AssociatedUdpSocket.html#method.restrict_client) before relaying.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_proto::{Address, Reply};
+/// use socks5_server::{
+///     connection::associate::{Associate, AssociatedUdpSocket, NeedReply},
+///     relay::{self, UdpRelayConfig},
+/// };
+/// use tokio::net::UdpSocket;
+///
+/// async fn associate(assoc: Associate<NeedReply>) {
+///     let socket = UdpSocket::bind("0.0.0.0:0").await.unwrap();
+///     let addr = Address::SocketAddress(socket.local_addr().unwrap());
+///     let socket = AssociatedUdpSocket::from((socket, 1500));
+///     let client_ip = assoc.peer_addr().unwrap().ip();
+///
+///     let mut assoc = assoc
+///         .reply(Reply::Succeeded, addr)
+///         .await
+///         .map_err(|(err, _)| err)
+///         .unwrap();
+///
+///     let stats = relay::relay_udp(&mut assoc, &socket, client_ip, UdpRelayConfig::default()).await;
+/// }
+/// ```
+pub async fn relay_udp<T>(
+    assoc: &mut Associate<Ready, T>,
+    socket: &AssociatedUdpSocket,
+    client_ip: IpAddr,
+    config: UdpRelayConfig,
+) -> UdpRelayStats
+where
+    T: AsyncRead + Unpin,
+{
+    let idle = time::sleep(config.idle_timeout.unwrap_or_default());
+    tokio::pin!(idle);
+
+    let mut relay = UdpRelay::new(config, client_ip);
+    let mut resolving = JoinSet::new();
+    let mut expiry = relay.expiry_interval();
+
+    // reused for every datagram, leaving a headroom for the header before datagrams from remotes
+    let mut client_buf = BytesMut::new();
+    let mut remote_buf =
+        vec![0; UdpHeader::MAX_SERIALIZED_LEN + u16::MAX as usize].into_boxed_slice();
+
+    // the address the client last sent a datagram from, which replies are sent to
+    let mut client_addr = None;
+
+    relay.stats.reason = loop {
+        let relayed = relay.relayed();
+
+        tokio::select! {
+            res = assoc.wait_until_closed() => match res {
+                Ok(()) => break CloseReason::Completed,
+                Err(err) => break CloseReason::ClientError(err),
+            },
+            res = socket.recv_buf_from(&mut client_buf) => match res {
+                Ok((pkt, header, addr)) => {
+                    if let Some(addr) = relay.handle_outbound(pkt, header, addr, &mut resolving).await {
+                        client_addr = Some(addr);
+                    }
+                }
+                Err((_, Some(_))) => relay.stats.dropped_packets += 1,
+                Err((err, None)) => break CloseReason::ClientError(err.into()),
+            },
+            Some(res) = resolving.join_next() => {
+                if let Some(addr) = relay.handle_resolved(res).await {
+                    client_addr = Some(addr);
+                }
+            }
+            res = relay.recv_inbound(&mut remote_buf[UdpHeader::MAX_SERIALIZED_LEN..]) => match res {
+                Ok((len, src, dst)) => {
+                    let Some(client_addr) = client_addr else {
+                        relay.stats.dropped_packets += 1;
+                        continue;
+                    };
+
+                    if let Some(header) = relay.handle_inbound(src, dst) {
+                        let buf = &mut remote_buf[..UdpHeader::MAX_SERIALIZED_LEN + len];
+                        let res = socket
+                            .send_to_with_headroom(buf, UdpHeader::MAX_SERIALIZED_LEN, &header, client_addr)
+                            .await;
+                        relay.record_inbound(res);
+                    }
+                }
+                // e.g. an ICMP port unreachable from one remote reported as `WSAECONNRESET` on Windows
+                Err(_) => relay.stats.dropped_packets += 1,
+            },
+            _ = expiry.tick() => relay.close_expired(),
+            () = &mut idle, if relay.config.idle_timeout.is_some() => break CloseReason::IdleTimeout,
+        }
+
+        // datagrams that were dropped do not keep the association alive
+        if let Some(timeout) = relay
+            .config
+            .idle_timeout
+            .filter(|_| relay.relayed() != relayed)
+        {
+            idle.as_mut().reset(Instant::now() + timeout);
+        }
+    };
+
+    relay.stats
+}
+
+/// Relays the datagrams of a UDP-over-TCP association, framed on `stream`, until the client closes it, or the idle timeout elapses.
+///
+/// Datagrams are exchanged with the client as [`UdpFrame`](https://docs.rs/socks5-proto/latest/socks5_proto/struct.UdpFrame.html)s, and are otherwise relayed like [`relay_udp()`](https://docs.rs/socks5-server/latest/socks5_server/relay/fn.relay_udp.html) does, with `client_ip` as the client IP in the session. Malformed frames are dropped.
+///
+/// `stream` is usually the control TCP connection itself, see [`Associate::relay_over_tcp()`](https://docs.rs/socks5-server/latest/socks5_server/connection/associate/struct.Associate.html#method.relay_over_tcp). Datagrams can also be carried on a second TCP connection, in which case the association should end when either connection is closed.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_proto::{Reply, UdpFrame};
+/// use socks5_server::{
+///     connection::associate::{Associate, NeedReply},
+///     relay::UdpRelayConfig,
+/// };
+///
+/// async fn associate(assoc: Associate<NeedReply>) {
+///     let client_ip = assoc.peer_addr().unwrap().ip();
+///
+///     let mut assoc = assoc
+///         .reply(Reply::Succeeded, UdpFrame::associate_address())
+///         .await
+///         .map_err(|(err, _)| err)
+///         .unwrap();
+///
+///     let stats = assoc
+///         .relay_over_tcp(client_ip, UdpRelayConfig::default())
+///         .await;
+/// }
+/// ```
+pub async fn relay_udp_over_tcp<S>(
+    stream: &mut S,
+    client_ip: IpAddr,
+    config: UdpRelayConfig,
+) -> UdpRelayStats
+where
+    S: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    let idle = time::sleep(config.idle_timeout.unwrap_or_default());
+    tokio::pin!(idle);
+
+    let mut relay = UdpRelay::new(config, client_ip);
+    let mut resolving = JoinSet::new();
+    let mut expiry = relay.expiry_interval();
+
+    let mut read_buf = BytesMut::new();
+    let mut write_buf = BytesMut::new();
+    let mut remote_buf = vec![0; u16::MAX as usize].into_boxed_slice();
+
+    relay.stats.reason = loop {
+        let relayed = relay.relayed();
+
+        tokio::select! {
+            res = stream.read_buf(&mut read_buf) => match res {
+                Ok(0) => break CloseReason::Completed,
+                Ok(_) => loop {
+                    match UdpFrame::decode(&mut read_buf) {
+                        Ok(Some(frame)) => {
+                            relay.handle_outbound(frame.payload, frame.header, (), &mut resolving).await;
+                        }
+                        Ok(None) => break,
+                        Err(_) => relay.stats.dropped_packets += 1,
+                    }
+                },
+                Err(err) => break CloseReason::ClientError(err),
+            },
+            Some(res) = resolving.join_next() => {
+                relay.handle_resolved(res).await;
+            }
+            res = relay.recv_inbound(&mut remote_buf) => match res {
+                Ok((len, src, dst)) => {
+                    let Some(header) = relay.handle_inbound(src, dst) else {
+                        continue;
+                    };
+
+                    let frame = UdpFrame::new(header, Bytes::copy_from_slice(&remote_buf[..len]));
+
+                    if frame.serialized_len() - 2 > UdpFrame::MAX_LEN {
+                        relay.stats.dropped_packets += 1;
+                        continue;
+                    }
+
+                    write_buf.clear();
+                    frame.encode(&mut write_buf);
+
+                    if let Err(err) = stream.write_all(&write_buf).await {
+                        break CloseReason::ClientError(err);
+                    }
+
+                    relay.record_inbound(Ok(len));
+                }
+                // e.g. an ICMP port unreachable from one remote reported as `WSAECONNRESET` on Windows
+                Err(_) => relay.stats.dropped_packets += 1,
+            },
+            _ = expiry.tick() => relay.close_expired(),
+            () = &mut idle, if relay.config.idle_timeout.is_some() => break CloseReason::IdleTimeout,
+        }
+
+        // datagrams that were dropped do not keep the association alive
+        if let Some(timeout) = relay
+            .config
+            .idle_timeout
+            .filter(|_| relay.relayed() != relayed)
+        {
+            idle.as_mut().reset(Instant::now() + timeout);
+        }
+    };
+
+    relay.stats
+}
+
+/// The maximum number of domain names of an association being resolved at once.
+const MAX_RESOLVING: usize = 64;
+
+/// A datagram from the client with its destination resolved, alongside where it came from.
+type Resolved<A> = (Option<SocketAddr>, Bytes, Address, A);
+
+struct UdpRelay {
+    config: UdpRelayConfig,
+    outbound_v4: Option<UdpSocket>,
+    outbound_v6: Option<UdpSocket>,
+    /// Outbound sockets by destination, alongside the address the client last sent to, with `NatMapping::AddressAndPortDependent`.
+    per_destination: HashMap<SocketAddr, (UdpSocket, Address)>,
+    session: Session,
+    stats: UdpRelayStats,
+}
+
+impl UdpRelay {
+    fn new(config: UdpRelayConfig, client_ip: IpAddr) -> Self {
+        let sessions = config.sessions.clone().unwrap_or_else(|| {
+            Arc::new(SessionTable::new(SessionConfig {
+                max_mappings_per_association: usize::MAX,
+                max_mappings_per_client: usize::MAX,
+                ..SessionConfig::default()
+            }))
+        });
+
+        Self {
+            session: sessions.open(client_ip),
+            config,
+            outbound_v4: None,
+            outbound_v6: None,
+            per_destination: HashMap::new(),
+            stats: UdpRelayStats {
+                client_to_remote_packets: 0,
+                client_to_remote_bytes: 0,
+                remote_to_client_packets: 0,
+                remote_to_client_bytes: 0,
+                dropped_packets: 0,
+                reason: CloseReason::Completed,
+            },
+        }
+    }
+
+    /// Sends a datagram from the client to its destination, or starts resolving the domain name of its destination in `resolving`. Returns `from` if the datagram was accepted, i.e. its destination is mapped, even if sending it failed.
+    async fn handle_outbound<A>(
+        &mut self,
+        pkt: Bytes,
+        header: UdpHeader,
+        from: A,
+        resolving: &mut JoinSet<Resolved<A>>,
+    ) -> Option<A>
+    where
+        A: Send + 'static,
+    {
+        if header.frag != 0 {
+            self.stats.dropped_packets += 1;
+            return None;
+        }
+
+        match header.address {
+            Address::SocketAddress(dst) => {
+                self.send_outbound(&pkt, dst, header.address, from).await
+            }
+            Address::DomainAddress(..) if resolving.len() >= MAX_RESOLVING => {
+                self.stats.dropped_packets += 1;
+                None
+            }
+            address => {
+                resolving.spawn(async move { (resolve(&address).await, pkt, address, from) });
+                None
+            }
+        }
+    }
+
+    /// Sends a datagram once its destination is resolved. Returns where it came from if it was accepted.
+    async fn handle_resolved<A>(&mut self, res: Result<Resolved<A>, JoinError>) -> Option<A> {
+        match res {
+            Ok((Some(dst), pkt, address, from)) => {
+                self.send_outbound(&pkt, dst, address, from).await
+            }
+            _ => {
+                self.stats.dropped_packets += 1;
+                None
+            }
+        }
+    }
+
+    /// Maps `dst` and sends a datagram to it. `address` is the address the client sent to.
+    async fn send_outbound<A>(
+        &mut self,
+        pkt: &[u8],
+        dst: SocketAddr,
+        address: Address,
+        from: A,
+    ) -> Option<A> {
+        if self.session.map(dst).is_err() {
+            self.stats.dropped_packets += 1;
+            return None;
+        }
+
+        let Ok(outbound) = self.outbound(dst, address).await else {
+            self.stats.dropped_packets += 1;
+            return Some(from);
+        };
+
+        match outbound.send_to(pkt, dst).await {
+            Ok(_) => {
+                self.stats.client_to_remote_packets += 1;
+                self.stats.client_to_remote_bytes += pkt.len() as u64;
+            }
+            Err(_) => self.stats.dropped_packets += 1,
+        }
+
+        Some(from)
+    }
+
+    /// Returns the interval at which the sockets of expired mappings are closed.
+    fn expiry_interval(&self) -> Interval {
+        let period = self
+            .session
+            .table()
+            .config()
+            .mapping_idle_timeout
+            .max(Duration::from_millis(1));
+
+        let mut interval = time::interval_at(Instant::now() + period, period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        interval
+    }
+
+    /// Closes the per-destination sockets of expired mappings.
+    fn close_expired(&mut self) {
+        if self.per_destination.is_empty() {
+            return;
+        }
+
+        let mappings = self.session.mappings();
+        self.per_destination.retain(|dst, _| mappings.contains(dst));
+    }
+
+    /// Filters a datagram from the remote `src`, returning the header to send it to the client with. `dst` is the destination of the per-destination socket it was received on, if any.
+    fn handle_inbound(&mut self, src: SocketAddr, dst: Option<SocketAddr>) -> Option<UdpHeader> {
+        let session = &self.session;
+
+        let is_allowed = match self.config.filtering {
+            NatFiltering::EndpointIndependent => {
+                session.refresh(src);
+                true
+            }
+            NatFiltering::AddressDependent => session.refresh_address(src.ip()),
+            NatFiltering::AddressAndPortDependent => session.refresh(src),
+        };
+
+        if !is_allowed {
+            self.stats.dropped_packets += 1;
+            return None;
+        }
+
+        let address = match dst.and_then(|dst| self.per_destination.get(&dst)) {
+            Some((_, address)) if dst == Some(src) => address.clone(),
+            _ => Address::SocketAddress(SocketAddr::new(src.ip().to_canonical(), src.port())),
+        };
+
+        Some(UdpHeader::new(0, address))
+    }
+
+    /// Returns the number of datagrams relayed in either direction.
+    fn relayed(&self) -> u64 {
+        self.stats.client_to_remote_packets + self.stats.remote_to_client_packets
+    }
+
+    /// Records the result of sending a datagram from a remote to the client.
+    fn record_inbound(&mut self, res: Result<usize, Error>) {
+        match res {
+            Ok(len) => {
+                self.stats.remote_to_client_packets += 1;
+                self.stats.remote_to_client_bytes += len as u64;
+            }
+            Err(_) => self.stats.dropped_packets += 1,
+        }
+    }
+
+    /// Returns the outbound socket for `dst`, binding it if needed. `address` is the address the client sent to.
+    async fn outbound(&mut self, dst: SocketAddr, address: Address) -> Result<&UdpSocket, Error> {
+        let bind_addr = match dst {
+            SocketAddr::V4(_) => self.config.outbound_v4,
+            SocketAddr::V6(_) => self.config.outbound_v6,
+        };
+
+        if self.config.mapping == NatMapping::AddressAndPortDependent {
+            let (socket, _) = match self.per_destination.entry(dst) {
+                Entry::Occupied(entry) => {
+                    let entry = entry.into_mut();
+                    entry.1 = address;
+                    entry
+                }
+                Entry::Vacant(entry) => {
+                    let socket = UdpSocket::bind(bind_addr).await?;
+                    entry.insert((socket, address))
+                }
+            };
+
+            return Ok(socket);
+        }
+
+        let socket = match dst {
+            SocketAddr::V4(_) => &mut self.outbound_v4,
+            SocketAddr::V6(_) => &mut self.outbound_v6,
+        };
+
+        if socket.is_none() {
+            *socket = Some(UdpSocket::bind(bind_addr).await?);
+        }
+
+        Ok(socket.as_ref().unwrap())
+    }
+
+    /// Receives a datagram on any outbound socket, returning the destination of the socket if it is a per-destination one. Never resolves if no outbound socket is bound yet.
+    async fn recv_inbound(
+        &self,
+        buf: &mut [u8],
+    ) -> Result<(usize, SocketAddr, Option<SocketAddr>), Error> {
+        future::poll_fn(|cx| {
+            let shared = [&self.outbound_v4, &self.outbound_v6]
+                .into_iter()
+                .flatten()
+                .map(|socket| (socket, None));
+            let per_destination = self
+                .per_destination
+                .iter()
+                .map(|(dst, (socket, _))| (socket, Some(*dst)));
+
+            for (socket, dst) in shared.chain(per_destination) {
+                let mut buf = ReadBuf::new(&mut *buf);
+
+                if let Poll::Ready(res) = socket.poll_recv_from(cx, &mut buf) {
+                    return Poll::Ready(res.map(|addr| (buf.filled().len(), addr, dst)));
+                }
+            }
+
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+async fn resolve(addr: &Address) -> Option<SocketAddr> {
+    match addr {
+        Address::SocketAddress(addr) => Some(*addr),
+        Address::DomainAddress(host, port) => {
+            let host = std::str::from_utf8(host).ok()?;
+            let mut addrs = net::lookup_host((host, *port)).await.ok()?;
+            addrs.next()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{self, DuplexStream};
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn config(mapping: NatMapping, filtering: NatFiltering) -> UdpRelayConfig {
+        UdpRelayConfig {
+            outbound_v4: SocketAddr::from((LOCALHOST, 0)),
+            mapping,
+            filtering,
+            ..Default::default()
+        }
+    }
+
+    async fn remote(ip: [u8; 4]) -> (UdpSocket, SocketAddr) {
+        let socket = UdpSocket::bind((Ipv4Addr::from(ip), 0)).await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        (socket, addr)
+    }
+
+    async fn send(client: &mut DuplexStream, pkt: &'static [u8], dst: Address) {
+        UdpFrame::new(UdpHeader::new(0, dst), Bytes::from_static(pkt))
+            .write_to(client)
+            .await
+            .unwrap();
+    }
+
+    async fn recv(client: &mut DuplexStream) -> UdpFrame {
+        time::timeout(Duration::from_secs(5), UdpFrame::read_from(client))
+            .await
+            .unwrap()
+            .unwrap()
+    }
+
+    /// Receives a datagram on a remote, returning the source endpoint the relay used.
+    async fn recv_remote(remote: &UdpSocket, expected: &[u8]) -> SocketAddr {
+        let mut buf = [0; 64];
+        let (len, src) = time::timeout(Duration::from_secs(5), remote.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[..len], expected);
+        src
+    }
+
+    async fn mapping(mapping: NatMapping) -> (SocketAddr, SocketAddr, UdpRelayStats) {
+        let (mut client, mut stream) = io::duplex(64 * 1024);
+        let config = config(mapping, NatFiltering::EndpointIndependent);
+        let relay =
+            tokio::spawn(async move { relay_udp_over_tcp(&mut stream, LOCALHOST, config).await });
+
+        let (r1, r1_addr) = remote([127, 0, 0, 1]).await;
+        let (r2, r2_addr) = remote([127, 0, 0, 1]).await;
+
+        send(&mut client, b"to r1", Address::SocketAddress(r1_addr)).await;
+        let src1 = recv_remote(&r1, b"to r1").await;
+
+        // resolved off the relay loop, as a domain name
+        let domain = Address::DomainAddress(b"127.0.0.1".to_vec(), r2_addr.port());
+        send(&mut client, b"to r2", domain.clone()).await;
+        let src2 = recv_remote(&r2, b"to r2").await;
+
+        r2.send_to(b"from r2", src2).await.unwrap();
+        let frame = recv(&mut client).await;
+        assert_eq!(&frame.payload[..], b"from r2");
+
+        // replies on a per-destination socket carry the address the client sent to
+        match mapping {
+            NatMapping::EndpointIndependent => {
+                assert_eq!(frame.header.address, Address::SocketAddress(r2_addr))
+            }
+            NatMapping::AddressAndPortDependent => assert_eq!(frame.header.address, domain),
+        }
+
+        drop(client);
+        (src1, src2, relay.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn endpoint_independent_mapping() {
+        let (src1, src2, stats) = mapping(NatMapping::EndpointIndependent).await;
+        assert_eq!(src1, src2);
+        assert!(matches!(stats.reason, CloseReason::Completed));
+        assert_eq!(stats.client_to_remote_packets, 2);
+        assert_eq!(stats.remote_to_client_packets, 1);
+    }
+
+    #[tokio::test]
+    async fn address_and_port_dependent_mapping() {
+        let (src1, src2, stats) = mapping(NatMapping::AddressAndPortDependent).await;
+        assert_ne!(src1, src2);
+        assert_eq!(stats.client_to_remote_packets, 2);
+        assert_eq!(stats.remote_to_client_packets, 1);
+    }
+
+    /// Sends a datagram to a remote, then datagrams to its source endpoint from another IP, from another port and from the remote itself. Returns the first datagram relayed to the client.
+    async fn filtering(filtering: NatFiltering) -> (UdpFrame, UdpRelayStats) {
+        let (mut client, mut stream) = io::duplex(64 * 1024);
+        let config = config(NatMapping::EndpointIndependent, filtering);
+        let relay =
+            tokio::spawn(async move { relay_udp_over_tcp(&mut stream, LOCALHOST, config).await });
+
+        let (r1, r1_addr) = remote([127, 0, 0, 1]).await;
+        let (other_port, _) = remote([127, 0, 0, 1]).await;
+        let (other_ip, _) = remote([127, 0, 0, 2]).await;
+
+        send(&mut client, b"to r1", Address::SocketAddress(r1_addr)).await;
+        let src = recv_remote(&r1, b"to r1").await;
+
+        other_ip.send_to(b"other ip", src).await.unwrap();
+        other_port.send_to(b"other port", src).await.unwrap();
+        r1.send_to(b"r1", src).await.unwrap();
+
+        let frame = recv(&mut client).await;
+
+        // r1 sent last, so the datagrams before it are handled once it is relayed
+        let mut last = frame.payload.clone();
+        while &last[..] != b"r1" {
+            last = recv(&mut client).await.payload;
+        }
+
+        drop(client);
+        (frame, relay.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn endpoint_independent_filtering() {
+        let (frame, stats) = filtering(NatFiltering::EndpointIndependent).await;
+        assert_eq!(&frame.payload[..], b"other ip");
+        assert_eq!(stats.dropped_packets, 0);
+    }
+
+    #[tokio::test]
+    async fn address_dependent_filtering() {
+        let (frame, stats) = filtering(NatFiltering::AddressDependent).await;
+        assert_eq!(&frame.payload[..], b"other port");
+        assert_eq!(stats.dropped_packets, 1);
+    }
+
+    #[tokio::test]
+    async fn address_and_port_dependent_filtering() {
+        let (frame, stats) = filtering(NatFiltering::AddressAndPortDependent).await;
+        assert_eq!(&frame.payload[..], b"r1");
+        assert_eq!(stats.dropped_packets, 2);
+    }
+
+    fn expiring_config() -> UdpRelayConfig {
+        UdpRelayConfig {
+            sessions: Some(Arc::new(SessionTable::new(SessionConfig {
+                mapping_idle_timeout: Duration::from_millis(100),
+                ..Default::default()
+            }))),
+            ..config(
+                NatMapping::AddressAndPortDependent,
+                NatFiltering::AddressAndPortDependent,
+            )
+        }
+    }
+
+    #[tokio::test]
+    async fn expired_mappings_are_filtered() {
+        let (mut client, mut stream) = io::duplex(64 * 1024);
+        let config = UdpRelayConfig {
+            mapping: NatMapping::EndpointIndependent,
+            ..expiring_config()
+        };
+        let relay =
+            tokio::spawn(async move { relay_udp_over_tcp(&mut stream, LOCALHOST, config).await });
+
+        let (r1, r1_addr) = remote([127, 0, 0, 1]).await;
+
+        send(&mut client, b"to r1", Address::SocketAddress(r1_addr)).await;
+        let src = recv_remote(&r1, b"to r1").await;
+
+        time::sleep(Duration::from_millis(150)).await;
+        r1.send_to(b"late", src).await.unwrap();
+        time::sleep(Duration::from_millis(50)).await;
+
+        send(&mut client, b"again", Address::SocketAddress(r1_addr)).await;
+        assert_eq!(recv_remote(&r1, b"again").await, src);
+        r1.send_to(b"fresh", src).await.unwrap();
+        assert_eq!(&recv(&mut client).await.payload[..], b"fresh");
+
+        drop(client);
+        let stats = relay.await.unwrap();
+        assert_eq!(stats.dropped_packets, 1);
+    }
+
+    #[tokio::test]
+    async fn expired_sockets_are_closed() {
+        let mut relay = UdpRelay::new(expiring_config(), LOCALHOST);
+        let (_r1, r1_addr) = remote([127, 0, 0, 1]).await;
+
+        let dst = Address::SocketAddress(r1_addr);
+        assert!(relay.send_outbound(b"x", r1_addr, dst, ()).await.is_some());
+        assert_eq!(relay.per_destination.len(), 1);
+
+        relay.close_expired();
+        assert_eq!(relay.per_destination.len(), 1);
+
+        time::sleep(Duration::from_millis(150)).await;
+        relay.close_expired();
+        assert!(relay.per_destination.is_empty());
+    }
+
+    #[tokio::test]
+    async fn idle_timeout() {
+        let (mut client, mut stream) = io::duplex(64 * 1024);
+        let config = UdpRelayConfig {
+            idle_timeout: Some(Duration::from_millis(100)),
+            ..config(
+                NatMapping::EndpointIndependent,
+                NatFiltering::EndpointIndependent,
+            )
+        };
+
+        let relay =
+            tokio::spawn(async move { relay_udp_over_tcp(&mut stream, LOCALHOST, config).await });
+
+        // dropped datagrams do not keep the association alive
+        for _ in 0..3 {
+            let dst = Address::DomainAddress(vec![0xff], 53);
+            send(&mut client, b"unresolvable", dst).await;
+            time::sleep(Duration::from_millis(30)).await;
+        }
+
+        let stats = relay.await.unwrap();
+        assert!(matches!(stats.reason, CloseReason::IdleTimeout));
+        assert_eq!(stats.dropped_packets, 3);
+        assert_eq!(stats.client_to_remote_packets, 0);
+    }
+}
@@ -0,0 +1,332 @@
+use super::{Upstream, UpstreamError};
+use crate::connection::BufferedStream;
+use async_trait::async_trait;
+use bytes::{Buf, BytesMut};
+use socks5_proto::{Address, Reply};
+use std::{
+    io::{Error, ErrorKind},
+    net::Ipv6Addr,
+};
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+
+/// An upstream connecting through an HTTP proxy with HTTP/1.1 CONNECT requests
+///
+/// The target is sent to the proxy unresolved as `CONNECT host:port`, optionally with Basic proxy authentication. A 2xx status is a success, and bytes the proxy sent after its response are kept in the returned [`BufferedStream`](https://docs.rs/socks5-server/latest/socks5_server/connection/struct.BufferedStream.html). Other statuses are sent to the client as the closest `Reply`:
+///
+/// | HTTP status                | Reply                             |
+/// |----------------------------|-----------------------------------|
+/// | 401, 403, 407              | `Reply::ConnectionNotAllowed`     |
+/// | 404, 410, 502, 503         | `Reply::HostUnreachable`          |
+/// | 405, 501                   | `Reply::CommandNotSupported`      |
+/// | 504                        | `Reply::TtlExpired`               |
+/// | others                     | `Reply::GeneralFailure`           |
+///
+/// Failures to reach the proxy and malformed responses are sent as `Reply::GeneralFailure`. A target domain name with characters other than the unreserved characters and sub-delimiters of RFC 3986, which could otherwise inject headers into the request, is refused as `Reply::AddressTypeNotSupported` before connecting to the proxy.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_proto::Address;
+/// use socks5_server::upstream::HttpUpstream;
+///
+/// let proxy = Address::DomainAddress(b"proxy.corp.example.com".to_vec(), 3128);
+///
+/// let mut upstream = HttpUpstream::new(proxy);
+/// upstream.set_basic_auth("user", "pass");
+/// ```
+#[derive(Clone, Debug)]
+pub struct HttpUpstream {
+    proxy: Address,
+    authorization: Option<String>,
+}
+
+impl HttpUpstream {
+    /// The maximum length of the response of the proxy, headers included.
+    const MAX_RESPONSE_LEN: usize = 8 * 1024;
+
+    /// Creates a new `HttpUpstream` connecting through the HTTP proxy at `proxy`.
+    #[inline]
+    pub fn new(proxy: Address) -> Self {
+        Self {
+            proxy,
+            authorization: None,
+        }
+    }
+
+    /// Authenticates with the proxy using the Basic scheme, sending a `Proxy-Authorization` header with every request.
+    pub fn set_basic_auth(&mut self, username: impl AsRef<[u8]>, password: impl AsRef<[u8]>) {
+        let mut credentials = username.as_ref().to_vec();
+        credentials.push(b':');
+        credentials.extend_from_slice(password.as_ref());
+
+        self.authorization = Some(format!("Basic {}", base64(&credentials)));
+    }
+
+    /// Returns the address of the proxy.
+    #[inline]
+    pub fn proxy(&self) -> &Address {
+        &self.proxy
+    }
+}
+
+#[async_trait]
+impl Upstream for HttpUpstream {
+    type Stream = BufferedStream<TcpStream>;
+
+    async fn connect(&self, target: &Address) -> Result<(Self::Stream, Address), UpstreamError> {
+        let authority = authority(target)
+            .map_err(|err| UpstreamError::new(Reply::AddressTypeNotSupported, err))?;
+
+        let stream = match &self.proxy {
+            Address::SocketAddress(addr) => TcpStream::connect(addr).await,
+            Address::DomainAddress(host, port) => {
+                let host = String::from_utf8_lossy(host);
+                TcpStream::connect((host.as_ref(), *port)).await
+            }
+        }
+        .map_err(|err| UpstreamError::new(Reply::GeneralFailure, err))?;
+
+        let mut stream = BufferedStream::new(stream);
+
+        let mut req = format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n");
+
+        if let Some(authorization) = &self.authorization {
+            req.push_str("Proxy-Authorization: ");
+            req.push_str(authorization);
+            req.push_str("\r\n");
+        }
+
+        req.push_str("\r\n");
+
+        stream
+            .write_all(req.as_bytes())
+            .await
+            .map_err(|err| UpstreamError::new(Reply::GeneralFailure, err))?;
+
+        let status = stream
+            .read_message(decode_response)
+            .await
+            .map_err(|err| UpstreamError::new(Reply::GeneralFailure, err))?;
+
+        if !(200..300).contains(&status) {
+            return Err(UpstreamError::new(
+                reply_for_status(status),
+                Error::other(format!("HTTP proxy responded with status {status}")),
+            ));
+        }
+
+        Ok((stream, Address::unspecified()))
+    }
+}
+
+/// Returns the target as the authority of the CONNECT request.
+fn authority(target: &Address) -> Result<String, Error> {
+    match target {
+        Address::SocketAddress(addr) => Ok(addr.to_string()),
+        Address::DomainAddress(host, port) => {
+            let host = std::str::from_utf8(host).unwrap_or_default();
+
+            if let Ok(ip) = host.parse::<Ipv6Addr>() {
+                return Ok(format!("[{ip}]:{port}"));
+            }
+
+            if host.is_empty() || !host.bytes().all(is_reg_name_byte) {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "invalid domain name in target",
+                ));
+            }
+
+            Ok(format!("{host}:{port}"))
+        }
+    }
+}
+
+/// Returns whether `byte` is an unreserved character or a sub-delimiter of RFC 3986.
+fn is_reg_name_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=".contains(&byte)
+}
+
+/// Decodes the response of the proxy, returning its status code. The body of a successful response is the tunnel, so the response ends with its headers.
+fn decode_response(buf: &mut BytesMut) -> Result<Option<u16>, Error> {
+    let Some(end) = buf.windows(4).position(|window| window == b"\r\n\r\n") else {
+        if buf.len() > HttpUpstream::MAX_RESPONSE_LEN {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "HTTP proxy response too long",
+            ));
+        }
+
+        return Ok(None);
+    };
+
+    let status = buf[..end]
+        .split(|byte| *byte == b'\n')
+        .next()
+        .and_then(|line| std::str::from_utf8(line).ok())
+        .and_then(|line| {
+            let mut parts = line.trim_end().split(' ');
+            let version = parts.next()?;
+            let status = parts.next()?;
+
+            version
+                .starts_with("HTTP/1.")
+                .then(|| status.parse::<u16>().ok())
+                .flatten()
+        })
+        .ok_or_else(|| Error::new(ErrorKind::InvalidData, "malformed HTTP proxy response"))?;
+
+    buf.advance(end + 4);
+
+    Ok(Some(status))
+}
+
+fn reply_for_status(status: u16) -> Reply {
+    match status {
+        401 | 403 | 407 => Reply::ConnectionNotAllowed,
+        404 | 410 | 502 | 503 => Reply::HostUnreachable,
+        405 | 501 => Reply::CommandNotSupported,
+        504 => Reply::TtlExpired,
+        _ => Reply::GeneralFailure,
+    }
+}
+
+fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(ALPHABET[(n >> (18 - i * 6)) as usize & 0x3f] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    fn domain(host: &[u8]) -> Address {
+        Address::DomainAddress(host.to_vec(), 443)
+    }
+
+    #[test]
+    fn authority_rejects_injection() {
+        assert_eq!(
+            authority(&domain(b"example.com")).unwrap(),
+            "example.com:443"
+        );
+        assert_eq!(authority(&domain(b"10.0.0.1")).unwrap(), "10.0.0.1:443");
+        assert_eq!(authority(&domain(b"::1")).unwrap(), "[::1]:443");
+        assert_eq!(
+            authority(&Address::SocketAddress(SocketAddr::from((
+                [0, 0, 0, 0, 0, 0, 0, 1],
+                80
+            ))))
+            .unwrap(),
+            "[::1]:80"
+        );
+
+        for host in [
+            &b"a\r\nX: y"[..],
+            b"",
+            b"a b",
+            b"a:b",
+            b"user@host",
+            b"host/path",
+            b"host?q",
+            b"host#f",
+            b"a\x00b",
+            b"\xff",
+        ] {
+            let err = authority(&domain(host)).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[tokio::test]
+    async fn connect_rejects_injection_before_connecting() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let upstream = HttpUpstream::new(Address::SocketAddress(listener.local_addr().unwrap()));
+
+        let err = upstream.connect(&domain(b"a\r\nX: y")).await.unwrap_err();
+        assert_eq!(err.reply, Reply::AddressTypeNotSupported);
+        assert_eq!(listener.accept().unwrap_err().kind(), ErrorKind::WouldBlock);
+    }
+
+    #[test]
+    fn decode_response_statuses() {
+        let mut buf = BytesMut::from(&b"HTTP/1.1 200 Connection established\r\n\r\ntunnel"[..]);
+        assert_eq!(decode_response(&mut buf).unwrap(), Some(200));
+        assert_eq!(&buf[..], b"tunnel");
+
+        let mut buf = BytesMut::from(
+            &b"HTTP/1.0 407 Proxy Authentication Required\r\nProxy-Authenticate: Basic\r\n\r\n"[..],
+        );
+        assert_eq!(decode_response(&mut buf).unwrap(), Some(407));
+        assert!(buf.is_empty());
+
+        for response in [
+            &b"SSH-2.0-OpenSSH\r\n\r\n"[..],
+            b"HTTP/1.1 abc\r\n\r\n",
+            b"\r\n\r\n",
+        ] {
+            let err = decode_response(&mut BytesMut::from(response)).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn decode_response_partial() {
+        let full = b"HTTP/1.1 200 OK\r\nVia: proxy\r\n\r\n";
+
+        for len in 0..full.len() {
+            let mut buf = BytesMut::from(&full[..len]);
+            assert_eq!(decode_response(&mut buf).unwrap(), None);
+            assert_eq!(buf.len(), len);
+        }
+
+        let mut buf = BytesMut::from(&b"HTTP/1.1 200 OK\r\n"[..]);
+        buf.resize(HttpUpstream::MAX_RESPONSE_LEN, b'a');
+        assert_eq!(decode_response(&mut buf).unwrap(), None);
+        buf.extend_from_slice(b"a");
+        assert_eq!(
+            decode_response(&mut buf).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn base64_vectors() {
+        // RFC 4648, section 10
+        for (input, output) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foob", "Zm9vYg=="),
+            ("fooba", "Zm9vYmE="),
+            ("foobar", "Zm9vYmFy"),
+        ] {
+            assert_eq!(base64(input.as_bytes()), output);
+        }
+
+        assert_eq!(base64(&[0xfb, 0xff, 0xbf]), "+/+/");
+    }
+}
@@ -0,0 +1,233 @@
+//! Opening the outbound leg of a CONNECT request through an upstream proxy.
+//!
+//! An [`Upstream`](https://docs.rs/socks5-server/latest/socks5_server/upstream/trait.Upstream.html) connects to a target on behalf of the server, e.g. through a chain of SOCKS5 proxies with [`Socks5Upstream`](https://docs.rs/socks5-server/latest/socks5_server/upstream/struct.Socks5Upstream.html), or through an HTTP proxy with [`HttpUpstream`](https://docs.rs/socks5-server/latest/socks5_server/upstream/struct.HttpUpstream.html). [`connect()`](https://docs.rs/socks5-server/latest/socks5_server/upstream/fn.connect.html), also available as [`Connect::connect_upstream()`](https://docs.rs/socks5-server/latest/socks5_server/connection/connect/struct.Connect.html#method.connect_upstream), opens the connection and replies to the client, translating a failure of the upstream into the closest `Reply`.
+
+mod http;
+mod socks5;
+
+pub use self::{http::HttpUpstream, socks5::Socks5Upstream};
+
+use crate::connection::connect::{Connect, NeedReply, Ready};
+use async_trait::async_trait;
+use socks5_proto::{Address, Reply};
+use std::io::{Error, ErrorKind};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+
+/// This trait is for opening outbound connections through an upstream proxy.
+///
+/// `connect()` returns the stream to the target alongside the address to reply to the client with, usually the address the upstream bound to connect to the target.
+///
+/// # Example
+/// ```rust
+/// use async_trait::async_trait;
+/// use socks5_proto::{Address, Reply};
+/// use socks5_server::upstream::{Upstream, UpstreamError};
+/// use tokio::net::TcpStream;
+///
+/// pub struct Direct;
+///
+/// #[async_trait]
+/// impl Upstream for Direct {
+///     type Stream = TcpStream;
+///
+///     async fn connect(&self, target: &Address) -> Result<(Self::Stream, Address), UpstreamError> {
+///         let stream = match target {
+///             Address::SocketAddress(addr) => TcpStream::connect(addr).await,
+///             Address::DomainAddress(host, port) => {
+///                 let host = String::from_utf8_lossy(host);
+///                 TcpStream::connect((host.as_ref(), *port)).await
+///             }
+///         }
+///         .map_err(UpstreamError::from)?;
+///
+///         let addr = stream.local_addr().map_err(UpstreamError::from)?;
+///         Ok((stream, Address::SocketAddress(addr)))
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait Upstream {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send;
+
+    async fn connect(&self, target: &Address) -> Result<(Self::Stream, Address), UpstreamError>;
+}
+
+/// A failure of an upstream, alongside the reply to send to the client
+#[derive(Debug, Error)]
+#[error("Upstream connection failed: {error}")]
+pub struct UpstreamError {
+    pub reply: Reply,
+    pub error: Error,
+}
+
+impl UpstreamError {
+    pub const fn new(reply: Reply, error: Error) -> Self {
+        Self { reply, error }
+    }
+}
+
+impl From<Error> for UpstreamError {
+    /// Guesses the reply from the kind of the error, e.g. `Reply::ConnectionRefused` for `ErrorKind::ConnectionRefused`.
+    fn from(error: Error) -> Self {
+        let reply = match error.kind() {
+            ErrorKind::ConnectionRefused => Reply::ConnectionRefused,
+            ErrorKind::PermissionDenied => Reply::ConnectionNotAllowed,
+            ErrorKind::NotFound | ErrorKind::TimedOut => Reply::HostUnreachable,
+            ErrorKind::Unsupported => Reply::CommandNotSupported,
+            _ => Reply::GeneralFailure,
+        };
+
+        Self::new(reply, error)
+    }
+}
+
+impl From<UpstreamError> for Error {
+    fn from(err: UpstreamError) -> Self {
+        err.error
+    }
+}
+
+/// Connects to `target` through `upstream`, and replies to the client.
+///
+/// On success, the client gets `Reply::Succeeded` with the address returned by the upstream, and the stream to the target is returned alongside the `Connect<Ready>`. On failure, the client gets the reply of the [`UpstreamError`](https://docs.rs/socks5-server/latest/socks5_server/upstream/struct.UpstreamError.html), its write half is shut down, and the error alongside the original stream is returned. The error of the upstream is returned even if the reply could not be sent.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_client::Auth;
+/// use socks5_proto::Address;
+/// use socks5_server::{
+///     connection::connect::{Connect, NeedReply},
+///     upstream::{self, Socks5Upstream},
+/// };
+/// use tokio::io;
+///
+/// async fn connect(conn: Connect<NeedReply>, addr: Address, upstream: &Socks5Upstream) {
+///     if let Ok((mut conn, mut remote)) = upstream::connect(conn, addr, upstream).await {
+///         let _ = io::copy_bidirectional(&mut conn, &mut remote).await;
+///     }
+/// }
+/// ```
+pub async fn connect<T, U>(
+    conn: Connect<NeedReply, T>,
+    target: Address,
+    upstream: &U,
+) -> Result<(Connect<Ready, T>, U::Stream), (Error, T)>
+where
+    T: AsyncWrite + Unpin,
+    U: Upstream + ?Sized,
+{
+    match upstream.connect(&target).await {
+        Ok((remote, addr)) => {
+            let conn = conn.reply(Reply::Succeeded, addr).await?;
+            Ok((conn, remote))
+        }
+        Err(err) => match conn.reply(err.reply, Address::unspecified()).await {
+            Ok(mut stream) => {
+                let _ = stream.shutdown().await;
+                Err((err.into(), stream.into_inner()))
+            }
+            // the failure of the upstream is more useful than the one of the client
+            Err((_, stream)) => Err((err.into(), stream)),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::{Negotiator, NoAuth},
+        connection::{Command, IncomingConnection},
+        AuthAdaptor,
+    };
+    use std::sync::Arc;
+    use tokio::io::{self, AsyncReadExt, DuplexStream};
+
+    /// An upstream returning a stream with `b"remote"` in it, or failing with `ConnectionRefused`.
+    struct Fixed(bool);
+
+    #[async_trait]
+    impl Upstream for Fixed {
+        type Stream = DuplexStream;
+
+        async fn connect(
+            &self,
+            target: &Address,
+        ) -> Result<(Self::Stream, Address), UpstreamError> {
+            assert_eq!(target, &Address::SocketAddress(([1, 2, 3, 4], 80).into()));
+
+            if !self.0 {
+                return Err(Error::from(ErrorKind::ConnectionRefused).into());
+            }
+
+            let (mut remote, stream) = io::duplex(64);
+            remote.write_all(b"remote").await.unwrap();
+            Ok((stream, Address::SocketAddress(([10, 0, 0, 1], 1080).into())))
+        }
+    }
+
+    /// Returns a CONNECT request to `1.2.3.4:80` waiting for a reply, alongside the client.
+    async fn request() -> (Connect<NeedReply, DuplexStream>, DuplexStream) {
+        let (mut client, server) = io::duplex(1024);
+        client
+            .write_all(&[0x05, 0x01, 0x00, 0x05, 0x01, 0x00, 0x01, 1, 2, 3, 4, 0, 80])
+            .await
+            .unwrap();
+
+        let auth: AuthAdaptor<(), DuplexStream> = Arc::new(NoAuth);
+        let conn = IncomingConnection::new(server, Arc::new(Negotiator::from(auth)));
+        let (conn, _, ()) = conn.authenticate().await.unwrap();
+
+        let Command::Connect(conn, _) = conn.wait_request().await.unwrap() else {
+            panic!("expected a CONNECT request");
+        };
+
+        (conn, client)
+    }
+
+    #[tokio::test]
+    async fn succeeded() {
+        let (conn, mut client) = request().await;
+        let target = Address::SocketAddress(([1, 2, 3, 4], 80).into());
+
+        let (_conn, mut remote) = connect(conn, target, &Fixed(true)).await.unwrap();
+
+        let mut buf = [0; 6];
+        remote.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"remote");
+
+        let mut resp = [0; 12];
+        client.read_exact(&mut resp).await.unwrap();
+        assert_eq!(resp, [5, 0, 5, 0, 0, 1, 10, 0, 0, 1, 0x04, 0x38]);
+    }
+
+    #[tokio::test]
+    async fn failed() {
+        let (conn, mut client) = request().await;
+        let target = Address::SocketAddress(([1, 2, 3, 4], 80).into());
+
+        let Err((err, _)) = connect(conn, target, &Fixed(false)).await else {
+            panic!("expected the upstream to fail");
+        };
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+
+        // the reply, then the write half is shut down
+        let mut resp = Vec::new();
+        client.read_to_end(&mut resp).await.unwrap();
+        assert_eq!(resp, [5, 0, 5, 5, 0, 1, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn failed_reply() {
+        let (conn, client) = request().await;
+        let target = Address::SocketAddress(([1, 2, 3, 4], 80).into());
+        drop(client);
+
+        let Err((err, _)) = connect(conn, target, &Fixed(false)).await else {
+            panic!("expected the upstream to fail");
+        };
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+    }
+}
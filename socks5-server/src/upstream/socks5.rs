@@ -0,0 +1,271 @@
+use super::{Upstream, UpstreamError};
+use async_trait::async_trait;
+use socks5_client::{Auth, Error as ClientError, Socks5Stream};
+use socks5_proto::{Address, Reply};
+use std::io::Error;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+};
+
+/// An upstream connecting through a chain of SOCKS5 proxies
+///
+/// The server connects to the first proxy, which connects to the second one, and so on, and the last proxy connects to the target. Domain names are passed to the next hop unresolved, so each name is resolved by the proxy connecting to it.
+///
+/// A failure reply of the last proxy, which connects to the target, is sent to the client as is. Other failures, e.g. a proxy being unreachable, a failed authentication with a proxy, or a proxy failing to connect to the next one, are sent as `Reply::GeneralFailure`, as they are not caused by the target.
+///
+/// # Example
+///
+/// ```rust
+/// use socks5_client::Auth;
+/// use socks5_proto::Address;
+/// use socks5_server::upstream::Socks5Upstream;
+///
+/// let first = Address::SocketAddress("10.0.0.1:1080".parse().unwrap());
+/// let second = Address::DomainAddress(b"exit.example.com".to_vec(), 1080);
+///
+/// let mut upstream = Socks5Upstream::new(first, Auth::None);
+/// upstream.push(second, Auth::password("user", "pass"));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Socks5Upstream {
+    hops: Vec<(Address, Auth)>,
+}
+
+impl Socks5Upstream {
+    /// Creates a new `Socks5Upstream` connecting through the proxy at `proxy`.
+    #[inline]
+    pub fn new(proxy: Address, auth: Auth) -> Self {
+        Self {
+            hops: vec![(proxy, auth)],
+        }
+    }
+
+    /// Appends a proxy to the chain, reached through the proxies before it.
+    #[inline]
+    pub fn push(&mut self, proxy: Address, auth: Auth) {
+        self.hops.push((proxy, auth));
+    }
+
+    /// Returns the proxies of the chain, in order.
+    #[inline]
+    pub fn hops(&self) -> &[(Address, Auth)] {
+        &self.hops
+    }
+}
+
+#[async_trait]
+impl Upstream for Socks5Upstream {
+    type Stream = TcpStream;
+
+    async fn connect(&self, target: &Address) -> Result<(Self::Stream, Address), UpstreamError> {
+        let (first, _) = &self.hops[0];
+
+        let stream = match first {
+            Address::SocketAddress(addr) => TcpStream::connect(addr).await,
+            Address::DomainAddress(host, port) => {
+                let host = String::from_utf8_lossy(host);
+                TcpStream::connect((host.as_ref(), *port)).await
+            }
+        }
+        .map_err(|err| UpstreamError::new(Reply::GeneralFailure, err))?;
+
+        self.connect_through(stream, target).await
+    }
+}
+
+impl Socks5Upstream {
+    /// Connects to `target` through the chain, over the connection `stream` to the first proxy.
+    async fn connect_through<S>(
+        &self,
+        mut stream: S,
+        target: &Address,
+    ) -> Result<(S, Address), UpstreamError>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let next_hops = self.hops[1..].iter().map(|(addr, _)| addr).chain([target]);
+        let mut bound_addr = Address::unspecified();
+
+        for (idx, ((_, auth), next)) in self.hops.iter().zip(next_hops).enumerate() {
+            let conn = Socks5Stream::connect_with(stream, next.clone(), auth)
+                .await
+                .map_err(|err| translate(err, idx == self.hops.len() - 1))?;
+
+            bound_addr = conn.bound_addr().clone();
+            stream = conn.into_inner();
+        }
+
+        Ok((stream, bound_addr))
+    }
+}
+
+/// Translates a failure of a proxy in the chain into the reply to the client. Only the reply of the last proxy is about the target.
+fn translate(err: ClientError, is_last: bool) -> UpstreamError {
+    match err {
+        ClientError::Reply(reply) if is_last => UpstreamError::new(reply.into(), reply.into()),
+        err => UpstreamError::new(Reply::GeneralFailure, Error::from(err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use socks5_client::ReplyError;
+    use std::io::ErrorKind;
+    use tokio::{
+        io::{self, AsyncReadExt, AsyncWriteExt, DuplexStream},
+        task::JoinHandle,
+    };
+
+    /// A first proxy sending `script` whatever the chain sends. Returns what the chain sent once it closes the connection.
+    fn proxy(script: &[u8]) -> (DuplexStream, JoinHandle<Vec<u8>>) {
+        let (client, mut server) = io::duplex(4096);
+        let script = script.to_vec();
+
+        let handle = tokio::spawn(async move {
+            server.write_all(&script).await.unwrap();
+
+            let mut buf = Vec::new();
+            server.read_to_end(&mut buf).await.unwrap();
+            buf
+        });
+
+        (client, handle)
+    }
+
+    /// A reply with `code` and the address `10.0.0.<host>:1080`.
+    fn reply(code: u8, host: u8) -> [u8; 10] {
+        [0x05, code, 0x00, 0x01, 10, 0, 0, host, 0x04, 0x38]
+    }
+
+    fn chain() -> Socks5Upstream {
+        let mut upstream = Socks5Upstream::new(
+            Address::SocketAddress(([10, 0, 0, 1], 1080).into()),
+            Auth::None,
+        );
+        upstream.push(
+            Address::DomainAddress(b"exit.example.com".to_vec(), 1080),
+            Auth::password("user", "pass"),
+        );
+        upstream
+    }
+
+    fn target() -> Address {
+        Address::DomainAddress(b"example.com".to_vec(), 443)
+    }
+
+    #[tokio::test]
+    async fn two_hops() {
+        let script = [
+            [0x05, 0x00].as_slice(),
+            &reply(0x00, 1),
+            &[0x05, 0x02, 0x01, 0x00],
+            &reply(0x00, 2),
+        ];
+        let (stream, proxy) = proxy(&script.concat());
+
+        let (stream, bound_addr) = chain().connect_through(stream, &target()).await.unwrap();
+        assert_eq!(
+            bound_addr,
+            Address::SocketAddress(([10, 0, 0, 2], 1080).into())
+        );
+
+        // the address of the second hop and the target are sent unresolved, each over the tunnel before it
+        drop(stream);
+        let expected = [
+            [0x05, 0x01, 0x00].as_slice(),
+            &[0x05, 0x01, 0x00, 0x03, 16],
+            b"exit.example.com",
+            &[0x04, 0x38],
+            &[0x05, 0x02, 0x00, 0x02],
+            &[0x01, 0x04],
+            b"user",
+            &[0x04],
+            b"pass",
+            &[0x05, 0x01, 0x00, 0x03, 11],
+            b"example.com",
+            &[0x01, 0xbb],
+        ];
+        assert_eq!(proxy.await.unwrap(), expected.concat());
+    }
+
+    #[tokio::test]
+    async fn last_hop_failure() {
+        let script = [
+            [0x05, 0x00].as_slice(),
+            &reply(0x00, 1),
+            &[0x05, 0x02, 0x01, 0x00],
+            &reply(0x04, 2),
+        ];
+        let (stream, _proxy) = proxy(&script.concat());
+
+        let err = chain()
+            .connect_through(stream, &target())
+            .await
+            .unwrap_err();
+        assert_eq!(err.reply, Reply::HostUnreachable);
+    }
+
+    #[tokio::test]
+    async fn intermediate_hop_failure() {
+        let script = [[0x05, 0x00].as_slice(), &reply(0x05, 1)];
+        let (stream, _proxy) = proxy(&script.concat());
+
+        let err = chain()
+            .connect_through(stream, &target())
+            .await
+            .unwrap_err();
+        assert_eq!(err.reply, Reply::GeneralFailure);
+        assert_eq!(err.error.kind(), ErrorKind::ConnectionRefused);
+    }
+
+    #[tokio::test]
+    async fn authentication_failure() {
+        let script = [
+            [0x05, 0x00].as_slice(),
+            &reply(0x00, 1),
+            &[0x05, 0x02, 0x01, 0xff],
+        ];
+        let (stream, _proxy) = proxy(&script.concat());
+
+        let err = chain()
+            .connect_through(stream, &target())
+            .await
+            .unwrap_err();
+        assert_eq!(err.reply, Reply::GeneralFailure);
+    }
+
+    #[test]
+    fn translate_replies() {
+        let replies = [
+            ReplyError::GeneralFailure,
+            ReplyError::ConnectionNotAllowed,
+            ReplyError::NetworkUnreachable,
+            ReplyError::HostUnreachable,
+            ReplyError::ConnectionRefused,
+            ReplyError::TtlExpired,
+            ReplyError::CommandNotSupported,
+            ReplyError::AddressTypeNotSupported,
+        ];
+
+        for reply in replies {
+            let err = translate(ClientError::Reply(reply), true);
+            assert_eq!(err.reply, Reply::from(reply));
+            assert_eq!(err.error.kind(), Error::from(reply).kind());
+
+            let err = translate(ClientError::Reply(reply), false);
+            assert_eq!(err.reply, Reply::GeneralFailure);
+            assert_eq!(err.error.kind(), Error::from(reply).kind());
+        }
+
+        for is_last in [true, false] {
+            let err = translate(ClientError::AuthenticationFailed, is_last);
+            assert_eq!(err.reply, Reply::GeneralFailure);
+
+            let err = translate(Error::from(ErrorKind::ConnectionReset).into(), is_last);
+            assert_eq!(err.reply, Reply::GeneralFailure);
+            assert_eq!(err.error.kind(), ErrorKind::ConnectionReset);
+        }
+    }
+}